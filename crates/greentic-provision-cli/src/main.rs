@@ -1,22 +1,41 @@
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io::{Cursor, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
 
 use clap::{Parser, Subcommand};
 use greentic_provision_core::discovery::PackManifest;
 use greentic_provision_core::{
-    DefaultProvisionPackDiscovery, ExecutionLimits, NoopExecutor, ProvisionEngine,
-    ProvisionExecutor, ProvisionInputs, ProvisionMode, ProvisionPackDiscovery, ProvisionStep,
-    TenantContext, WasmtimeExecutor,
+    AllowlistTransport, ApplyError, ApplyMode, AuthorizationCallback, Capability,
+    DefaultProvisionPackDiscovery, EncryptedFileSecretsStore, ExecutionLimits, FileConfigStore,
+    FileInstallStore, HostBindings, HostCall, HostRecorder, HostRecording, HostReplay,
+    HttpFixtures, HttpTransport, InMemoryConfigStore, InMemorySecretsStore, JsonLinesObserver,
+    LeakScanner, NetworkTransport, NoopExecutor, NoopOAuthHandler, OAuthHandler,
+    OAuthProviderConfig, PkceOAuthHandler, ProgressObserver, ProvisionApplier, ProvisionEngine,
+    ProvisionExecutor, ProvisionInputs, ProvisionMetrics, ProvisionMode, ProvisionPackDiscovery,
+    ProvisionStep, REDACTED, ReplayTransport, SecretRef, SecretString, SecretsKey, SecretsStore,
+    StepLayout, StepResult, TenantContext, WasiOptions, WasmtimeExecutor, expose_secrets,
 };
+use greentic_types::validate::{Diagnostic, Severity};
 use serde_json::Value;
+use sha2::{Digest, Sha256};
 use tempfile::TempDir;
+use url::Url;
 use zip::ZipArchive;
 
+#[cfg(feature = "otel")]
+mod otel;
+mod server;
+
 #[derive(Debug, Parser)]
 #[command(name = "greentic-provision")]
 #[command(about = "Provisioning engine CLI for Greentic packs", long_about = None)]
 struct Cli {
+    /// Export tracing spans to an OTLP/HTTP collector, e.g. `http://localhost:4318`.
+    #[cfg(feature = "otel")]
+    #[arg(long, global = true)]
+    otlp_endpoint: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -31,6 +50,21 @@ enum Commands {
         #[command(subcommand)]
         command: DryRunCommands,
     },
+    /// Run a pack and write its plan to the file-backed stores.
+    Apply {
+        #[command(subcommand)]
+        command: ApplyCommands,
+    },
+    /// Manage the encrypted secrets store.
+    Secrets {
+        #[command(subcommand)]
+        command: SecretsCommands,
+    },
+    /// Complete OAuth authorizations left pending by `apply setup`.
+    Oauth {
+        #[command(subcommand)]
+        command: OAuthCommands,
+    },
     Conformance {
         #[arg(long)]
         packs: PathBuf,
@@ -40,6 +74,35 @@ enum Commands {
         provider: Option<String>,
         #[arg(long)]
         live: bool,
+        /// Print and write secret answers unredacted. For local debugging only.
+        #[arg(long)]
+        unsafe_show_secrets: bool,
+    },
+    /// Re-run a pack against the host calls recorded in a conformance failure artifact.
+    Replay {
+        #[arg(long)]
+        artifact: PathBuf,
+        #[arg(long)]
+        json: bool,
+        /// Print and write secret answers unredacted. For local debugging only.
+        #[arg(long)]
+        unsafe_show_secrets: bool,
+    },
+    /// Run as a long-lived HTTP service.
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Expose Prometheus metrics at `/metrics`.
+        #[arg(long)]
+        metrics: bool,
+        /// Install records file shared with other `greentic-provision` runs.
+        #[arg(long)]
+        installs: Option<PathBuf>,
+        /// Directory holding one JSON document per config namespace.
+        #[arg(long)]
+        config_dir: Option<PathBuf>,
+        #[command(flatten)]
+        secrets: SecretsArgs,
     },
 }
 
@@ -68,20 +131,209 @@ enum DryRunCommands {
         public_base_url: Option<String>,
         #[arg(long)]
         answers: Option<PathBuf>,
+        /// Canned HTTP responses served to the pack's `http` capability.
+        #[arg(long)]
+        http_fixtures: Option<PathBuf>,
+        /// Print step progress to stderr.
+        #[arg(long)]
+        progress: bool,
+        /// Write run events as JSON Lines to this file.
+        #[arg(long)]
+        events: Option<PathBuf>,
         #[arg(long)]
         json: bool,
+        /// Print and write secret answers unredacted. For local debugging only.
+        #[arg(long)]
+        unsafe_show_secrets: bool,
     },
 }
 
-#[derive(Debug, Clone, clap::ValueEnum)]
+#[derive(Debug, Subcommand)]
+enum ApplyCommands {
+    Setup {
+        #[arg(long)]
+        pack: PathBuf,
+        #[arg(long, default_value = "wasm")]
+        executor: ExecutorKind,
+        #[arg(long)]
+        provider_id: String,
+        #[arg(long)]
+        install_id: String,
+        #[command(flatten)]
+        tenant: TenantArgs,
+        #[arg(long)]
+        public_base_url: Option<String>,
+        #[arg(long)]
+        answers: Option<PathBuf>,
+        /// Canned HTTP responses served instead of the network to the pack's `http` capability
+        /// and the OAuth token endpoints.
+        #[arg(long)]
+        http_fixtures: Option<PathBuf>,
+        /// Directory holding one JSON document per config namespace.
+        #[arg(long)]
+        config_dir: Option<PathBuf>,
+        /// Install records file shared with other `greentic-provision` runs.
+        #[arg(long)]
+        installs: Option<PathBuf>,
+        #[command(flatten)]
+        secrets: SecretsArgs,
+        /// OAuth providers to start authorizations with. Without it, OAuth ops are skipped.
+        #[arg(long)]
+        oauth_providers: Option<PathBuf>,
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// Where `apply` and `serve` keep secrets. Without a key file or passphrase they are not persisted.
+#[derive(Debug, clap::Args)]
+struct SecretsArgs {
+    /// Directory holding one encrypted JSON document per secrets namespace.
+    #[arg(long)]
+    secrets_dir: Option<PathBuf>,
+    /// Key file written by `greentic-provision secrets keygen`.
+    #[arg(long, conflicts_with = "secrets_passphrase_env")]
+    secrets_key_file: Option<PathBuf>,
+    /// Environment variable holding a passphrase to derive the secrets key from.
+    #[arg(long)]
+    secrets_passphrase_env: Option<String>,
+}
+
+impl SecretsArgs {
+    /// The encrypted secrets store, or `None` without a key file or passphrase.
+    fn open(&self) -> Result<Option<EncryptedFileSecretsStore>, CliError> {
+        let secrets_dir = self
+            .secrets_dir
+            .clone()
+            .unwrap_or_else(EncryptedFileSecretsStore::default_dir);
+        let key = secrets_key(
+            &secrets_dir,
+            self.secrets_key_file.as_deref(),
+            self.secrets_passphrase_env.as_deref(),
+        )?;
+        Ok(key.map(|key| EncryptedFileSecretsStore::new(secrets_dir, key)))
+    }
+}
+
+/// Tenant an install belongs to. `oauth finish` must name the same tenant as `apply setup`.
+#[derive(Debug, clap::Args)]
+struct TenantArgs {
+    #[arg(long)]
+    environment: Option<String>,
+    #[arg(long)]
+    tenant: Option<String>,
+    #[arg(long)]
+    team: Option<String>,
+    #[arg(long)]
+    user: Option<String>,
+}
+
+impl TenantArgs {
+    fn context(&self) -> TenantContext {
+        TenantContext {
+            environment: self.environment.clone(),
+            tenant: self.tenant.clone(),
+            team: self.team.clone(),
+            user: self.user.clone(),
+        }
+    }
+
+    /// The flags selecting this tenant, for commands printed as hints.
+    fn flags(&self) -> String {
+        [
+            ("--environment", &self.environment),
+            ("--tenant", &self.tenant),
+            ("--team", &self.team),
+            ("--user", &self.user),
+        ]
+        .into_iter()
+        .filter_map(|(flag, value)| Some(format!(" {flag} {}", value.as_ref()?)))
+        .collect()
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum OAuthCommands {
+    /// Exchange the code from the provider's redirect for tokens and store them with the install.
+    Finish {
+        #[arg(long)]
+        provider_id: String,
+        #[arg(long)]
+        install_id: String,
+        #[command(flatten)]
+        tenant: TenantArgs,
+        /// The URL the provider redirected back to, carrying `code` and `state`.
+        #[arg(long, required_unless_present = "state", conflicts_with = "state")]
+        redirect: Option<String>,
+        #[arg(long, requires = "code")]
+        state: Option<String>,
+        #[arg(long, requires = "state")]
+        code: Option<String>,
+        /// The providers file `apply setup` started the authorization with.
+        #[arg(long)]
+        oauth_providers: PathBuf,
+        /// Canned HTTP responses served for the token endpoint instead of contacting it.
+        #[arg(long)]
+        http_fixtures: Option<PathBuf>,
+        /// Install records file shared with other `greentic-provision` runs.
+        #[arg(long)]
+        installs: Option<PathBuf>,
+        #[command(flatten)]
+        secrets: SecretsArgs,
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+/// One provider in an `--oauth-providers` file, keyed by the name packs use in `OAuthOp`s.
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OAuthProviderEntry {
+    /// A `greentic-oauth-client` preset, e.g. `google` or `microsoft`.
+    preset: Option<String>,
+    client_id: String,
+    /// Environment variable holding the client secret of a confidential client.
+    client_secret_env: Option<String>,
+    authorize_url: Option<String>,
+    token_url: Option<String>,
+    redirect_url: Option<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum SecretsCommands {
+    /// Write a new random key file for the encrypted secrets store.
+    Keygen {
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Re-encrypt every secrets namespace under a new key.
+    Rotate {
+        #[arg(long)]
+        secrets_dir: Option<PathBuf>,
+        #[arg(long, conflicts_with = "passphrase_env")]
+        key_file: Option<PathBuf>,
+        #[arg(long)]
+        passphrase_env: Option<String>,
+        #[arg(long, conflicts_with = "new_passphrase_env")]
+        new_key_file: Option<PathBuf>,
+        #[arg(long)]
+        new_passphrase_env: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 enum ExecutorKind {
     Noop,
+    #[default]
     Wasm,
 }
 
 enum CliExecutor {
     Noop(NoopExecutor),
-    Wasm(WasmtimeExecutor),
+    Wasm(Box<WasmtimeExecutor>),
 }
 
 impl ProvisionExecutor for CliExecutor {
@@ -95,10 +347,23 @@ impl ProvisionExecutor for CliExecutor {
             CliExecutor::Wasm(exec) => exec.run_step(step, ctx),
         }
     }
+
+    fn max_concurrency(&self) -> Option<usize> {
+        match self {
+            CliExecutor::Noop(exec) => exec.max_concurrency(),
+            CliExecutor::Wasm(exec) => exec.max_concurrency(),
+        }
+    }
 }
 
 fn main() -> Result<(), CliError> {
     let cli = Cli::parse();
+    #[cfg(feature = "otel")]
+    let _otlp = cli
+        .otlp_endpoint
+        .as_deref()
+        .map(otel::OtlpExport::install)
+        .transpose()?;
 
     match cli.command {
         Commands::Pack { command } => match command {
@@ -137,10 +402,14 @@ fn main() -> Result<(), CliError> {
                 install_id,
                 public_base_url,
                 answers,
+                http_fixtures,
+                progress,
+                events,
                 json,
+                unsafe_show_secrets,
             } => {
                 let pack_ctx = resolve_pack_path(&pack)?;
-                let _manifest = load_manifest(&pack_ctx.root)?;
+                let manifest = load_manifest(&pack_ctx.root)?;
                 let answers_json = answers
                     .map(|path| load_json_value(&path))
                     .transpose()?
@@ -154,20 +423,27 @@ fn main() -> Result<(), CliError> {
                     answers: answers_json,
                     existing_state: None,
                 };
+                let redaction =
+                    OutputRedaction::new(&manifest, &inputs.answers, unsafe_show_secrets);
 
-                let executor = match executor {
-                    ExecutorKind::Noop => CliExecutor::Noop(NoopExecutor),
-                    ExecutorKind::Wasm => {
-                        let executor =
-                            WasmtimeExecutor::new(pack_ctx.root, ExecutionLimits::default())?;
-                        CliExecutor::Wasm(executor)
-                    }
-                };
-                let engine = ProvisionEngine::new(executor);
+                let executor = dry_run_executor(
+                    executor,
+                    &pack_ctx,
+                    &manifest,
+                    http_fixtures.as_ref(),
+                    HostBindings::new(),
+                )?;
+                let mut engine = ProvisionEngine::new(executor);
+                if progress {
+                    engine = engine.with_observer(Arc::new(ProgressObserver::stderr()));
+                }
+                if let Some(path) = events {
+                    engine = engine.with_observer(Arc::new(JsonLinesObserver::create(&path)?));
+                }
                 let result = engine.run(ProvisionMode::DryRun, inputs);
 
                 if json {
-                    println!("{}", serde_json::to_string_pretty(&result)?);
+                    println!("{}", redaction.to_json(&result)?);
                 } else {
                     println!(
                         "Dry-run completed with {} diagnostics.",
@@ -177,17 +453,259 @@ fn main() -> Result<(), CliError> {
                 }
             }
         },
+        Commands::Apply { command } => match command {
+            ApplyCommands::Setup {
+                pack,
+                executor,
+                provider_id,
+                install_id,
+                tenant,
+                public_base_url,
+                answers,
+                http_fixtures,
+                config_dir,
+                installs,
+                secrets,
+                oauth_providers,
+                json,
+            } => {
+                let pack_ctx = resolve_pack_path(&pack)?;
+                let manifest = load_manifest(&pack_ctx.root)?;
+                let answers_json = answers
+                    .map(|path| load_json_value(&path))
+                    .transpose()?
+                    .unwrap_or(Value::Object(serde_json::Map::new()));
+
+                let finish_command = oauth_providers.as_ref().map(|path| {
+                    format!(
+                        "greentic-provision oauth finish --provider-id {provider_id} --install-id {install_id}{} --oauth-providers {}",
+                        tenant.flags(),
+                        path.display()
+                    )
+                });
+                let inputs = ProvisionInputs {
+                    tenant: tenant.context(),
+                    provider_id,
+                    install_id,
+                    public_base_url,
+                    answers: answers_json,
+                    existing_state: None,
+                };
+
+                let config_store =
+                    FileConfigStore::new(config_dir.unwrap_or_else(FileConfigStore::default_dir));
+                let encrypted = secrets.open()?;
+                let executor = apply_executor(
+                    executor,
+                    &pack_ctx,
+                    &manifest,
+                    http_fixtures.as_ref(),
+                    store_host(&config_store, encrypted.as_ref()),
+                )?;
+                let result =
+                    ProvisionEngine::new(executor).run(ProvisionMode::Install, inputs.clone());
+                let errors: Vec<_> = result
+                    .diagnostics
+                    .iter()
+                    .filter(|diagnostic| diagnostic.severity == Severity::Error)
+                    .collect();
+                if !errors.is_empty() {
+                    for diagnostic in &errors {
+                        eprintln!("error: {}: {}", diagnostic.code, diagnostic.message);
+                    }
+                    return Err(CliError::SetupFailed(errors.len()));
+                }
+
+                let secrets_patch = &result.plan.secrets_patch;
+                let mut secrets_store: Box<dyn SecretsStore> = match &encrypted {
+                    Some(store) => Box::new(store.clone()),
+                    None if secrets_patch.set.is_empty() && secrets_patch.delete.is_empty() => {
+                        Box::new(InMemorySecretsStore::default())
+                    }
+                    None => {
+                        return Err(CliError::MissingSecretsKey(
+                            "--secrets-key-file or --secrets-passphrase-env to store the plan's secrets",
+                        ));
+                    }
+                };
+                let mut oauth_handler: Box<dyn OAuthHandler> = match &oauth_providers {
+                    Some(path) => Box::new(pkce_oauth_handler(
+                        path,
+                        http_fixtures.as_ref(),
+                        encrypted.ok_or(CliError::MissingSecretsKey(
+                            "--secrets-key-file or --secrets-passphrase-env to keep pending OAuth authorizations",
+                        ))?,
+                    )?),
+                    None => Box::new(NoopOAuthHandler),
+                };
+                let mut applier = ProvisionApplier::new(
+                    inputs,
+                    config_store,
+                    secrets_store.as_mut(),
+                    oauth_handler.as_mut(),
+                    FileInstallStore::new(installs.unwrap_or_else(FileInstallStore::default_path))?,
+                );
+                let report = applier.apply(result, ApplyMode::Apply)?;
+
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                } else {
+                    println!(
+                        "Applied {} config changes to {}.",
+                        report.config_changes.len(),
+                        report.install_record.config_namespace
+                    );
+                    for pending in &report.pending_authorizations {
+                        println!("Authorize {} at {}", pending.provider, pending.url);
+                        if let Some(command) = &finish_command {
+                            println!(
+                                "  then run `{command} --redirect <url>` with the URL it redirects to (state {})",
+                                pending.state
+                            );
+                        }
+                    }
+                }
+            }
+        },
+        Commands::Oauth { command } => match command {
+            OAuthCommands::Finish {
+                provider_id,
+                install_id,
+                tenant,
+                redirect,
+                state,
+                code,
+                oauth_providers,
+                http_fixtures,
+                installs,
+                secrets,
+                json,
+            } => {
+                let callback = match (redirect, state, code) {
+                    (Some(redirect), _, _) => {
+                        AuthorizationCallback::from_redirect(&redirect).map_err(CliError::OAuth)?
+                    }
+                    (None, Some(state), Some(code)) => AuthorizationCallback {
+                        code: SecretString::new(code),
+                        state,
+                    },
+                    _ => unreachable!("clap requires --redirect or --state with --code"),
+                };
+                let store = secrets.open()?.ok_or(CliError::MissingSecretsKey(
+                    "--secrets-key-file or --secrets-passphrase-env",
+                ))?;
+                let handler =
+                    pkce_oauth_handler(&oauth_providers, http_fixtures.as_ref(), store.clone())?;
+                let inputs = ProvisionInputs {
+                    tenant: tenant.context(),
+                    provider_id: provider_id.clone(),
+                    install_id: install_id.clone(),
+                    public_base_url: None,
+                    answers: Value::Object(serde_json::Map::new()),
+                    existing_state: None,
+                };
+                // Finishing writes only the tokens; config is never touched.
+                let mut applier = ProvisionApplier::new(
+                    inputs,
+                    InMemoryConfigStore::default(),
+                    store,
+                    handler,
+                    FileInstallStore::new(installs.unwrap_or_else(FileInstallStore::default_path))?,
+                );
+                let report = applier
+                    .finish_authorization(&callback.state, &callback.code)
+                    .map_err(|failure| match failure.error {
+                        ApplyError::NotFound(_) if failure.phase == "install_record" => {
+                            CliError::InstallNotFound {
+                                provider_id,
+                                install_id,
+                            }
+                        }
+                        _ => failure.into(),
+                    })?;
+                let record = &report.install_record;
+                let namespace = &record.secrets_namespace;
+                let stored = &report.secret_set_keys;
+
+                if json {
+                    let summary = serde_json::json!({
+                        "provider_id": record.provider_id,
+                        "install_id": record.install_id,
+                        "secrets_namespace": namespace,
+                        "stored": stored,
+                    });
+                    println!("{}", serde_json::to_string_pretty(&summary)?);
+                } else {
+                    println!("Stored {} in {namespace}.", stored.join(" and "));
+                }
+            }
+        },
+        Commands::Secrets { command } => match command {
+            SecretsCommands::Keygen { out } => {
+                let key = SecretsKey::generate();
+                key.save(&out)?;
+                println!("Wrote secrets key {} to {}", key.id(), out.display());
+            }
+            SecretsCommands::Rotate {
+                secrets_dir,
+                key_file,
+                passphrase_env,
+                new_key_file,
+                new_passphrase_env,
+            } => {
+                let secrets_dir =
+                    secrets_dir.unwrap_or_else(EncryptedFileSecretsStore::default_dir);
+                let key =
+                    secrets_key(&secrets_dir, key_file.as_deref(), passphrase_env.as_deref())?
+                        .ok_or(CliError::MissingSecretsKey(
+                            "--key-file or --passphrase-env",
+                        ))?;
+                let new_key = secrets_key(
+                    &secrets_dir,
+                    new_key_file.as_deref(),
+                    new_passphrase_env.as_deref(),
+                )?
+                .ok_or(CliError::MissingSecretsKey(
+                    "--new-key-file or --new-passphrase-env",
+                ))?;
+                let new_id = new_key.id();
+                let mut store = EncryptedFileSecretsStore::new(&secrets_dir, key);
+                let rewritten = store.rotate(new_key)?;
+                println!("Re-encrypted {rewritten} secrets namespaces with key {new_id}.");
+            }
+        },
         Commands::Conformance {
             packs,
             report,
             provider,
             live,
+            unsafe_show_secrets,
         } => {
             if live {
                 eprintln!("warning: live mode is not implemented; running dry-run only");
             }
-            run_conformance(&packs, &report, provider.as_deref())?;
+            run_conformance(&packs, &report, provider.as_deref(), unsafe_show_secrets)?;
         }
+        Commands::Replay {
+            artifact,
+            json,
+            unsafe_show_secrets,
+        } => run_replay(&artifact, json, unsafe_show_secrets)?,
+        Commands::Serve {
+            listen,
+            metrics,
+            installs,
+            config_dir,
+            secrets,
+        } => server::serve(
+            &listen,
+            server::ServerState::new(
+                FileInstallStore::new(installs.unwrap_or_else(FileInstallStore::default_path))?,
+                FileConfigStore::new(config_dir.unwrap_or_else(FileConfigStore::default_dir)),
+                secrets.open()?,
+                metrics.then(ProvisionMetrics::new),
+            ),
+        )?,
     }
 
     Ok(())
@@ -197,6 +715,7 @@ fn run_conformance(
     packs_dir: &PathBuf,
     report_path: &PathBuf,
     provider: Option<&str>,
+    show_secrets: bool,
 ) -> Result<(), CliError> {
     let log_dir = PathBuf::from("target/conformance_logs");
     std::fs::create_dir_all(&log_dir)?;
@@ -253,17 +772,55 @@ fn run_conformance(
             }
         };
 
+        let answers_path = pack_ctx.root.join("fixtures").join("answers.json");
+        let answers = if answers_path.exists() {
+            match load_json_value(&answers_path) {
+                Ok(answers) => answers,
+                Err(err) => {
+                    reports.push(ConformancePackReport::failed(
+                        &pack_label,
+                        format!("answers fixture error: {err}"),
+                    ));
+                    continue;
+                }
+            }
+        } else {
+            Value::Object(serde_json::Map::new())
+        };
+
         let inputs = ProvisionInputs {
             tenant: TenantContext::default(),
             provider_id: descriptor.pack_id.clone(),
             install_id: format!("{}-install", descriptor.pack_id),
             public_base_url: Some("https://example.invalid".to_string()),
-            answers: Value::Object(serde_json::Map::new()),
+            answers,
             existing_state: None,
         };
 
-        let executor = match WasmtimeExecutor::new(&pack_ctx.root, ExecutionLimits::default()) {
-            Ok(exec) => exec,
+        let fixtures_path = pack_ctx.root.join("fixtures").join("http.json");
+        let fixtures = if fixtures_path.exists() {
+            match HttpFixtures::load(&fixtures_path) {
+                Ok(fixtures) => fixtures,
+                Err(err) => {
+                    reports.push(ConformancePackReport::failed(
+                        &pack_label,
+                        format!("http fixtures error: {err}"),
+                    ));
+                    continue;
+                }
+            }
+        } else {
+            HttpFixtures::default()
+        };
+
+        let recorder = HostRecorder::new();
+        let executor = match WasmtimeExecutor::new(&pack_ctx.root, ExecutionLimits::default())
+            .map_err(CliError::from)
+            .and_then(|exec| pack_executor(exec, &manifest))
+        {
+            Ok(exec) => exec.with_host(
+                replay_host(HostBindings::new(), fixtures).with_recorder(recorder.clone()),
+            ),
             Err(err) => {
                 reports.push(ConformancePackReport::failed(
                     &pack_label,
@@ -273,30 +830,35 @@ fn run_conformance(
             }
         };
 
-        if let Some(requirements_flow) = descriptor.requirements_flow.as_deref() {
-            let ctx = greentic_provision_core::ProvisionContext {
-                inputs: inputs.clone(),
-                mode: ProvisionMode::DryRun,
-                step: ProvisionStep::Validate,
-                prior_results: Vec::new(),
-            };
-            if let Err(err) = executor.run_named_step(requirements_flow, &ctx) {
-                reports.push(ConformancePackReport::failed(
-                    &pack_label,
-                    format!("requirements failed: {err}"),
-                ));
-                continue;
-            }
+        if let Some(requirements_flow) = descriptor.requirements_flow.as_deref()
+            && let Err(err) = run_requirements(&executor, requirements_flow, &inputs)
+        {
+            reports.push(ConformancePackReport::failed(
+                &pack_label,
+                format!("requirements failed: {err}"),
+            ));
+            continue;
         }
 
         let engine = ProvisionEngine::new(executor);
         let result = engine.run(ProvisionMode::DryRun, inputs.clone());
 
-        let checks = check_conformance(&result);
+        let recording = recorder.recording();
+        let redaction = OutputRedaction::new(&manifest, &inputs.answers, show_secrets);
+        let mut checks = check_conformance(&result);
+        checks.extend(check_secret_leaks(&redaction.scanner, &result, &recording));
         let report_entry = if checks.is_empty() {
             ConformancePackReport::passed(&pack_label, descriptor.pack_version.clone(), result)
         } else {
-            capture_failure_artifacts(&pack_label, &inputs, &result)?;
+            capture_failure_artifacts(
+                &pack_label,
+                &pack_path,
+                &pack_ctx.root,
+                &inputs,
+                &result,
+                &recording,
+                &redaction,
+            )?;
             ConformancePackReport::failed_with(&pack_label, descriptor.pack_version.clone(), checks)
         };
         write_conformance_log(&log_dir, &report_entry)?;
@@ -315,6 +877,192 @@ fn run_conformance(
     Ok(())
 }
 
+/// Runs a pack's requirements flow ahead of its setup flow, as a dry-run `validate` step. Its host
+/// calls are recorded as a step of their own, so `replay` must run it too.
+fn run_requirements(
+    executor: &WasmtimeExecutor,
+    requirements_flow: &str,
+    inputs: &ProvisionInputs,
+) -> Result<(), greentic_provision_core::executor::ExecutorError> {
+    let ctx = greentic_provision_core::ProvisionContext {
+        inputs: inputs.clone(),
+        mode: ProvisionMode::DryRun,
+        step: ProvisionStep::Validate,
+        prior_results: Vec::new(),
+    };
+    executor.run_named_step(requirements_flow, &ctx).map(drop)
+}
+
+/// The encrypted secrets key from a key file or a passphrase held in an environment variable.
+fn secrets_key(
+    secrets_dir: &Path,
+    key_file: Option<&Path>,
+    passphrase_env: Option<&str>,
+) -> Result<Option<SecretsKey>, CliError> {
+    if let Some(path) = key_file {
+        return Ok(Some(SecretsKey::load(path)?));
+    }
+    let Some(var) = passphrase_env else {
+        return Ok(None);
+    };
+    let passphrase =
+        std::env::var(var).map_err(|_| CliError::MissingPassphrase(var.to_string()))?;
+    Ok(Some(EncryptedFileSecretsStore::passphrase_key(
+        secrets_dir,
+        &passphrase,
+    )?))
+}
+
+/// A PKCE handler for the providers in `path`. Token requests only reach the configured token
+/// endpoints.
+fn pkce_oauth_handler<S: SecretsStore>(
+    path: &Path,
+    http_fixtures: Option<&PathBuf>,
+    pending: S,
+) -> Result<PkceOAuthHandler<S>, CliError> {
+    let entries: BTreeMap<String, OAuthProviderEntry> =
+        serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let providers = entries
+        .into_iter()
+        .map(|(name, entry)| Ok((name.clone(), oauth_provider_config(&name, entry)?)))
+        .collect::<Result<Vec<_>, CliError>>()?;
+    let token_hosts = providers
+        .iter()
+        .map(|(name, config)| {
+            Url::parse(config.token_url())
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_string))
+                .ok_or_else(|| {
+                    CliError::OAuthProviders(format!("provider {name}: invalid token_url"))
+                })
+        })
+        .collect::<Result<Vec<_>, CliError>>()?;
+    let transport = allowlisted_transport(http_fixtures, token_hosts)?;
+    let mut handler = PkceOAuthHandler::new(transport, pending);
+    for (name, config) in providers {
+        handler = handler.with_provider(name, config);
+    }
+    Ok(handler)
+}
+
+fn oauth_provider_config(
+    name: &str,
+    entry: OAuthProviderEntry,
+) -> Result<OAuthProviderConfig, CliError> {
+    let invalid = |reason: &str| CliError::OAuthProviders(format!("provider {name}: {reason}"));
+    let mut config = match (&entry.preset, &entry.authorize_url, &entry.token_url) {
+        (Some(preset), _, _) => {
+            OAuthProviderConfig::preset(preset, &entry.client_id).map_err(CliError::OAuth)?
+        }
+        (None, Some(authorize_url), Some(token_url)) => {
+            OAuthProviderConfig::new(authorize_url, token_url, &entry.client_id)
+        }
+        _ => {
+            return Err(invalid(
+                "needs a preset or both authorize_url and token_url",
+            ));
+        }
+    };
+    if let Some(url) = entry.authorize_url {
+        config = config.with_authorize_url(url);
+    }
+    if let Some(url) = entry.token_url {
+        config = config.with_token_url(url);
+    }
+    if let Some(url) = entry.redirect_url {
+        config = config.with_redirect_url(url);
+    }
+    if !entry.scopes.is_empty() {
+        config = config.with_default_scopes(entry.scopes);
+    }
+    if let Some(var) = entry.client_secret_env {
+        let secret = std::env::var(&var)
+            .map_err(|_| invalid(&format!("environment variable {var} is not set")))?;
+        config = config.with_client_secret(secret);
+    }
+    Ok(config)
+}
+
+fn dry_run_executor(
+    kind: ExecutorKind,
+    pack_ctx: &PackContext,
+    manifest: &PackManifest,
+    http_fixtures: Option<&PathBuf>,
+    host: HostBindings,
+) -> Result<CliExecutor, CliError> {
+    Ok(match kind {
+        ExecutorKind::Noop => CliExecutor::Noop(NoopExecutor),
+        ExecutorKind::Wasm => {
+            let fixtures = http_fixtures
+                .map(|path| HttpFixtures::load(path))
+                .transpose()?
+                .unwrap_or_default();
+            let executor = pack_executor(
+                WasmtimeExecutor::new(&pack_ctx.root, ExecutionLimits::default())?,
+                manifest,
+            )?
+            .with_host(replay_host(host, fixtures));
+            CliExecutor::Wasm(Box::new(executor))
+        }
+    })
+}
+
+/// Executor for `apply setup`. Pack HTTP calls go to the network, or to `http_fixtures` when
+/// given, and only reach the hosts listed in the manifest's `meta.http_hosts`.
+fn apply_executor(
+    kind: ExecutorKind,
+    pack_ctx: &PackContext,
+    manifest: &PackManifest,
+    http_fixtures: Option<&PathBuf>,
+    host: HostBindings,
+) -> Result<CliExecutor, CliError> {
+    Ok(match kind {
+        ExecutorKind::Noop => CliExecutor::Noop(NoopExecutor),
+        ExecutorKind::Wasm => {
+            let transport = allowlisted_transport(http_fixtures, manifest.meta.http_hosts.clone())?;
+            let executor = pack_executor(
+                WasmtimeExecutor::new(&pack_ctx.root, ExecutionLimits::default())?,
+                manifest,
+            )?
+            .with_host(host.with_http_transport(transport));
+            CliExecutor::Wasm(Box::new(executor))
+        }
+    })
+}
+
+/// Network transport for apply-time HTTP, or a replay of `http_fixtures` when given, restricted
+/// to `hosts` either way.
+fn allowlisted_transport(
+    http_fixtures: Option<&PathBuf>,
+    hosts: Vec<String>,
+) -> Result<Arc<dyn HttpTransport>, CliError> {
+    Ok(match http_fixtures {
+        Some(path) => Arc::new(AllowlistTransport::new(
+            ReplayTransport::new(HttpFixtures::load(path)?),
+            hosts,
+        )),
+        None => Arc::new(AllowlistTransport::new(NetworkTransport::new()?, hosts)),
+    })
+}
+
+/// `host` for dry-runs: HTTP is only ever served from fixtures.
+fn replay_host(host: HostBindings, fixtures: HttpFixtures) -> HostBindings {
+    host.with_http_transport(Arc::new(ReplayTransport::new(fixtures)))
+}
+
+/// Host bindings whose `config_get` and `secret_exists` read the install's namespaces in the
+/// stores an apply writes to, so a re-run sees what the previous one stored.
+fn store_host(
+    config: &FileConfigStore,
+    secrets: Option<&EncryptedFileSecretsStore>,
+) -> HostBindings {
+    let host = HostBindings::new().with_config_store(Arc::new(Mutex::new(config.clone())));
+    match secrets {
+        Some(store) => host.with_secrets_store(Arc::new(Mutex::new(store.clone()))),
+        None => host,
+    }
+}
+
 fn check_conformance(result: &greentic_provision_core::ProvisionResult) -> Vec<String> {
     let mut errors = Vec::new();
     let serialized_once = serde_json::to_string(&result.plan).unwrap_or_default();
@@ -331,38 +1079,314 @@ fn check_conformance(result: &greentic_provision_core::ProvisionResult) -> Vec<S
     {
         errors.push("secrets_patch contains non-redacted values".to_string());
     }
+    for (key, value) in &result.plan.secrets_patch.set {
+        if let Some(Err(err)) = value.reference.as_deref().map(SecretRef::parse) {
+            errors.push(format!("secrets_patch.{key}: {err}"));
+        }
+    }
+    errors.extend(check_step_dispatch(result));
+    errors
+}
+
+/// Secret answers may only leave a pack through `secrets_patch`. Anything else in the result, or
+/// in the host calls written to failure artifacts, must not carry them in any encoding.
+fn check_secret_leaks(
+    scanner: &LeakScanner,
+    result: &greentic_provision_core::ProvisionResult,
+    recording: &HostRecording,
+) -> Vec<String> {
+    let host_calls = serde_json::to_value(recording).unwrap_or_default();
+    scanner
+        .scan_result(result)
+        .into_iter()
+        .chain(scanner.scan_value("host_calls", &host_calls))
+        .map(|leak| format!("secret leak: {leak}"))
+        .collect()
+}
+
+/// A single-component pack must answer every step value, since the engine drives all four steps
+/// through the same module.
+fn check_step_dispatch(result: &greentic_provision_core::ProvisionResult) -> Vec<String> {
+    let step_results = result.step_results.as_deref().unwrap_or_default();
+    if !step_results
+        .iter()
+        .any(|step| step.output.layout == Some(StepLayout::SingleComponent))
+    {
+        return Vec::new();
+    }
+
+    let mut errors = Vec::new();
+    for step in [
+        ProvisionStep::Collect,
+        ProvisionStep::Validate,
+        ProvisionStep::Apply,
+        ProvisionStep::Summary,
+    ] {
+        let Some(step_result) = step_results.iter().find(|result| result.step == step) else {
+            errors.push(format!(
+                "single component did not run step {}",
+                step.as_str()
+            ));
+            continue;
+        };
+        if let Some(error) = step_result.output.data.get("error") {
+            errors.push(format!(
+                "single component failed step {}: {}",
+                step.as_str(),
+                error.as_str().unwrap_or_default()
+            ));
+        }
+    }
     errors
 }
 
 fn capture_failure_artifacts(
     pack_label: &str,
+    pack_path: &Path,
+    pack_root: &Path,
     inputs: &ProvisionInputs,
     result: &greentic_provision_core::ProvisionResult,
+    recording: &HostRecording,
+    redaction: &OutputRedaction,
 ) -> Result<(), CliError> {
     let timestamp = greentic_provision_core::executor::timestamp_label();
     let artifact_dir = PathBuf::from(".greentic/provision/artifacts")
         .join(pack_label)
         .join(timestamp);
     std::fs::create_dir_all(&artifact_dir)?;
-    std::fs::write(
-        artifact_dir.join("inputs.json"),
-        serde_json::to_string_pretty(inputs)?,
-    )?;
+    std::fs::write(artifact_dir.join("inputs.json"), redaction.to_json(inputs)?)?;
     std::fs::write(
         artifact_dir.join("step_outputs.json"),
-        serde_json::to_string_pretty(&result.step_results)?,
+        redaction.to_json(&result.step_results)?,
     )?;
     std::fs::write(
         artifact_dir.join("diagnostics.json"),
-        serde_json::to_string_pretty(&result.diagnostics)?,
+        redaction.to_json(&result.diagnostics)?,
     )?;
+    // Replays run the copy, so the artifact still reproduces after the pack changes or moves.
+    copy_dir(pack_root, &artifact_dir.join(ARTIFACT_PACK_DIR))?;
+    let artifact_pack = ArtifactPack {
+        pack: pack_label.to_string(),
+        path: std::path::absolute(pack_path)?,
+        digest: pack_digest(&artifact_dir.join(ARTIFACT_PACK_DIR))?,
+    };
     std::fs::write(
         artifact_dir.join("pack.json"),
-        serde_json::to_string_pretty(&serde_json::json!({ "pack": pack_label }))?,
+        serde_json::to_string_pretty(&artifact_pack)?,
+    )?;
+    std::fs::write(
+        artifact_dir.join("host_calls.json"),
+        redaction.to_json(recording)?,
     )?;
     Ok(())
 }
 
+/// Redacts secret answers from what the CLI prints or writes, unless `--unsafe-show-secrets` is
+/// given, in which case plaintext plan secrets are shown too.
+struct OutputRedaction {
+    scanner: LeakScanner,
+    show_secrets: bool,
+}
+
+impl OutputRedaction {
+    fn new(manifest: &PackManifest, answers: &Value, show_secrets: bool) -> Self {
+        Self {
+            scanner: LeakScanner::from_answers_with_schema(
+                answers,
+                manifest.meta.answers_schema.as_ref(),
+            ),
+            show_secrets,
+        }
+    }
+
+    fn to_json(&self, value: &impl serde::Serialize) -> Result<String, CliError> {
+        Ok(serde_json::to_string_pretty(&self.to_value(value)?)?)
+    }
+
+    fn to_value(&self, value: &impl serde::Serialize) -> serde_json::Result<Value> {
+        if self.show_secrets {
+            return expose_secrets(|| serde_json::to_value(value));
+        }
+        self.scanner.redact(value)
+    }
+}
+
+/// Directory of a failure artifact holding the copy of the pack that failed.
+const ARTIFACT_PACK_DIR: &str = "pack";
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct ArtifactPack {
+    pack: String,
+    /// Where the pack was loaded from when the artifact was captured.
+    path: PathBuf,
+    /// Digest of the copy in [`ARTIFACT_PACK_DIR`], checked before replaying it.
+    digest: String,
+}
+
+/// Copies the files under `from` into `to`, creating directories as needed.
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+/// `sha256:`-prefixed digest over the relative path and contents of every file under `root`, in
+/// path order.
+fn pack_digest(root: &Path) -> std::io::Result<String> {
+    fn collect(root: &Path, dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                collect(root, &path, files)?;
+            } else {
+                files.push(
+                    path.strip_prefix(root)
+                        .map_err(std::io::Error::other)?
+                        .into(),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    collect(root, root, &mut files)?;
+    files.sort();
+    let mut hasher = Sha256::new();
+    for file in files {
+        let name = file.to_string_lossy().replace('\\', "/");
+        let contents = std::fs::read(root.join(&file))?;
+        hasher.update((name.len() as u64).to_le_bytes());
+        hasher.update(name.as_bytes());
+        hasher.update((contents.len() as u64).to_le_bytes());
+        hasher.update(&contents);
+    }
+    Ok(format!("sha256:{:x}", hasher.finalize()))
+}
+
+/// Applies what the pack manifest declares about its runtime needs.
+fn pack_executor(
+    executor: WasmtimeExecutor,
+    manifest: &PackManifest,
+) -> Result<WasmtimeExecutor, CliError> {
+    let capabilities = Capability::parse_declared(&manifest.meta.capabilities)
+        .map_err(CliError::InvalidManifest)?;
+    let mut executor = executor.with_capabilities(capabilities);
+    if manifest.meta.wasi {
+        executor = executor.with_wasi(WasiOptions::default());
+    }
+    if let Some(layout) = manifest.meta.step_layout {
+        executor = executor.with_step_layout(layout);
+    }
+    Ok(executor)
+}
+
+fn run_replay(artifact_dir: &Path, json: bool, show_secrets: bool) -> Result<(), CliError> {
+    let artifact_pack: ArtifactPack =
+        serde_json::from_value(load_json_value(&artifact_dir.join("pack.json"))?)?;
+    let inputs: ProvisionInputs =
+        serde_json::from_value(load_json_value(&artifact_dir.join("inputs.json"))?)?;
+    let recorded: Option<Vec<StepResult>> =
+        serde_json::from_value(load_json_value(&artifact_dir.join("step_outputs.json"))?)?;
+    let recording = HostRecording::load(&artifact_dir.join("host_calls.json"))?;
+
+    let pack_root = artifact_dir.join(ARTIFACT_PACK_DIR);
+    if pack_digest(&pack_root)? != artifact_pack.digest {
+        return Err(CliError::ArtifactPackChanged(pack_root));
+    }
+    let manifest = load_manifest(&pack_root)?;
+    let redaction = OutputRedaction::new(&manifest, &inputs.answers, show_secrets);
+    let replay = HostReplay::new(recording);
+    let executor = pack_executor(
+        WasmtimeExecutor::new(&pack_root, ExecutionLimits::default())?,
+        &manifest,
+    )?
+    .with_host(HostBindings::new().with_replay(replay.clone()));
+    // Conformance only writes artifacts for packs whose requirements flow passed.
+    if let Some(requirements_flow) = DefaultProvisionPackDiscovery::discover(&manifest)
+        .and_then(|descriptor| descriptor.requirements_flow)
+    {
+        run_requirements(&executor, &requirements_flow, &inputs).map_err(|err| {
+            CliError::ReplayDiverged(format!("requirements flow {requirements_flow}: {err}"))
+        })?;
+    }
+    let result = ProvisionEngine::new(executor).run(ProvisionMode::DryRun, inputs);
+
+    if json {
+        println!("{}", redaction.to_json(&result)?);
+    }
+    let replayed = result.step_results.unwrap_or_default();
+    let recorded = recorded.unwrap_or_default();
+    // Artifacts hold redacted answers and step outputs. A step whose recorded output had secrets
+    // redacted ran on the real answers, so the replay cannot reproduce it from the artifact.
+    let mut diverged = Vec::new();
+    let mut unreproducible = Vec::new();
+    for (replayed, recorded) in replayed.iter().zip(recorded.iter()) {
+        let recorded_value = serde_json::to_value(recorded)?;
+        if serde_json::to_value(replayed)? == recorded_value {
+            continue;
+        }
+        let step = replayed.step.as_str().to_string();
+        if contains_redacted(&recorded_value) {
+            unreproducible.push(step);
+        } else {
+            diverged.push(step);
+        }
+    }
+    if !diverged.is_empty() {
+        return Err(CliError::ReplayDiverged(diverged.join(", ")));
+    }
+    if replayed.len() != recorded.len() {
+        return Err(CliError::ReplayDiverged(format!(
+            "replayed {} steps, recorded {}",
+            replayed.len(),
+            recorded.len()
+        )));
+    }
+    let unconsumed = replay.unconsumed();
+    if !unconsumed.is_empty() {
+        let steps: Vec<_> = unconsumed
+            .iter()
+            .map(|step| {
+                let calls: Vec<_> = step.calls.iter().map(HostCall::name).collect();
+                format!("{} ({})", step.step, calls.join(", "))
+            })
+            .collect();
+        return Err(CliError::ReplayCallsLeftOver(steps.join("; ")));
+    }
+    for step in &unreproducible {
+        eprintln!("step {step}: redacted, not reproducible");
+    }
+    if !json {
+        println!(
+            "Replay of {} matched {} recorded steps.",
+            artifact_pack.pack,
+            recorded.len() - unreproducible.len()
+        );
+    }
+    Ok(())
+}
+
+/// Whether a string or key anywhere in `value` had a secret replaced with [`REDACTED`].
+fn contains_redacted(value: &Value) -> bool {
+    match value {
+        Value::String(text) => text.contains(REDACTED),
+        Value::Array(items) => items.iter().any(contains_redacted),
+        Value::Object(map) => map
+            .iter()
+            .any(|(key, item)| key.contains(REDACTED) || contains_redacted(item)),
+        _ => false,
+    }
+}
+
 fn write_conformance_log(
     log_dir: &std::path::Path,
     report: &ConformancePackReport,
@@ -619,6 +1643,54 @@ enum CliError {
     Zip(#[from] zip::result::ZipError),
     #[error("executor error: {0}")]
     Executor(#[from] greentic_provision_core::executor::ExecutorError),
+    #[error("http fixtures error: {0}")]
+    HttpFixtures(#[from] greentic_provision_core::HttpError),
     #[error("conformance failed")]
     ConformanceFailed,
+    #[error("setup reported {0} error diagnostics; nothing was applied")]
+    SetupFailed(usize),
+    #[error("invalid pack manifest: {}", describe_diagnostics(.0))]
+    InvalidManifest(Vec<Diagnostic>),
+    #[error("replay diverged from recorded step outputs: {0}")]
+    ReplayDiverged(String),
+    #[error("replay left recorded host calls unused: {0}")]
+    ReplayCallsLeftOver(String),
+    #[error("artifact pack {0} does not match the digest recorded in pack.json")]
+    ArtifactPackChanged(PathBuf),
+    #[error("server error: {0}")]
+    Server(String),
+    #[error(transparent)]
+    Apply(#[from] greentic_provision_core::ApplyFailure),
+    #[error("secrets store error: {0}")]
+    Secrets(#[from] greentic_provision_core::ApplyError),
+    #[error("secrets key error: {0}")]
+    SecretsKey(#[from] greentic_provision_core::SecretsKeyError),
+    #[error("a secrets key is required: pass {0}")]
+    MissingSecretsKey(&'static str),
+    #[error("environment variable {0} holding the secrets passphrase is not set")]
+    MissingPassphrase(String),
+    #[error("oauth error: {0}")]
+    OAuth(greentic_provision_core::ApplyError),
+    #[error("invalid oauth providers file: {0}")]
+    OAuthProviders(String),
+    #[error("no install {install_id} of provider {provider_id}; run `apply setup` first")]
+    InstallNotFound {
+        provider_id: String,
+        install_id: String,
+    },
+    #[cfg(feature = "otel")]
+    #[error("telemetry error: {0}")]
+    Telemetry(String),
+}
+
+/// `code: message (path)` for each diagnostic, separated by `; `.
+fn describe_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|diagnostic| match &diagnostic.path {
+            Some(path) => format!("{}: {} ({path})", diagnostic.code, diagnostic.message),
+            None => format!("{}: {}", diagnostic.code, diagnostic.message),
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
 }
@@ -0,0 +1,52 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing_subscriber::layer::SubscriberExt;
+
+use crate::CliError;
+
+/// Exports spans over OTLP/HTTP until dropped, flushing anything still buffered.
+pub struct OtlpExport {
+    provider: SdkTracerProvider,
+}
+
+impl OtlpExport {
+    /// `endpoint` is the collector base URL, e.g. `http://localhost:4318`; the traces path is
+    /// appended when missing.
+    pub fn install(endpoint: &str) -> Result<Self, CliError> {
+        let endpoint = endpoint.trim_end_matches('/');
+        let endpoint = if endpoint.ends_with("/v1/traces") {
+            endpoint.to_string()
+        } else {
+            format!("{endpoint}/v1/traces")
+        };
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()
+            .map_err(|err| CliError::Telemetry(err.to_string()))?;
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(
+                Resource::builder()
+                    .with_service_name("greentic-provision")
+                    .build(),
+            )
+            .build();
+        let tracer = provider.tracer("greentic-provision");
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        tracing::subscriber::set_global_default(subscriber)
+            .map_err(|err| CliError::Telemetry(err.to_string()))?;
+        Ok(Self { provider })
+    }
+}
+
+impl Drop for OtlpExport {
+    fn drop(&mut self) {
+        if let Err(err) = self.provider.shutdown() {
+            eprintln!("warning: failed to flush spans: {err}");
+        }
+    }
+}
@@ -0,0 +1,493 @@
+//! `greentic-provision serve`: a local REST/JSON API over the engine and applier.
+//!
+//! Requests are handled one at a time on the calling thread, so a plan request blocks the server
+//! until the pack has run. Sessions live in memory; install records, config and secrets go to the
+//! same file stores `apply setup` writes to.
+
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher, RandomState};
+use std::io::{Cursor, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use greentic_provision_core::discovery::PackManifest;
+use greentic_provision_core::{
+    ApplyError, ApplyErrorKind, ApplyMode, ApplyReport, DefaultProvisionPackDiscovery,
+    EncryptedFileSecretsStore, FileConfigStore, FileInstallStore, InMemorySecretsStore,
+    InstallStore, NoopOAuthHandler, ProvisionApplier, ProvisionDescriptor, ProvisionEngine,
+    ProvisionInputs, ProvisionMetrics, ProvisionMode, ProvisionPackDiscovery, ProvisionResult,
+    SecretsStore, TenantContext,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+
+use crate::{
+    CliError, CliExecutor, ExecutorKind, OutputRedaction, PackContext, dry_run_executor, store_host,
+};
+
+/// Shared state of `greentic-provision serve`.
+pub struct ServerState {
+    metrics: Option<ProvisionMetrics>,
+    installs: FileInstallStore,
+    config: FileConfigStore,
+    /// `None` without a secrets key; plans that set or delete secrets are then refused.
+    secrets: Option<EncryptedFileSecretsStore>,
+    sessions: BTreeMap<String, Session>,
+    ids: RandomState,
+    next_id: u64,
+}
+
+struct Session {
+    pack: PackContext,
+    manifest: PackManifest,
+    descriptor: ProvisionDescriptor,
+    executor: ExecutorKind,
+    http_fixtures: Option<PathBuf>,
+    inputs: ProvisionInputs,
+    plan: Option<ProvisionResult>,
+    approved: bool,
+    applied: Option<ApplyReport>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InspectRequest {
+    pack: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateSession {
+    pack: PathBuf,
+    provider_id: String,
+    install_id: String,
+    #[serde(default)]
+    public_base_url: Option<String>,
+    #[serde(default)]
+    tenant: TenantContext,
+    #[serde(default)]
+    answers: Option<Value>,
+    #[serde(default)]
+    executor: ExecutorKind,
+    /// Canned HTTP responses served to the pack's `http` capability.
+    #[serde(default)]
+    http_fixtures: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct SessionView<'a> {
+    id: &'a str,
+    status: &'static str,
+    descriptor: &'a ProvisionDescriptor,
+    tenant: &'a TenantContext,
+    provider_id: &'a str,
+    install_id: &'a str,
+    answers: &'a Value,
+    diagnostics: Option<usize>,
+}
+
+impl Session {
+    fn status(&self) -> &'static str {
+        match (&self.plan, self.approved, &self.applied) {
+            (_, _, Some(_)) => "applied",
+            (Some(_), true, None) => "approved",
+            (Some(_), false, None) => "planned",
+            (None, _, None) => "collecting",
+        }
+    }
+
+    /// Secret answers, by name or by the pack's `answers_schema`, never leave the server.
+    fn redaction(&self) -> OutputRedaction {
+        OutputRedaction::new(&self.manifest, &self.inputs.answers, false)
+    }
+
+    fn view(&self, id: &str) -> serde_json::Result<Value> {
+        self.redaction().to_value(&SessionView {
+            id,
+            status: self.status(),
+            descriptor: &self.descriptor,
+            tenant: &self.inputs.tenant,
+            provider_id: &self.inputs.provider_id,
+            install_id: &self.inputs.install_id,
+            answers: &self.inputs.answers,
+            diagnostics: self.plan.as_ref().map(|plan| plan.diagnostics.len()),
+        })
+    }
+}
+
+enum Reply {
+    Json(u16, Value),
+    Text(String, &'static str),
+    Empty,
+}
+
+#[derive(Debug)]
+struct ApiError {
+    status: u16,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn not_found(what: &str) -> Self {
+        Self::new(404, format!("{what} not found"))
+    }
+
+    fn conflict(message: impl Into<String>) -> Self {
+        Self::new(409, message)
+    }
+}
+
+impl From<CliError> for ApiError {
+    fn from(err: CliError) -> Self {
+        Self::new(400, err.to_string())
+    }
+}
+
+impl From<ApplyError> for ApiError {
+    fn from(err: ApplyError) -> Self {
+        Self::new(store_status(err.kind()), err.to_string())
+    }
+}
+
+/// A store failure is the server's problem, not the client's: 404 and 403 are kept for requests
+/// naming a missing session or install, so a store that loses data or refuses the server's own
+/// writes is a 500 and one that cannot be reached is a 503. Only an invalid plan is the client's.
+fn store_status(kind: ApplyErrorKind) -> u16 {
+    match kind {
+        ApplyErrorKind::Invalid => 400,
+        ApplyErrorKind::Backend => 503,
+        ApplyErrorKind::Conflict => 409,
+        ApplyErrorKind::NotFound | ApplyErrorKind::PermissionDenied => 500,
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::new(400, format!("invalid request body: {err}"))
+    }
+}
+
+pub fn serve(listen: &str, mut state: ServerState) -> Result<(), CliError> {
+    let server = Server::http(listen).map_err(|err| CliError::Server(err.to_string()))?;
+    match server.server_addr().to_ip() {
+        Some(addr) => println!("Listening on http://{addr}"),
+        None => println!("Listening on {listen}"),
+    }
+    std::io::stdout().flush()?;
+
+    for mut request in server.incoming_requests() {
+        let reply = state
+            .route(&mut request)
+            .unwrap_or_else(|err| Reply::Json(err.status, json!({ "error": err.message })));
+        if let Err(err) = request.respond(response(reply)) {
+            eprintln!("warning: failed to send response: {err}");
+        }
+    }
+    Ok(())
+}
+
+impl ServerState {
+    pub fn new(
+        installs: FileInstallStore,
+        config: FileConfigStore,
+        secrets: Option<EncryptedFileSecretsStore>,
+        metrics: Option<ProvisionMetrics>,
+    ) -> Self {
+        Self {
+            metrics,
+            installs,
+            config,
+            secrets,
+            sessions: BTreeMap::new(),
+            ids: RandomState::new(),
+            next_id: 0,
+        }
+    }
+
+    fn route(&mut self, request: &mut Request) -> Result<Reply, ApiError> {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments: Vec<&str> = path.split('/').filter(|part| !part.is_empty()).collect();
+        let method = request.method().clone();
+
+        match (&method, segments.as_slice()) {
+            (Method::Get, ["healthz"]) => Ok(Reply::Text("ok\n".to_string(), TEXT)),
+            (Method::Get, ["metrics"]) => match &self.metrics {
+                Some(metrics) => Ok(Reply::Text(metrics.encode(), OPENMETRICS)),
+                None => Err(ApiError::not_found("metrics")),
+            },
+            (Method::Post, ["v1", "packs", "inspect"]) => {
+                let body: InspectRequest = read_json(request)?;
+                let (_, _, descriptor) = open_pack(&body.pack)?;
+                Ok(Reply::Json(200, serde_json::to_value(descriptor)?))
+            }
+            (Method::Post, ["v1", "sessions"]) => self.create_session(read_json(request)?),
+            (Method::Get, ["v1", "sessions", id]) => {
+                let session = self.session(id)?;
+                Ok(Reply::Json(200, session.view(id)?))
+            }
+            (Method::Delete, ["v1", "sessions", id]) => self
+                .sessions
+                .remove(*id)
+                .map(|_| Reply::Empty)
+                .ok_or_else(|| ApiError::not_found("session")),
+            (Method::Put, ["v1", "sessions", id, "answers"]) => {
+                let answers: Value = read_json(request)?;
+                if !answers.is_object() {
+                    return Err(ApiError::new(400, "answers must be a JSON object"));
+                }
+                let session = self.session_mut(id)?;
+                if session.applied.is_some() {
+                    return Err(ApiError::conflict("session is already applied"));
+                }
+                session.inputs.answers = answers;
+                session.plan = None;
+                session.approved = false;
+                Ok(Reply::Json(200, session.view(id)?))
+            }
+            (Method::Post, ["v1", "sessions", id, "plan"]) => self.plan(id),
+            (Method::Post, ["v1", "sessions", id, "approve"]) => {
+                let session = self.session_mut(id)?;
+                if session.plan.is_none() {
+                    return Err(ApiError::conflict("generate the plan before approving it"));
+                }
+                session.approved = true;
+                Ok(Reply::Json(200, session.view(id)?))
+            }
+            (Method::Post, ["v1", "sessions", id, "apply"]) => self.apply(id),
+            (Method::Get, ["v1", "installs"]) => {
+                let records = self.installs.list(&tenant_query(query))?;
+                Ok(Reply::Json(200, serde_json::to_value(records)?))
+            }
+            (Method::Delete, ["v1", "installs", provider_id, install_id]) => {
+                if self
+                    .installs
+                    .delete(&tenant_query(query), provider_id, install_id)?
+                {
+                    Ok(Reply::Empty)
+                } else {
+                    Err(ApiError::not_found("install"))
+                }
+            }
+            (
+                _,
+                ["healthz" | "metrics"]
+                | ["v1", "packs", "inspect"]
+                | ["v1", "sessions", ..]
+                | ["v1", "installs", ..],
+            ) => Err(ApiError::new(
+                405,
+                format!("{method} is not allowed on {path}"),
+            )),
+            _ => Err(ApiError::not_found(path)),
+        }
+    }
+
+    fn create_session(&mut self, body: CreateSession) -> Result<Reply, ApiError> {
+        let answers = body.answers.unwrap_or_else(|| json!({}));
+        if !answers.is_object() {
+            return Err(ApiError::new(400, "answers must be a JSON object"));
+        }
+        let (pack, manifest, descriptor) = open_pack(&body.pack)?;
+        let session = Session {
+            pack,
+            manifest,
+            descriptor,
+            executor: body.executor,
+            http_fixtures: body.http_fixtures,
+            inputs: ProvisionInputs {
+                tenant: body.tenant,
+                provider_id: body.provider_id,
+                install_id: body.install_id,
+                public_base_url: body.public_base_url,
+                answers,
+                existing_state: None,
+            },
+            plan: None,
+            approved: false,
+            applied: None,
+        };
+
+        let id = self.session_id();
+        let view = session.view(&id)?;
+        self.sessions.insert(id, session);
+        Ok(Reply::Json(201, view))
+    }
+
+    /// Runs the dry-run on first request and returns the cached result until the answers change.
+    fn plan(&mut self, id: &str) -> Result<Reply, ApiError> {
+        let metrics = self.metrics.clone();
+        let host = store_host(&self.config, self.secrets.as_ref());
+        let session = self.session_mut(id)?;
+        if session.plan.is_none() {
+            let executor = match dry_run_executor(
+                session.executor,
+                &session.pack,
+                &session.manifest,
+                session.http_fixtures.as_ref(),
+                host,
+            )? {
+                CliExecutor::Wasm(executor) => match &metrics {
+                    Some(metrics) => {
+                        CliExecutor::Wasm(Box::new(executor.with_metrics(metrics.clone())))
+                    }
+                    None => CliExecutor::Wasm(executor),
+                },
+                executor => executor,
+            };
+            let mut engine = ProvisionEngine::new(executor);
+            if let Some(metrics) = metrics {
+                engine = engine.with_observer(Arc::new(metrics));
+            }
+            session.plan = Some(engine.run(ProvisionMode::DryRun, session.inputs.clone()));
+        }
+        Ok(Reply::Json(
+            200,
+            session.redaction().to_value(&session.plan)?,
+        ))
+    }
+
+    fn apply(&mut self, id: &str) -> Result<Reply, ApiError> {
+        let session = self
+            .sessions
+            .get_mut(id)
+            .ok_or_else(|| ApiError::not_found("session"))?;
+        if session.applied.is_some() {
+            return Err(ApiError::conflict("session is already applied"));
+        }
+        let Some(plan) = session.plan.clone().filter(|_| session.approved) else {
+            return Err(ApiError::conflict("approve the plan before applying it"));
+        };
+
+        let secrets_patch = &plan.plan.secrets_patch;
+        let mut unkeyed = InMemorySecretsStore::default();
+        let secrets: &mut dyn SecretsStore = match &mut self.secrets {
+            Some(store) => store,
+            None if secrets_patch.set.is_empty() && secrets_patch.delete.is_empty() => &mut unkeyed,
+            None => {
+                return Err(ApiError::new(
+                    500,
+                    CliError::MissingSecretsKey(
+                        "--secrets-key-file or --secrets-passphrase-env to serve plans that set secrets",
+                    )
+                    .to_string(),
+                ));
+            }
+        };
+        let mut applier = ProvisionApplier::new(
+            session.inputs.clone(),
+            &mut self.config,
+            secrets,
+            NoopOAuthHandler,
+            &mut self.installs,
+        );
+        if let Some(metrics) = &self.metrics {
+            applier = applier.with_metrics(metrics.clone());
+        }
+        match applier.apply(plan, ApplyMode::Apply) {
+            Ok(report) => {
+                let body = session.redaction().to_value(&report)?;
+                session.applied = Some(report);
+                Ok(Reply::Json(200, body))
+            }
+            // The session stays approved so the client can retry once the store recovers. A
+            // reference that does not resolve is a problem with the answers, not the stores.
+            Err(failure) => Ok(Reply::Json(
+                if failure.phase == "resolve" {
+                    400
+                } else {
+                    store_status(failure.error.kind())
+                },
+                json!({
+                    "error": failure.to_string(),
+                    "phase": failure.phase,
+                    "operations": failure.operations,
+                    "reverted": failure.reverted,
+                }),
+            )),
+        }
+    }
+
+    fn session(&self, id: &str) -> Result<&Session, ApiError> {
+        self.sessions
+            .get(id)
+            .ok_or_else(|| ApiError::not_found("session"))
+    }
+
+    fn session_mut(&mut self, id: &str) -> Result<&mut Session, ApiError> {
+        self.sessions
+            .get_mut(id)
+            .ok_or_else(|| ApiError::not_found("session"))
+    }
+
+    fn session_id(&mut self) -> String {
+        self.next_id += 1;
+        let mut hasher = self.ids.build_hasher();
+        hasher.write_u64(self.next_id);
+        format!("{:016x}", hasher.finish())
+    }
+}
+
+fn open_pack(path: &PathBuf) -> Result<(PackContext, PackManifest, ProvisionDescriptor), ApiError> {
+    let pack = crate::resolve_pack_path(path)?;
+    let manifest = crate::load_manifest(&pack.root)?;
+    let descriptor =
+        DefaultProvisionPackDiscovery::discover(&manifest).ok_or(CliError::NoProvisioningEntry)?;
+    Ok((pack, manifest, descriptor))
+}
+
+/// Largest request body the API reads. Requests name packs by path, so bodies stay small.
+const MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+fn read_json<T: serde::de::DeserializeOwned>(request: &mut Request) -> Result<T, ApiError> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_BYTES + 1)
+        .read_to_string(&mut body)
+        .map_err(|err| ApiError::new(400, format!("failed to read request body: {err}")))?;
+    if body.len() as u64 > MAX_BODY_BYTES {
+        return Err(ApiError::new(
+            413,
+            format!("request body exceeds {MAX_BODY_BYTES} bytes"),
+        ));
+    }
+    Ok(serde_json::from_str(&body)?)
+}
+
+/// Reads `environment`, `tenant`, `team` and `user` query parameters.
+fn tenant_query(query: &str) -> TenantContext {
+    let mut tenant = TenantContext::default();
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        let slot = match key.as_ref() {
+            "environment" => &mut tenant.environment,
+            "tenant" => &mut tenant.tenant,
+            "team" => &mut tenant.team,
+            "user" => &mut tenant.user,
+            _ => continue,
+        };
+        *slot = Some(value.into_owned());
+    }
+    tenant
+}
+
+const TEXT: &str = "text/plain; charset=utf-8";
+const OPENMETRICS: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+fn response(reply: Reply) -> Response<Cursor<Vec<u8>>> {
+    let (status, body, content_type) = match reply {
+        Reply::Json(status, body) => (status, body.to_string(), "application/json"),
+        Reply::Text(body, content_type) => (200, body, content_type),
+        Reply::Empty => (204, String::new(), TEXT),
+    };
+    let header = Header::from_bytes("Content-Type", content_type).expect("static header is valid");
+    Response::from_string(body)
+        .with_status_code(StatusCode(status))
+        .with_header(header)
+}
@@ -1,9 +1,17 @@
 use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::metrics::ProvisionMetrics;
+use crate::oauth::AuthorizationRequest;
+use crate::secret::SecretString;
+use crate::secret_refs::{DefaultSecretResolver, ResolveContext, SecretRef, SecretResolver};
+use crate::telemetry;
 use crate::types::{
     OAuthOp, ProvisionInputs, ProvisionResult, RedactedValue, SubscriptionOp, TenantContext,
 };
@@ -22,8 +30,15 @@ pub struct ApplyReport {
     pub secret_set_keys: Vec<String>,
     pub secret_deleted_keys: Vec<String>,
     pub oauth_ops: Vec<OAuthOp>,
+    /// Authorize URLs from `OAuthOp::Start`, each waiting for an `OAuthOp::Finish` with its
+    /// `state`.
+    #[serde(default)]
+    pub pending_authorizations: Vec<AuthorizationRequest>,
     pub subscription_state: Vec<SubscriptionState>,
     pub install_record: ProviderInstallRecord,
+    /// Every write in the order it was made, or planned in dry-run mode.
+    #[serde(default)]
+    pub operations: Vec<OperationOutcome>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -44,16 +59,193 @@ pub struct SubscriptionState {
     pub last_sync: Option<String>,
 }
 
+/// Error returned by a config, secrets, install or OAuth backend.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ApplyError {
+    /// The backend failed or could not be reached.
+    #[error("store backend error: {0}")]
+    Backend(String),
+    /// The write lost a race with another writer or contradicts stored state. Raised by stores
+    /// that detect concurrent modification; io errors never map here.
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("permission denied: {0}")]
+    PermissionDenied(String),
+    /// The plan or an input it relies on is malformed, so retrying against the same stores
+    /// cannot succeed. Stores never raise it.
+    #[error("invalid: {0}")]
+    Invalid(String),
+}
+
+/// The variant of an [`ApplyError`], for reports and metrics.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ApplyErrorKind {
+    Backend,
+    Conflict,
+    NotFound,
+    PermissionDenied,
+    Invalid,
+}
+
+impl ApplyError {
+    pub fn kind(&self) -> ApplyErrorKind {
+        match self {
+            ApplyError::Backend(_) => ApplyErrorKind::Backend,
+            ApplyError::Conflict(_) => ApplyErrorKind::Conflict,
+            ApplyError::NotFound(_) => ApplyErrorKind::NotFound,
+            ApplyError::PermissionDenied(_) => ApplyErrorKind::PermissionDenied,
+            ApplyError::Invalid(_) => ApplyErrorKind::Invalid,
+        }
+    }
+}
+
+impl From<std::io::Error> for ApplyError {
+    fn from(err: std::io::Error) -> Self {
+        let message = err.to_string();
+        match err.kind() {
+            std::io::ErrorKind::NotFound => ApplyError::NotFound(message),
+            std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem => {
+                ApplyError::PermissionDenied(message)
+            }
+            _ => ApplyError::Backend(message),
+        }
+    }
+}
+
+/// What happened to one config key, secret or install record during an apply. Secret values are
+/// never included.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OperationOutcome {
+    pub target: WriteTarget,
+    pub op: WriteOp,
+    pub namespace: String,
+    pub key: String,
+    #[serde(flatten)]
+    pub status: OperationStatus,
+}
+
+impl OperationOutcome {
+    pub fn failed(&self) -> bool {
+        matches!(self.status, OperationStatus::Failed { .. })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteOp {
+    Set,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum OperationStatus {
+    /// Dry-run: the write would be made.
+    Planned,
+    Applied,
+    Failed {
+        kind: ApplyErrorKind,
+        error: String,
+    },
+}
+
+/// A failed [`ProvisionApplier::apply`]. Every write made before the failure was compensated;
+/// `reverted` lists them in the order they were undone.
+#[derive(Debug, thiserror::Error)]
+#[error("apply failed in {phase} phase: {error}")]
+pub struct ApplyFailure {
+    /// `config`, `secrets`, `install_record` or `oauth`.
+    pub phase: &'static str,
+    #[source]
+    pub error: ApplyError,
+    /// Every write attempted before the failure, ending with the one that failed.
+    pub operations: Vec<OperationOutcome>,
+    pub reverted: Vec<RevertedWrite>,
+}
+
+impl ApplyFailure {
+    /// Whether every compensation succeeded, leaving the stores as they were before the apply.
+    pub fn fully_reverted(&self) -> bool {
+        self.reverted.iter().all(|write| write.error.is_none())
+    }
+}
+
+/// One write undone after a failed apply. Secret values are never included.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RevertedWrite {
+    pub target: WriteTarget,
+    pub namespace: String,
+    pub key: String,
+    pub action: RevertAction,
+    /// Set when the compensation itself failed.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteTarget {
+    Config,
+    Secret,
+    InstallRecord,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RevertAction {
+    /// The previous value was written back.
+    Restored,
+    /// The entry did not exist before the apply and was removed.
+    Removed,
+}
+
 pub trait InstallStore {
+    /// `Ok(None)` means no such install; a failed read is an error, never a missing record.
     fn get(
         &self,
         tenant: &TenantContext,
         provider_id: &str,
         install_id: &str,
-    ) -> Option<ProviderInstallRecord>;
-    fn put(&mut self, record: ProviderInstallRecord);
-    fn list(&self, tenant: &TenantContext) -> Vec<ProviderInstallRecord>;
-    fn delete(&mut self, tenant: &TenantContext, provider_id: &str, install_id: &str) -> bool;
+    ) -> Result<Option<ProviderInstallRecord>, ApplyError>;
+    fn put(&mut self, record: ProviderInstallRecord) -> Result<(), ApplyError>;
+    fn list(&self, tenant: &TenantContext) -> Result<Vec<ProviderInstallRecord>, ApplyError>;
+    fn delete(
+        &mut self,
+        tenant: &TenantContext,
+        provider_id: &str,
+        install_id: &str,
+    ) -> Result<bool, ApplyError>;
+}
+
+/// Lets long-lived hosts lend their stores to a [`ProvisionApplier`] for one apply.
+impl<T: InstallStore + ?Sized> InstallStore for &mut T {
+    fn get(
+        &self,
+        tenant: &TenantContext,
+        provider_id: &str,
+        install_id: &str,
+    ) -> Result<Option<ProviderInstallRecord>, ApplyError> {
+        (**self).get(tenant, provider_id, install_id)
+    }
+
+    fn put(&mut self, record: ProviderInstallRecord) -> Result<(), ApplyError> {
+        (**self).put(record)
+    }
+
+    fn list(&self, tenant: &TenantContext) -> Result<Vec<ProviderInstallRecord>, ApplyError> {
+        (**self).list(tenant)
+    }
+
+    fn delete(
+        &mut self,
+        tenant: &TenantContext,
+        provider_id: &str,
+        install_id: &str,
+    ) -> Result<bool, ApplyError> {
+        (**self).delete(tenant, provider_id, install_id)
+    }
 }
 
 #[derive(Debug, Default)]
@@ -61,30 +253,50 @@ pub struct InMemoryInstallStore {
     records: Vec<ProviderInstallRecord>,
 }
 
+/// Install records in one JSON file, safe to share between processes.
+///
+/// Every write takes an exclusive advisory lock on a `<file>.lock` sidecar, re-reads the file,
+/// applies the change to the fresh records and replaces the file with an atomic rename. Writers
+/// never lose each other's updates, and readers never see a half-written file, so reads take no
+/// lock.
 #[derive(Debug)]
 pub struct FileInstallStore {
     path: PathBuf,
-    records: Vec<ProviderInstallRecord>,
 }
 
 impl FileInstallStore {
+    /// Opens the store at `path`, failing if an existing file cannot be read.
     pub fn new(path: impl Into<PathBuf>) -> Result<Self, std::io::Error> {
         let path = path.into();
-        let records = load_records(&path)?;
-        Ok(Self { path, records })
+        load_records(&path)?;
+        Ok(Self { path })
     }
 
     pub fn default_path() -> PathBuf {
         PathBuf::from(".greentic/provision/installs.json")
     }
 
-    fn persist(&self) -> Result<(), std::io::Error> {
-        if let Some(parent) = self.path.parent() {
-            std::fs::create_dir_all(parent)?;
+    /// The records on disk. A file that cannot be read or parsed is an error rather than an
+    /// older copy, so callers never act on stale records.
+    fn current(&self) -> Result<Vec<ProviderInstallRecord>, ApplyError> {
+        Ok(load_records(&self.path)?)
+    }
+
+    /// Applies `change` to the records on disk under the write lock and persists them if it
+    /// reports a change.
+    fn update(
+        &mut self,
+        change: impl FnOnce(&mut Vec<ProviderInstallRecord>) -> bool,
+    ) -> Result<bool, ApplyError> {
+        // Held until `_lock` is dropped at the end of this call.
+        let _lock = lock_for_write(&self.path)?;
+
+        let mut records = load_records(&self.path)?;
+        let changed = change(&mut records);
+        if changed {
+            write_atomic(&self.path, &records)?;
         }
-        let payload = serde_json::to_string_pretty(&self.records).map_err(std::io::Error::other)?;
-        std::fs::write(&self.path, payload)?;
-        Ok(())
+        Ok(changed)
     }
 }
 
@@ -94,45 +306,31 @@ impl InstallStore for InMemoryInstallStore {
         tenant: &TenantContext,
         provider_id: &str,
         install_id: &str,
-    ) -> Option<ProviderInstallRecord> {
-        self.records
-            .iter()
-            .find(|record| {
-                record.tenant == *tenant
-                    && record.provider_id == provider_id
-                    && record.install_id == install_id
-            })
-            .cloned()
+    ) -> Result<Option<ProviderInstallRecord>, ApplyError> {
+        Ok(find_record(&self.records, tenant, provider_id, install_id).cloned())
     }
 
-    fn put(&mut self, record: ProviderInstallRecord) {
-        if let Some(existing) = self.records.iter_mut().find(|item| {
-            item.tenant == record.tenant
-                && item.provider_id == record.provider_id
-                && item.install_id == record.install_id
-        }) {
-            *existing = record;
-        } else {
-            self.records.push(record);
-        }
+    fn put(&mut self, record: ProviderInstallRecord) -> Result<(), ApplyError> {
+        upsert_record(&mut self.records, record);
+        Ok(())
     }
 
-    fn list(&self, tenant: &TenantContext) -> Vec<ProviderInstallRecord> {
-        self.records
-            .iter()
-            .filter(|record| record.tenant == *tenant)
-            .cloned()
-            .collect()
+    fn list(&self, tenant: &TenantContext) -> Result<Vec<ProviderInstallRecord>, ApplyError> {
+        Ok(tenant_records(&self.records, tenant))
     }
 
-    fn delete(&mut self, tenant: &TenantContext, provider_id: &str, install_id: &str) -> bool {
-        let initial_len = self.records.len();
-        self.records.retain(|record| {
-            !(record.tenant == *tenant
-                && record.provider_id == provider_id
-                && record.install_id == install_id)
-        });
-        initial_len != self.records.len()
+    fn delete(
+        &mut self,
+        tenant: &TenantContext,
+        provider_id: &str,
+        install_id: &str,
+    ) -> Result<bool, ApplyError> {
+        Ok(remove_record(
+            &mut self.records,
+            tenant,
+            provider_id,
+            install_id,
+        ))
     }
 }
 
@@ -142,53 +340,83 @@ impl InstallStore for FileInstallStore {
         tenant: &TenantContext,
         provider_id: &str,
         install_id: &str,
-    ) -> Option<ProviderInstallRecord> {
-        self.records
-            .iter()
-            .find(|record| {
-                record.tenant == *tenant
-                    && record.provider_id == provider_id
-                    && record.install_id == install_id
-            })
-            .cloned()
+    ) -> Result<Option<ProviderInstallRecord>, ApplyError> {
+        Ok(find_record(&self.current()?, tenant, provider_id, install_id).cloned())
     }
 
-    fn put(&mut self, record: ProviderInstallRecord) {
-        if let Some(existing) = self.records.iter_mut().find(|item| {
-            item.tenant == record.tenant
-                && item.provider_id == record.provider_id
-                && item.install_id == record.install_id
-        }) {
-            *existing = record;
-        } else {
-            self.records.push(record);
-        }
-        let _ = self.persist();
+    fn put(&mut self, record: ProviderInstallRecord) -> Result<(), ApplyError> {
+        self.update(|records| {
+            upsert_record(records, record);
+            true
+        })?;
+        Ok(())
     }
 
-    fn list(&self, tenant: &TenantContext) -> Vec<ProviderInstallRecord> {
-        self.records
-            .iter()
-            .filter(|record| record.tenant == *tenant)
-            .cloned()
-            .collect()
+    fn list(&self, tenant: &TenantContext) -> Result<Vec<ProviderInstallRecord>, ApplyError> {
+        Ok(tenant_records(&self.current()?, tenant))
     }
 
-    fn delete(&mut self, tenant: &TenantContext, provider_id: &str, install_id: &str) -> bool {
-        let initial_len = self.records.len();
-        self.records.retain(|record| {
-            !(record.tenant == *tenant
-                && record.provider_id == provider_id
-                && record.install_id == install_id)
-        });
-        let removed = initial_len != self.records.len();
-        if removed {
-            let _ = self.persist();
-        }
-        removed
+    fn delete(
+        &mut self,
+        tenant: &TenantContext,
+        provider_id: &str,
+        install_id: &str,
+    ) -> Result<bool, ApplyError> {
+        self.update(|records| remove_record(records, tenant, provider_id, install_id))
     }
 }
 
+fn find_record<'a>(
+    records: &'a [ProviderInstallRecord],
+    tenant: &TenantContext,
+    provider_id: &str,
+    install_id: &str,
+) -> Option<&'a ProviderInstallRecord> {
+    records.iter().find(|record| {
+        record.tenant == *tenant
+            && record.provider_id == provider_id
+            && record.install_id == install_id
+    })
+}
+
+fn upsert_record(records: &mut Vec<ProviderInstallRecord>, record: ProviderInstallRecord) {
+    if let Some(existing) = records.iter_mut().find(|item| {
+        item.tenant == record.tenant
+            && item.provider_id == record.provider_id
+            && item.install_id == record.install_id
+    }) {
+        *existing = record;
+    } else {
+        records.push(record);
+    }
+}
+
+fn tenant_records(
+    records: &[ProviderInstallRecord],
+    tenant: &TenantContext,
+) -> Vec<ProviderInstallRecord> {
+    records
+        .iter()
+        .filter(|record| record.tenant == *tenant)
+        .cloned()
+        .collect()
+}
+
+fn remove_record(
+    records: &mut Vec<ProviderInstallRecord>,
+    tenant: &TenantContext,
+    provider_id: &str,
+    install_id: &str,
+) -> bool {
+    let initial_len = records.len();
+    records.retain(|record| {
+        !(record.tenant == *tenant
+            && record.provider_id == provider_id
+            && record.install_id == install_id)
+    });
+    initial_len != records.len()
+}
+
 fn load_records(path: &Path) -> Result<Vec<ProviderInstallRecord>, std::io::Error> {
     if !path.exists() {
         return Ok(Vec::new());
@@ -199,9 +427,86 @@ fn load_records(path: &Path) -> Result<Vec<ProviderInstallRecord>, std::io::Erro
     Ok(records)
 }
 
+/// Writes `value` to a sibling temp file, syncs it, renames it over `path` and syncs the
+/// directory, so a crash leaves either the old or the new contents and a completed write survives
+/// power loss.
+pub(crate) fn write_atomic(path: &Path, value: &impl Serialize) -> Result<(), std::io::Error> {
+    let payload = serde_json::to_vec_pretty(value).map_err(std::io::Error::other)?;
+    let temp = sidecar_path(path, "tmp");
+    let mut file = File::create(&temp)?;
+    file.write_all(&payload)?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)?;
+    sync_parent_dir(path)
+}
+
+/// Persists the directory entry of a file that was just renamed into place.
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<(), std::io::Error> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+/// Directories cannot be opened for syncing on this platform; the rename is as durable as the
+/// filesystem makes it.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<(), std::io::Error> {
+    Ok(())
+}
+
+/// Creates `path`'s directory and takes an exclusive lock on its `.lock` sidecar, released when
+/// the returned file is dropped.
+pub(crate) fn lock_for_write(path: &Path) -> Result<File, std::io::Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let lock = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(sidecar_path(path, "lock"))?;
+    lock.lock()?;
+    Ok(lock)
+}
+
+/// `installs.json` -> `installs.json.<suffix>`.
+pub(crate) fn sidecar_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
 pub trait ConfigStore {
-    fn apply_patch(&mut self, namespace: &str, patch: &BTreeMap<String, Value>) -> Vec<String>;
-    fn read_namespace(&self, namespace: &str) -> BTreeMap<String, Value>;
+    fn apply_patch(
+        &mut self,
+        namespace: &str,
+        patch: &BTreeMap<String, Value>,
+    ) -> Result<Vec<String>, ApplyError>;
+    fn remove_keys(&mut self, namespace: &str, keys: &[String]) -> Result<(), ApplyError>;
+    /// Reads every value in `namespace`; a namespace that does not exist is empty. A failed read
+    /// is an error, never an empty namespace, since the applier snapshots it for rollback.
+    fn read_namespace(&self, namespace: &str) -> Result<BTreeMap<String, Value>, ApplyError>;
+}
+
+impl<T: ConfigStore + ?Sized> ConfigStore for &mut T {
+    fn apply_patch(
+        &mut self,
+        namespace: &str,
+        patch: &BTreeMap<String, Value>,
+    ) -> Result<Vec<String>, ApplyError> {
+        (**self).apply_patch(namespace, patch)
+    }
+
+    fn remove_keys(&mut self, namespace: &str, keys: &[String]) -> Result<(), ApplyError> {
+        (**self).remove_keys(namespace, keys)
+    }
+
+    fn read_namespace(&self, namespace: &str) -> Result<BTreeMap<String, Value>, ApplyError> {
+        (**self).read_namespace(namespace)
+    }
 }
 
 pub struct ConfigApplier<C> {
@@ -220,7 +525,11 @@ where
         patch.keys().cloned().collect()
     }
 
-    pub fn apply(&mut self, namespace: &str, patch: &BTreeMap<String, Value>) -> Vec<String> {
+    pub fn apply(
+        &mut self,
+        namespace: &str,
+        patch: &BTreeMap<String, Value>,
+    ) -> Result<Vec<String>, ApplyError> {
         self.store.apply_patch(namespace, patch)
     }
 
@@ -235,42 +544,202 @@ pub struct InMemoryConfigStore {
 }
 
 impl ConfigStore for InMemoryConfigStore {
-    fn apply_patch(&mut self, namespace: &str, patch: &BTreeMap<String, Value>) -> Vec<String> {
+    fn apply_patch(
+        &mut self,
+        namespace: &str,
+        patch: &BTreeMap<String, Value>,
+    ) -> Result<Vec<String>, ApplyError> {
         let entry = self.namespaces.entry(namespace.to_string()).or_default();
         let mut changed = Vec::new();
         for (key, value) in patch {
             entry.insert(key.clone(), value.clone());
             changed.push(key.clone());
         }
-        changed
+        Ok(changed)
+    }
+
+    fn remove_keys(&mut self, namespace: &str, keys: &[String]) -> Result<(), ApplyError> {
+        if let Some(entry) = self.namespaces.get_mut(namespace) {
+            for key in keys {
+                entry.remove(key);
+            }
+        }
+        Ok(())
     }
 
-    fn read_namespace(&self, namespace: &str) -> BTreeMap<String, Value> {
-        self.namespaces.get(namespace).cloned().unwrap_or_default()
+    fn read_namespace(&self, namespace: &str) -> Result<BTreeMap<String, Value>, ApplyError> {
+        Ok(self.namespaces.get(namespace).cloned().unwrap_or_default())
     }
 }
 
+/// Persists each config namespace as its own JSON document in a directory, by default
+/// `.greentic/provision/config/`.
+///
+/// Writes follow [`FileInstallStore`]: lock the document's `.lock` sidecar, re-read it, apply the
+/// change and atomically replace it. A namespace left empty has its document removed.
+#[derive(Debug, Clone)]
+pub struct FileConfigStore {
+    dir: PathBuf,
+}
+
+impl FileConfigStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn default_dir() -> PathBuf {
+        PathBuf::from(".greentic/provision/config")
+    }
+
+    /// The document holding `namespace`.
+    ///
+    /// `provision:dev:acme:unknown:slack:main` -> `provision.dev.acme.unknown.slack.main.json`. Separators
+    /// become `.`, and any other byte outside `[A-Za-z0-9_-]` is percent-encoded, so distinct
+    /// namespaces never share a file.
+    pub fn namespace_path(&self, namespace: &str) -> PathBuf {
+        self.dir.join(namespace_file_name(namespace))
+    }
+
+    /// Applies `change` to the namespace's document under its write lock.
+    fn update(
+        &mut self,
+        namespace: &str,
+        change: impl FnOnce(&mut BTreeMap<String, Value>),
+    ) -> Result<(), ApplyError> {
+        let path = self.namespace_path(namespace);
+        let _lock = lock_for_write(&path)?;
+        let mut values = load_namespace(&path)?;
+        let before = values.clone();
+        change(&mut values);
+        if values == before {
+            return Ok(());
+        }
+        if values.is_empty() {
+            std::fs::remove_file(&path)?;
+        } else {
+            write_atomic(&path, &values)?;
+        }
+        Ok(())
+    }
+}
+
+impl ConfigStore for FileConfigStore {
+    fn apply_patch(
+        &mut self,
+        namespace: &str,
+        patch: &BTreeMap<String, Value>,
+    ) -> Result<Vec<String>, ApplyError> {
+        if patch.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.update(namespace, |values| {
+            values.extend(
+                patch
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone())),
+            );
+        })?;
+        Ok(patch.keys().cloned().collect())
+    }
+
+    fn remove_keys(&mut self, namespace: &str, keys: &[String]) -> Result<(), ApplyError> {
+        if keys.is_empty() || !self.namespace_path(namespace).exists() {
+            return Ok(());
+        }
+        self.update(namespace, |values| {
+            for key in keys {
+                values.remove(key);
+            }
+        })
+    }
+
+    fn read_namespace(&self, namespace: &str) -> Result<BTreeMap<String, Value>, ApplyError> {
+        Ok(load_namespace(&self.namespace_path(namespace))?)
+    }
+}
+
+pub(crate) fn namespace_file_name(namespace: &str) -> String {
+    let mut name = String::with_capacity(namespace.len() + 5);
+    for byte in namespace.bytes() {
+        match byte {
+            b':' => name.push('.'),
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'-' => name.push(byte as char),
+            _ => name.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    name.push_str(".json");
+    name
+}
+
+fn load_namespace(path: &Path) -> Result<BTreeMap<String, Value>, std::io::Error> {
+    load_document(path)
+}
+
+/// Reads a JSON document, or `T::default()` if it does not exist yet.
+pub(crate) fn load_document<T: DeserializeOwned + Default>(
+    path: &Path,
+) -> Result<T, std::io::Error> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(err) => return Err(err),
+    };
+    serde_json::from_str(&contents)
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
 pub trait SecretsStore {
-    fn set_secret(&mut self, namespace: &str, key: &str, value: &str);
-    fn delete_secret(&mut self, namespace: &str, key: &str);
+    fn set_secret(&mut self, namespace: &str, key: &str, value: &str) -> Result<(), ApplyError>;
+    fn delete_secret(&mut self, namespace: &str, key: &str) -> Result<(), ApplyError>;
+    /// Reads a value back so a failed apply can restore it. Hosts never expose it to packs.
+    /// `Ok(None)` means the secret is not set; a failed read is an error.
+    fn get_secret(&self, namespace: &str, key: &str) -> Result<Option<SecretString>, ApplyError>;
     fn list_keys(&self, namespace: &str) -> Vec<String>;
 }
 
+impl<T: SecretsStore + ?Sized> SecretsStore for &mut T {
+    fn set_secret(&mut self, namespace: &str, key: &str, value: &str) -> Result<(), ApplyError> {
+        (**self).set_secret(namespace, key, value)
+    }
+
+    fn delete_secret(&mut self, namespace: &str, key: &str) -> Result<(), ApplyError> {
+        (**self).delete_secret(namespace, key)
+    }
+
+    fn get_secret(&self, namespace: &str, key: &str) -> Result<Option<SecretString>, ApplyError> {
+        (**self).get_secret(namespace, key)
+    }
+
+    fn list_keys(&self, namespace: &str) -> Vec<String> {
+        (**self).list_keys(namespace)
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct InMemorySecretsStore {
-    namespaces: BTreeMap<String, BTreeMap<String, String>>,
+    namespaces: BTreeMap<String, BTreeMap<String, SecretString>>,
 }
 
 impl SecretsStore for InMemorySecretsStore {
-    fn set_secret(&mut self, namespace: &str, key: &str, value: &str) {
+    fn set_secret(&mut self, namespace: &str, key: &str, value: &str) -> Result<(), ApplyError> {
         let entry = self.namespaces.entry(namespace.to_string()).or_default();
-        entry.insert(key.to_string(), value.to_string());
+        entry.insert(key.to_string(), SecretString::new(value));
+        Ok(())
     }
 
-    fn delete_secret(&mut self, namespace: &str, key: &str) {
+    fn delete_secret(&mut self, namespace: &str, key: &str) -> Result<(), ApplyError> {
         if let Some(entry) = self.namespaces.get_mut(namespace) {
             entry.remove(key);
         }
+        Ok(())
+    }
+
+    fn get_secret(&self, namespace: &str, key: &str) -> Result<Option<SecretString>, ApplyError> {
+        Ok(self
+            .namespaces
+            .get(namespace)
+            .and_then(|entry| entry.get(key))
+            .cloned())
     }
 
     fn list_keys(&self, namespace: &str) -> Vec<String> {
@@ -281,22 +750,84 @@ impl SecretsStore for InMemorySecretsStore {
     }
 }
 
+/// Secret key the access token from a finished authorization is stored under.
+pub const OAUTH_ACCESS_TOKEN_KEY: &str = "oauth_access_token";
+/// Secret key the refresh token from a finished authorization is stored under.
+pub const OAUTH_REFRESH_TOKEN_KEY: &str = "oauth_refresh_token";
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct OAuthTokenSet {
-    pub access_token: String,
-    pub refresh_token: Option<String>,
+    pub access_token: SecretString,
+    pub refresh_token: Option<SecretString>,
 }
 
+/// Runs an OAuth authorization in two halves, so a setup can pause while the user consents.
 pub trait OAuthHandler {
-    fn start(&mut self, op: &OAuthOp) -> Option<OAuthTokenSet>;
+    /// Begins an authorization for the install whose secrets live in `secrets_namespace` and keeps
+    /// what [`finish`](Self::finish) needs under the returned `state`. `None` means the handler
+    /// does not run OAuth.
+    fn start(
+        &mut self,
+        secrets_namespace: &str,
+        provider: &str,
+        scopes: &[String],
+        redirect_url: Option<&str>,
+    ) -> Result<Option<AuthorizationRequest>, ApplyError>;
+
+    /// Exchanges the `code` the provider redirected back with for tokens. Each `state` can be
+    /// finished once, and only for the `secrets_namespace` it was started for.
+    fn finish(
+        &mut self,
+        secrets_namespace: &str,
+        state: &str,
+        code: &SecretString,
+    ) -> Result<OAuthTokenSet, ApplyError>;
+}
+
+impl<T: OAuthHandler + ?Sized> OAuthHandler for &mut T {
+    fn start(
+        &mut self,
+        secrets_namespace: &str,
+        provider: &str,
+        scopes: &[String],
+        redirect_url: Option<&str>,
+    ) -> Result<Option<AuthorizationRequest>, ApplyError> {
+        (**self).start(secrets_namespace, provider, scopes, redirect_url)
+    }
+
+    fn finish(
+        &mut self,
+        secrets_namespace: &str,
+        state: &str,
+        code: &SecretString,
+    ) -> Result<OAuthTokenSet, ApplyError> {
+        (**self).finish(secrets_namespace, state, code)
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct NoopOAuthHandler;
 
 impl OAuthHandler for NoopOAuthHandler {
-    fn start(&mut self, _op: &OAuthOp) -> Option<OAuthTokenSet> {
-        None
+    fn start(
+        &mut self,
+        _secrets_namespace: &str,
+        _provider: &str,
+        _scopes: &[String],
+        _redirect_url: Option<&str>,
+    ) -> Result<Option<AuthorizationRequest>, ApplyError> {
+        Ok(None)
+    }
+
+    fn finish(
+        &mut self,
+        _secrets_namespace: &str,
+        state: &str,
+        _code: &SecretString,
+    ) -> Result<OAuthTokenSet, ApplyError> {
+        Err(ApplyError::NotFound(format!(
+            "no pending oauth authorization for state {state}"
+        )))
     }
 }
 
@@ -306,6 +837,8 @@ pub struct ProvisionApplier<C, S, O, I> {
     secrets_store: S,
     oauth_handler: O,
     install_store: I,
+    metrics: Option<ProvisionMetrics>,
+    secret_resolver: Box<dyn SecretResolver>,
 }
 
 impl<C, S, O, I> ProvisionApplier<C, S, O, I>
@@ -328,43 +861,212 @@ where
             secrets_store,
             oauth_handler,
             install_store,
+            metrics: None,
+            secret_resolver: Box::new(DefaultSecretResolver::new()),
         }
     }
 
-    pub fn apply(&mut self, result: ProvisionResult, mode: ApplyMode) -> ApplyReport {
+    /// Counts applies and secret writes per provider.
+    pub fn with_metrics(mut self, metrics: ProvisionMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Replaces the [`DefaultSecretResolver`] used for `ref` entries in the secrets patch.
+    pub fn with_secret_resolver(mut self, resolver: impl SecretResolver + 'static) -> Self {
+        self.secret_resolver = Box::new(resolver);
+        self
+    }
+
+    /// Resolves secret references and OAuth codes, then runs the config, secrets, install record
+    /// and OAuth phases in order. References are only resolved in [`ApplyMode::Apply`], and a
+    /// reference that cannot be resolved fails the apply before anything is written.
+    ///
+    /// An `OAuthOp::Start` adds its authorize URL to `pending_authorizations`. An
+    /// `OAuthOp::Finish` stores the exchanged tokens as `oauth_access_token` and
+    /// `oauth_refresh_token` in the install's secrets namespace.
+    ///
+    /// In [`ApplyMode::Apply`] every write is journaled with the value it replaced. If a later
+    /// write fails, the journal is undone in reverse order and the returned [`ApplyFailure`]
+    /// lists each reverted write. Both outcomes carry one [`OperationOutcome`] per write.
+    pub fn apply(
+        &mut self,
+        result: ProvisionResult,
+        mode: ApplyMode,
+    ) -> Result<ApplyReport, ApplyFailure> {
+        self.transact(|applier, tx| applier.run_phases(&result, mode, tx))
+    }
+
+    /// Finishes an authorization an earlier apply started, as the `OAuthOp::Finish` of a plan
+    /// would, without touching config or the install record. The install must exist, and its
+    /// secrets namespace is where the tokens go and what the handler checks the `state` against.
+    /// Token writes are journaled and rolled back like those of [`apply`](Self::apply).
+    pub fn finish_authorization(
+        &mut self,
+        state: &str,
+        code: &SecretString,
+    ) -> Result<ApplyReport, ApplyFailure> {
+        self.transact(|applier, tx| applier.run_finish(state, code, tx))
+    }
+
+    /// Runs `phases` against a fresh journal, rolling back and recording metrics on failure.
+    fn transact(
+        &mut self,
+        phases: impl FnOnce(&mut Self, &mut Transaction) -> PhaseResult<ApplyReport>,
+    ) -> Result<ApplyReport, ApplyFailure> {
+        let mut tx = Transaction::default();
+        match phases(self, &mut tx) {
+            Ok(mut report) => {
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_apply(&self.inputs.provider_id, true, tx.secret_writes());
+                }
+                report.operations = tx.operations;
+                Ok(report)
+            }
+            Err((phase, error)) => {
+                let reverted = {
+                    let _span = telemetry::apply_phase(&self.inputs, "rollback").entered();
+                    self.rollback(tx.journal)
+                };
+                if let Some(metrics) = &self.metrics {
+                    metrics.record_apply(&self.inputs.provider_id, false, 0);
+                    let failed_secrets = tx
+                        .operations
+                        .iter()
+                        .filter(|op| op.target == WriteTarget::Secret && op.failed())
+                        .count();
+                    metrics.record_secret_failures(&self.inputs.provider_id, failed_secrets);
+                }
+                Err(ApplyFailure {
+                    phase,
+                    error,
+                    operations: tx.operations,
+                    reverted,
+                })
+            }
+        }
+    }
+
+    fn run_phases(
+        &mut self,
+        result: &ProvisionResult,
+        mode: ApplyMode,
+        tx: &mut Transaction,
+    ) -> PhaseResult<ApplyReport> {
         let namespace = provision_namespace(
             &self.inputs.tenant,
             &self.inputs.provider_id,
             &self.inputs.install_id,
         );
-        let secrets_namespace = format!("{}:secrets", namespace);
-
-        let (config_changes, secret_set_keys, secret_deleted_keys) = if mode == ApplyMode::Apply {
-            let config_changes = self
-                .config_store
-                .apply_patch(&namespace, &result.plan.config_patch);
-            let mut secret_set_keys = Vec::new();
-            let mut secret_deleted_keys = Vec::new();
-            for (key, value) in &result.plan.secrets_patch.set {
-                if let Some(secret_value) = redacted_to_value(value) {
-                    self.secrets_store
-                        .set_secret(&secrets_namespace, key, &secret_value);
-                    secret_set_keys.push(key.clone());
+        let secrets_namespace = secrets_namespace(&namespace);
+
+        let (resolved, oauth_codes) = if mode == ApplyMode::Apply {
+            let _span = telemetry::apply_phase(&self.inputs, "resolve").entered();
+            let resolved = self
+                .resolve_references(&result.plan.secrets_patch.set)
+                .map_err(|err| ("resolve", err))?;
+            let oauth_codes = self
+                .resolve_oauth_codes(&result.plan.oauth_ops)
+                .map_err(|err| ("resolve", err))?;
+            (resolved, oauth_codes)
+        } else {
+            (BTreeMap::new(), BTreeMap::new())
+        };
+
+        let config_changes = {
+            let _span = telemetry::apply_phase(&self.inputs, "config").entered();
+            let patch = &result.plan.config_patch;
+            if mode == ApplyMode::Apply {
+                let previous = self
+                    .config_store
+                    .read_namespace(&namespace)
+                    .map_err(|err| ("config", err))?;
+                tx.journal.extend(patch.keys().map(|key| Undo::Config {
+                    namespace: namespace.clone(),
+                    key: key.clone(),
+                    previous: previous.get(key).cloned(),
+                }));
+                let outcome = self.config_store.apply_patch(&namespace, patch);
+                for key in patch.keys() {
+                    tx.record(WriteTarget::Config, WriteOp::Set, &namespace, key, &outcome);
+                }
+                outcome.map_err(|err| ("config", err))?
+            } else {
+                for key in patch.keys() {
+                    tx.planned(WriteTarget::Config, WriteOp::Set, &namespace, key);
                 }
+                patch.keys().cloned().collect()
             }
-            for key in &result.plan.secrets_patch.delete {
-                self.secrets_store.delete_secret(&secrets_namespace, key);
-                secret_deleted_keys.push(key.clone());
+        };
+
+        let (secret_set_keys, secret_deleted_keys) = {
+            let _span = telemetry::apply_phase(&self.inputs, "secrets").entered();
+            if mode == ApplyMode::Apply {
+                let mut secret_set_keys = Vec::new();
+                let mut secret_deleted_keys = Vec::new();
+                for (key, value) in &result.plan.secrets_patch.set {
+                    let secret_value = match resolved.get(key) {
+                        Some(resolved) => Some(resolved.clone()),
+                        None => redacted_to_value(value),
+                    };
+                    if let Some(secret_value) = secret_value {
+                        self.write_secret(
+                            tx,
+                            &secrets_namespace,
+                            key,
+                            secret_value.expose_secret(),
+                        )
+                        .map_err(|err| ("secrets", err))?;
+                        secret_set_keys.push(key.clone());
+                    }
+                }
+                for key in &result.plan.secrets_patch.delete {
+                    if let Some(previous) = self
+                        .secrets_store
+                        .get_secret(&secrets_namespace, key)
+                        .map_err(|err| ("secrets", err))?
+                    {
+                        tx.journal.push(Undo::Secret {
+                            namespace: secrets_namespace.clone(),
+                            key: key.clone(),
+                            previous: Some(previous),
+                        });
+                    }
+                    let outcome = self.secrets_store.delete_secret(&secrets_namespace, key);
+                    tx.record(
+                        WriteTarget::Secret,
+                        WriteOp::Delete,
+                        &secrets_namespace,
+                        key,
+                        &outcome,
+                    );
+                    outcome.map_err(|err| ("secrets", err))?;
+                    secret_deleted_keys.push(key.clone());
+                }
+                (secret_set_keys, secret_deleted_keys)
+            } else {
+                let secret_set_keys: Vec<String> =
+                    result.plan.secrets_patch.set.keys().cloned().collect();
+                let secret_deleted_keys = result.plan.secrets_patch.delete.clone();
+                for key in &secret_set_keys {
+                    tx.planned(WriteTarget::Secret, WriteOp::Set, &secrets_namespace, key);
+                }
+                for key in &secret_deleted_keys {
+                    tx.planned(
+                        WriteTarget::Secret,
+                        WriteOp::Delete,
+                        &secrets_namespace,
+                        key,
+                    );
+                }
+                (secret_set_keys, secret_deleted_keys)
             }
-            (config_changes, secret_set_keys, secret_deleted_keys)
-        } else {
-            let config_changes = result.plan.config_patch.keys().cloned().collect();
-            let secret_set_keys = result.plan.secrets_patch.set.keys().cloned().collect();
-            let secret_deleted_keys = result.plan.secrets_patch.delete.clone();
-            (config_changes, secret_set_keys, secret_deleted_keys)
         };
 
-        let subscription_state = apply_subscription_ops(&result.plan.subscription_ops);
+        let subscription_state = {
+            let _span = telemetry::apply_phase(&self.inputs, "subscriptions").entered();
+            apply_subscription_ops(&result.plan.subscription_ops)
+        };
         let install_record = ProviderInstallRecord {
             tenant: self.inputs.tenant.clone(),
             provider_id: self.inputs.provider_id.clone(),
@@ -375,39 +1077,325 @@ where
         };
 
         if mode == ApplyMode::Apply {
-            self.install_store.put(install_record.clone());
-        }
-
-        let mut oauth_ops = Vec::new();
-        for op in &result.plan.oauth_ops {
-            oauth_ops.push(op.clone());
-            if mode == ApplyMode::Apply
-                && let Some(token_set) = self.oauth_handler.start(op)
-            {
-                self.secrets_store.set_secret(
-                    &secrets_namespace,
-                    "oauth_access_token",
-                    &token_set.access_token,
-                );
-                if let Some(refresh) = token_set.refresh_token {
-                    self.secrets_store.set_secret(
-                        &secrets_namespace,
-                        "oauth_refresh_token",
-                        &refresh,
-                    );
+            let _span = telemetry::apply_phase(&self.inputs, "install_record").entered();
+            tx.journal.push(Undo::Install {
+                namespace: namespace.clone(),
+                previous: self
+                    .install_store
+                    .get(
+                        &self.inputs.tenant,
+                        &self.inputs.provider_id,
+                        &self.inputs.install_id,
+                    )
+                    .map_err(|err| ("install_record", err))?,
+            });
+            let outcome = self.install_store.put(install_record.clone());
+            tx.record(
+                WriteTarget::InstallRecord,
+                WriteOp::Set,
+                &namespace,
+                &self.inputs.install_id,
+                &outcome,
+            );
+            outcome.map_err(|err| ("install_record", err))?;
+        } else {
+            tx.planned(
+                WriteTarget::InstallRecord,
+                WriteOp::Set,
+                &namespace,
+                &self.inputs.install_id,
+            );
+        }
+
+        let (oauth_ops, pending_authorizations) = {
+            let _span = telemetry::apply_phase(&self.inputs, "oauth").entered();
+            let mut oauth_ops = Vec::new();
+            let mut pending_authorizations = Vec::new();
+            for op in &result.plan.oauth_ops {
+                oauth_ops.push(op.clone());
+                if mode != ApplyMode::Apply {
+                    continue;
                 }
+                let token_set = match op {
+                    OAuthOp::Start {
+                        provider,
+                        scopes,
+                        redirect_url,
+                    } => {
+                        let request = self
+                            .oauth_handler
+                            .start(
+                                &secrets_namespace,
+                                provider,
+                                scopes,
+                                redirect_url.as_deref(),
+                            )
+                            .map_err(|err| ("oauth", err))?;
+                        pending_authorizations.extend(request);
+                        continue;
+                    }
+                    OAuthOp::Finish { state, .. } => self
+                        .oauth_handler
+                        .finish(&secrets_namespace, state, &oauth_codes[state])
+                        .map_err(|err| ("oauth", err))?,
+                };
+                self.store_tokens(tx, &secrets_namespace, token_set)
+                    .map_err(|err| ("oauth", err))?;
             }
-        }
+            (oauth_ops, pending_authorizations)
+        };
 
-        ApplyReport {
+        Ok(ApplyReport {
             mode,
             config_changes,
             secret_set_keys,
             secret_deleted_keys,
             oauth_ops,
+            pending_authorizations,
             subscription_state,
             install_record,
+            operations: Vec::new(),
+        })
+    }
+
+    fn resolve_references(
+        &self,
+        set: &BTreeMap<String, RedactedValue>,
+    ) -> Result<BTreeMap<String, SecretString>, ApplyError> {
+        let ctx = ResolveContext {
+            inputs: &self.inputs,
+            secrets: &self.secrets_store,
+        };
+        let mut resolved = BTreeMap::new();
+        for (key, value) in set {
+            let Some(reference) = &value.reference else {
+                // A serialized plan drops plaintext values, so a redacted entry without a
+                // reference has lost its value and would silently skip the secret.
+                if redacted_to_value(value).is_none() {
+                    return Err(ApplyError::Invalid(format!(
+                        "secret {key} is redacted and carries neither a value nor a ref"
+                    )));
+                }
+                continue;
+            };
+            let reference = SecretRef::parse(reference)
+                .map_err(|err| ApplyError::Invalid(format!("secret {key}: {err}")))?;
+            resolved.insert(key.clone(), self.secret_resolver.resolve(&reference, &ctx)?);
+        }
+        Ok(resolved)
+    }
+
+    /// The `code` of every `OAuthOp::Finish`, by `state`, read from the op or its reference.
+    fn resolve_oauth_codes(
+        &self,
+        ops: &[OAuthOp],
+    ) -> Result<BTreeMap<String, SecretString>, ApplyError> {
+        let ctx = ResolveContext {
+            inputs: &self.inputs,
+            secrets: &self.secrets_store,
+        };
+        let mut codes = BTreeMap::new();
+        for op in ops {
+            let OAuthOp::Finish { state, code } = op else {
+                continue;
+            };
+            let code = match (&code.value, &code.reference) {
+                (Some(value), _) => value.clone(),
+                (None, Some(reference)) => {
+                    let reference = SecretRef::parse(reference).map_err(|err| {
+                        ApplyError::Invalid(format!("oauth code for state {state}: {err}"))
+                    })?;
+                    self.secret_resolver.resolve(&reference, &ctx)?
+                }
+                (None, None) => {
+                    return Err(ApplyError::Invalid(format!(
+                        "oauth finish for state {state} carries no code"
+                    )));
+                }
+            };
+            codes.insert(state.clone(), code);
+        }
+        Ok(codes)
+    }
+
+    fn run_finish(
+        &mut self,
+        state: &str,
+        code: &SecretString,
+        tx: &mut Transaction,
+    ) -> PhaseResult<ApplyReport> {
+        let install_record = self
+            .install_store
+            .get(
+                &self.inputs.tenant,
+                &self.inputs.provider_id,
+                &self.inputs.install_id,
+            )
+            .map_err(|err| ("install_record", err))?
+            .ok_or_else(|| {
+                (
+                    "install_record",
+                    ApplyError::NotFound(format!(
+                        "no install {} for provider {}",
+                        self.inputs.install_id, self.inputs.provider_id
+                    )),
+                )
+            })?;
+
+        let _span = telemetry::apply_phase(&self.inputs, "oauth").entered();
+        let namespace = install_record.secrets_namespace.clone();
+        let token_set = self
+            .oauth_handler
+            .finish(&namespace, state, code)
+            .map_err(|err| ("oauth", err))?;
+        let secret_set_keys = self
+            .store_tokens(tx, &namespace, token_set)
+            .map_err(|err| ("oauth", err))?;
+
+        Ok(ApplyReport {
+            mode: ApplyMode::Apply,
+            config_changes: Vec::new(),
+            secret_set_keys,
+            secret_deleted_keys: Vec::new(),
+            oauth_ops: vec![OAuthOp::Finish {
+                state: state.to_string(),
+                code: RedactedValue::redacted(),
+            }],
+            pending_authorizations: Vec::new(),
+            subscription_state: install_record.subscriptions.clone(),
+            install_record,
+            operations: Vec::new(),
+        })
+    }
+
+    /// Writes the tokens of a finished authorization and returns the keys written.
+    fn store_tokens(
+        &mut self,
+        tx: &mut Transaction,
+        namespace: &str,
+        token_set: OAuthTokenSet,
+    ) -> Result<Vec<String>, ApplyError> {
+        let mut stored = vec![OAUTH_ACCESS_TOKEN_KEY.to_string()];
+        self.write_secret(
+            tx,
+            namespace,
+            OAUTH_ACCESS_TOKEN_KEY,
+            token_set.access_token.expose_secret(),
+        )?;
+        if let Some(refresh) = token_set.refresh_token {
+            self.write_secret(
+                tx,
+                namespace,
+                OAUTH_REFRESH_TOKEN_KEY,
+                refresh.expose_secret(),
+            )?;
+            stored.push(OAUTH_REFRESH_TOKEN_KEY.to_string());
         }
+        Ok(stored)
+    }
+
+    fn write_secret(
+        &mut self,
+        tx: &mut Transaction,
+        namespace: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), ApplyError> {
+        let previous = self.secrets_store.get_secret(namespace, key)?;
+        tx.journal.push(Undo::Secret {
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+            previous,
+        });
+        let outcome = self.secrets_store.set_secret(namespace, key, value);
+        tx.record(WriteTarget::Secret, WriteOp::Set, namespace, key, &outcome);
+        outcome
+    }
+
+    /// Undoes journaled writes newest first. A failed compensation is recorded and the rest still
+    /// run.
+    fn rollback(&mut self, journal: Vec<Undo>) -> Vec<RevertedWrite> {
+        journal
+            .into_iter()
+            .rev()
+            .map(|undo| {
+                let (target, namespace, key, action, outcome) = match undo {
+                    Undo::Config {
+                        namespace,
+                        key,
+                        previous,
+                    } => {
+                        let (action, outcome) = match previous {
+                            Some(value) => (
+                                RevertAction::Restored,
+                                self.config_store
+                                    .apply_patch(
+                                        &namespace,
+                                        &BTreeMap::from([(key.clone(), value)]),
+                                    )
+                                    .map(|_| ()),
+                            ),
+                            None => (
+                                RevertAction::Removed,
+                                self.config_store
+                                    .remove_keys(&namespace, std::slice::from_ref(&key)),
+                            ),
+                        };
+                        (WriteTarget::Config, namespace, key, action, outcome)
+                    }
+                    Undo::Secret {
+                        namespace,
+                        key,
+                        previous,
+                    } => {
+                        let (action, outcome) = match previous {
+                            Some(value) => (
+                                RevertAction::Restored,
+                                self.secrets_store.set_secret(
+                                    &namespace,
+                                    &key,
+                                    value.expose_secret(),
+                                ),
+                            ),
+                            None => (
+                                RevertAction::Removed,
+                                self.secrets_store.delete_secret(&namespace, &key),
+                            ),
+                        };
+                        (WriteTarget::Secret, namespace, key, action, outcome)
+                    }
+                    Undo::Install {
+                        namespace,
+                        previous,
+                    } => {
+                        let (action, outcome) = match previous {
+                            Some(record) => {
+                                (RevertAction::Restored, self.install_store.put(record))
+                            }
+                            None => (
+                                RevertAction::Removed,
+                                self.install_store
+                                    .delete(
+                                        &self.inputs.tenant,
+                                        &self.inputs.provider_id,
+                                        &self.inputs.install_id,
+                                    )
+                                    .map(|_| ()),
+                            ),
+                        };
+                        let key = self.inputs.install_id.clone();
+                        (WriteTarget::InstallRecord, namespace, key, action, outcome)
+                    }
+                };
+                RevertedWrite {
+                    target,
+                    namespace,
+                    key,
+                    action,
+                    error: outcome.err().map(|err| err.to_string()),
+                }
+            })
+            .collect()
     }
 
     pub fn into_parts(self) -> (C, S, O, I) {
@@ -420,7 +1408,82 @@ where
     }
 }
 
-fn redacted_to_value(value: &RedactedValue) -> Option<String> {
+type PhaseResult<T> = Result<T, (&'static str, ApplyError)>;
+
+/// The undo journal and operation log of one apply.
+#[derive(Default)]
+struct Transaction {
+    journal: Vec<Undo>,
+    operations: Vec<OperationOutcome>,
+}
+
+impl Transaction {
+    fn planned(&mut self, target: WriteTarget, op: WriteOp, namespace: &str, key: &str) {
+        self.push(target, op, namespace, key, OperationStatus::Planned);
+    }
+
+    fn record<T>(
+        &mut self,
+        target: WriteTarget,
+        op: WriteOp,
+        namespace: &str,
+        key: &str,
+        outcome: &Result<T, ApplyError>,
+    ) {
+        let status = match outcome {
+            Ok(_) => OperationStatus::Applied,
+            Err(err) => OperationStatus::Failed {
+                kind: err.kind(),
+                error: err.to_string(),
+            },
+        };
+        self.push(target, op, namespace, key, status);
+    }
+
+    fn push(
+        &mut self,
+        target: WriteTarget,
+        op: WriteOp,
+        namespace: &str,
+        key: &str,
+        status: OperationStatus,
+    ) {
+        self.operations.push(OperationOutcome {
+            target,
+            op,
+            namespace: namespace.to_string(),
+            key: key.to_string(),
+            status,
+        });
+    }
+
+    fn secret_writes(&self) -> usize {
+        self.operations
+            .iter()
+            .filter(|op| op.target == WriteTarget::Secret && op.status == OperationStatus::Applied)
+            .count()
+    }
+}
+
+/// A journaled write and the value it replaced.
+enum Undo {
+    Config {
+        namespace: String,
+        key: String,
+        previous: Option<Value>,
+    },
+    Secret {
+        namespace: String,
+        key: String,
+        previous: Option<SecretString>,
+    },
+    Install {
+        namespace: String,
+        previous: Option<ProviderInstallRecord>,
+    },
+}
+
+fn redacted_to_value(value: &RedactedValue) -> Option<SecretString> {
     if value.redacted {
         return None;
     }
@@ -453,7 +1516,11 @@ fn apply_subscription_ops(ops: &[SubscriptionOp]) -> Vec<SubscriptionState> {
         .collect()
 }
 
-fn provision_namespace(tenant: &TenantContext, provider_id: &str, install_id: &str) -> String {
+pub(crate) fn provision_namespace(
+    tenant: &TenantContext,
+    provider_id: &str,
+    install_id: &str,
+) -> String {
     let env = tenant.environment.as_deref().unwrap_or("unknown");
     let tenant_id = tenant.tenant.as_deref().unwrap_or("unknown");
     let team = tenant.team.as_deref().unwrap_or("unknown");
@@ -463,11 +1530,34 @@ fn provision_namespace(tenant: &TenantContext, provider_id: &str, install_id: &s
     )
 }
 
+pub(crate) fn secrets_namespace(config_namespace: &str) -> String {
+    format!("{}:secrets", config_namespace)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::types::{ProvisionPlan, RedactedValue, SecretsPatch};
 
+    fn inputs() -> ProvisionInputs {
+        ProvisionInputs {
+            tenant: TenantContext::default(),
+            provider_id: "provider".to_string(),
+            install_id: "install".to_string(),
+            public_base_url: None,
+            answers: Value::Null,
+            existing_state: None,
+        }
+    }
+
+    fn result(plan: ProvisionPlan) -> ProvisionResult {
+        ProvisionResult {
+            plan,
+            diagnostics: Vec::new(),
+            step_results: None,
+        }
+    }
+
     #[test]
     fn apply_updates_config_namespace() {
         let inputs = ProvisionInputs {
@@ -477,22 +1567,14 @@ mod tests {
                 team: Some("team-a".to_string()),
                 user: None,
             },
-            provider_id: "provider".to_string(),
-            install_id: "install".to_string(),
-            public_base_url: None,
-            answers: Value::Null,
-            existing_state: None,
+            ..inputs()
         };
 
         let mut plan = ProvisionPlan::default();
         plan.config_patch
             .insert("foo".to_string(), Value::String("bar".to_string()));
 
-        let result = ProvisionResult {
-            plan,
-            diagnostics: Vec::new(),
-            step_results: None,
-        };
+        let result = result(plan);
 
         let mut applier = ProvisionApplier::new(
             inputs,
@@ -502,22 +1584,19 @@ mod tests {
             InMemoryInstallStore::default(),
         );
 
-        let report = applier.apply(result, ApplyMode::Apply);
+        let report = applier
+            .apply(result, ApplyMode::Apply)
+            .expect("apply succeeds");
         let (config_store, _secrets, _oauth, _installs) = applier.into_parts();
-        let config = config_store.read_namespace(&report.install_record.config_namespace);
+        let config = config_store
+            .read_namespace(&report.install_record.config_namespace)
+            .expect("read config");
         assert_eq!(config.get("foo"), Some(&Value::String("bar".to_string())));
     }
 
     #[test]
     fn apply_secrets_patch_does_not_expose_values() {
-        let inputs = ProvisionInputs {
-            tenant: TenantContext::default(),
-            provider_id: "provider".to_string(),
-            install_id: "install".to_string(),
-            public_base_url: None,
-            answers: Value::Null,
-            existing_state: None,
-        };
+        let inputs = inputs();
 
         let mut plan = ProvisionPlan::default();
         let mut secrets_patch = SecretsPatch::default();
@@ -527,11 +1606,7 @@ mod tests {
         secrets_patch.delete.push("old".to_string());
         plan.secrets_patch = secrets_patch;
 
-        let result = ProvisionResult {
-            plan,
-            diagnostics: Vec::new(),
-            step_results: None,
-        };
+        let result = result(plan);
 
         let mut applier = ProvisionApplier::new(
             inputs,
@@ -541,27 +1616,18 @@ mod tests {
             InMemoryInstallStore::default(),
         );
 
-        let report = applier.apply(result, ApplyMode::DryRun);
+        let report = applier
+            .apply(result, ApplyMode::DryRun)
+            .expect("dry-run apply succeeds");
         assert_eq!(report.secret_set_keys, vec!["secret".to_string()]);
         assert_eq!(report.secret_deleted_keys, vec!["old".to_string()]);
     }
 
     #[test]
     fn install_record_persisted_and_retrievable() {
-        let inputs = ProvisionInputs {
-            tenant: TenantContext::default(),
-            provider_id: "provider".to_string(),
-            install_id: "install".to_string(),
-            public_base_url: None,
-            answers: Value::Null,
-            existing_state: None,
-        };
+        let inputs = inputs();
 
-        let result = ProvisionResult {
-            plan: ProvisionPlan::default(),
-            diagnostics: Vec::new(),
-            step_results: None,
-        };
+        let result = result(ProvisionPlan::default());
 
         let mut applier = ProvisionApplier::new(
             inputs.clone(),
@@ -571,11 +1637,405 @@ mod tests {
             InMemoryInstallStore::default(),
         );
 
-        let report = applier.apply(result, ApplyMode::Apply);
+        let report = applier
+            .apply(result, ApplyMode::Apply)
+            .expect("apply succeeds");
         let (_config, _secrets, _oauth, store) = applier.into_parts();
         let stored = store
             .get(&inputs.tenant, &inputs.provider_id, &inputs.install_id)
+            .expect("read record")
             .expect("missing record");
         assert_eq!(stored, report.install_record);
     }
+
+    /// Rejects every put; deletes succeed so rollback can clean up.
+    #[derive(Default)]
+    struct RejectingInstallStore;
+
+    impl InstallStore for RejectingInstallStore {
+        fn get(
+            &self,
+            _: &TenantContext,
+            _: &str,
+            _: &str,
+        ) -> Result<Option<ProviderInstallRecord>, ApplyError> {
+            Ok(None)
+        }
+
+        fn put(&mut self, _record: ProviderInstallRecord) -> Result<(), ApplyError> {
+            Err(ApplyError::Backend("disk full".to_string()))
+        }
+
+        fn list(&self, _: &TenantContext) -> Result<Vec<ProviderInstallRecord>, ApplyError> {
+            Ok(Vec::new())
+        }
+
+        fn delete(&mut self, _: &TenantContext, _: &str, _: &str) -> Result<bool, ApplyError> {
+            Ok(false)
+        }
+    }
+
+    /// Accepts the first `remaining` secret writes, then fails every write and delete.
+    struct ExhaustingSecretsStore {
+        inner: InMemorySecretsStore,
+        remaining: usize,
+    }
+
+    impl SecretsStore for ExhaustingSecretsStore {
+        fn set_secret(
+            &mut self,
+            namespace: &str,
+            key: &str,
+            value: &str,
+        ) -> Result<(), ApplyError> {
+            if self.remaining == 0 {
+                return Err(ApplyError::Backend("quota exceeded".to_string()));
+            }
+            self.remaining -= 1;
+            self.inner.set_secret(namespace, key, value)
+        }
+
+        fn delete_secret(&mut self, _namespace: &str, _key: &str) -> Result<(), ApplyError> {
+            Err(ApplyError::Backend("quota exceeded".to_string()))
+        }
+
+        fn get_secret(
+            &self,
+            namespace: &str,
+            key: &str,
+        ) -> Result<Option<SecretString>, ApplyError> {
+            self.inner.get_secret(namespace, key)
+        }
+
+        fn list_keys(&self, namespace: &str) -> Vec<String> {
+            self.inner.list_keys(namespace)
+        }
+    }
+
+    fn rollback_plan() -> ProvisionResult {
+        let mut plan = ProvisionPlan::default();
+        plan.config_patch
+            .insert("existing".to_string(), Value::String("new".to_string()));
+        plan.config_patch
+            .insert("added".to_string(), Value::Bool(true));
+        plan.secrets_patch
+            .set
+            .insert("token".to_string(), RedactedValue::plaintext("rotated"));
+        plan.secrets_patch
+            .set
+            .insert("webhook".to_string(), RedactedValue::plaintext("fresh"));
+        result(plan)
+    }
+
+    #[test]
+    fn failed_install_record_rolls_back_config_and_secrets() {
+        let inputs = inputs();
+        let namespace = provision_namespace(&inputs.tenant, "provider", "install");
+        let secrets_ns = secrets_namespace(&namespace);
+        let mut config = InMemoryConfigStore::default();
+        config
+            .apply_patch(
+                &namespace,
+                &BTreeMap::from([("existing".to_string(), Value::String("old".to_string()))]),
+            )
+            .expect("seed config");
+        let mut secrets = InMemorySecretsStore::default();
+        secrets
+            .set_secret(&secrets_ns, "token", "original")
+            .expect("seed secret");
+
+        let mut applier = ProvisionApplier::new(
+            inputs,
+            config,
+            secrets,
+            NoopOAuthHandler,
+            RejectingInstallStore,
+        );
+        let failure = applier
+            .apply(rollback_plan(), ApplyMode::Apply)
+            .expect_err("install record write fails");
+        assert_eq!(failure.phase, "install_record");
+        assert_eq!(failure.error, ApplyError::Backend("disk full".to_string()));
+        assert!(failure.fully_reverted());
+        let failed = failure.operations.last().expect("failed operation");
+        assert_eq!(failed.target, WriteTarget::InstallRecord);
+        assert_eq!(
+            failed.status,
+            OperationStatus::Failed {
+                kind: ApplyErrorKind::Backend,
+                error: "store backend error: disk full".to_string(),
+            }
+        );
+        assert_eq!(
+            failure.operations.iter().filter(|op| op.failed()).count(),
+            1
+        );
+        let reverted: Vec<_> = failure
+            .reverted
+            .iter()
+            .map(|write| (write.target, write.key.as_str(), write.action))
+            .collect();
+        assert_eq!(
+            reverted,
+            [
+                (WriteTarget::InstallRecord, "install", RevertAction::Removed),
+                (WriteTarget::Secret, "webhook", RevertAction::Removed),
+                (WriteTarget::Secret, "token", RevertAction::Restored),
+                (WriteTarget::Config, "existing", RevertAction::Restored),
+                (WriteTarget::Config, "added", RevertAction::Removed),
+            ]
+        );
+
+        let (config, secrets, _oauth, _installs) = applier.into_parts();
+        assert_eq!(
+            config.read_namespace(&namespace).expect("read config"),
+            BTreeMap::from([("existing".to_string(), Value::String("old".to_string()))])
+        );
+        assert_eq!(
+            secrets
+                .get_secret(&secrets_ns, "token")
+                .expect("read secret")
+                .as_ref()
+                .map(SecretString::expose_secret),
+            Some("original")
+        );
+        assert_eq!(secrets.get_secret(&secrets_ns, "webhook"), Ok(None));
+    }
+
+    #[test]
+    fn failed_compensation_is_reported() {
+        let mut applier = ProvisionApplier::new(
+            inputs(),
+            InMemoryConfigStore::default(),
+            ExhaustingSecretsStore {
+                inner: InMemorySecretsStore::default(),
+                remaining: 1,
+            },
+            NoopOAuthHandler,
+            InMemoryInstallStore::default(),
+        );
+        let failure = applier
+            .apply(rollback_plan(), ApplyMode::Apply)
+            .expect_err("second secret write fails");
+        assert_eq!(failure.phase, "secrets");
+        assert!(failure.to_string().contains("quota exceeded"));
+        assert!(!failure.fully_reverted());
+        // The rejected write and the accepted one both need a delete, which the store refuses.
+        let failed: Vec<_> = failure
+            .reverted
+            .iter()
+            .filter(|write| write.error.is_some())
+            .map(|write| write.key.as_str())
+            .collect();
+        assert_eq!(failed, ["webhook", "token"]);
+        let (config, _secrets, _oauth, installs) = applier.into_parts();
+        assert!(
+            config
+                .read_namespace("provision:unknown:unknown:unknown:provider:install")
+                .expect("read config")
+                .is_empty()
+        );
+        assert!(
+            installs
+                .list(&TenantContext::default())
+                .expect("list")
+                .is_empty()
+        );
+    }
+
+    /// Holds secrets it cannot read back, like a remote store that is unreachable for reads.
+    struct UnreadableSecretsStore {
+        inner: InMemorySecretsStore,
+    }
+
+    impl SecretsStore for UnreadableSecretsStore {
+        fn set_secret(
+            &mut self,
+            namespace: &str,
+            key: &str,
+            value: &str,
+        ) -> Result<(), ApplyError> {
+            self.inner.set_secret(namespace, key, value)
+        }
+
+        fn delete_secret(&mut self, namespace: &str, key: &str) -> Result<(), ApplyError> {
+            self.inner.delete_secret(namespace, key)
+        }
+
+        fn get_secret(&self, _: &str, _: &str) -> Result<Option<SecretString>, ApplyError> {
+            Err(ApplyError::Backend("read timed out".to_string()))
+        }
+
+        fn list_keys(&self, namespace: &str) -> Vec<String> {
+            self.inner.list_keys(namespace)
+        }
+    }
+
+    #[test]
+    fn unreadable_previous_values_fail_before_overwriting() {
+        let inputs = inputs();
+        let namespace = provision_namespace(&inputs.tenant, "provider", "install");
+        let secrets_ns = secrets_namespace(&namespace);
+        let mut inner = InMemorySecretsStore::default();
+        inner
+            .set_secret(&secrets_ns, "token", "original")
+            .expect("seed secret");
+
+        let mut applier = ProvisionApplier::new(
+            inputs,
+            InMemoryConfigStore::default(),
+            UnreadableSecretsStore { inner },
+            NoopOAuthHandler,
+            InMemoryInstallStore::default(),
+        );
+        let failure = applier
+            .apply(rollback_plan(), ApplyMode::Apply)
+            .expect_err("previous secret cannot be read");
+        assert_eq!(failure.phase, "secrets");
+        assert_eq!(
+            failure.error,
+            ApplyError::Backend("read timed out".to_string())
+        );
+        assert!(failure.fully_reverted());
+        assert!(
+            failure
+                .reverted
+                .iter()
+                .all(|write| write.target == WriteTarget::Config)
+        );
+
+        let (_config, secrets, _oauth, _installs) = applier.into_parts();
+        assert_eq!(
+            secrets
+                .inner
+                .get_secret(&secrets_ns, "token")
+                .expect("read secret")
+                .as_ref()
+                .map(SecretString::expose_secret),
+            Some("original")
+        );
+    }
+
+    #[test]
+    fn report_lists_operation_outcomes() {
+        let mut applier = ProvisionApplier::new(
+            inputs(),
+            InMemoryConfigStore::default(),
+            InMemorySecretsStore::default(),
+            NoopOAuthHandler,
+            InMemoryInstallStore::default(),
+        );
+        let planned = applier
+            .apply(rollback_plan(), ApplyMode::DryRun)
+            .expect("dry-run apply succeeds");
+        let applied = applier
+            .apply(rollback_plan(), ApplyMode::Apply)
+            .expect("apply succeeds");
+
+        let summary = |report: &ApplyReport| -> Vec<_> {
+            report
+                .operations
+                .iter()
+                .map(|op| (op.target, op.key.clone(), op.status.clone()))
+                .collect()
+        };
+        let expected = |status: OperationStatus| {
+            vec![
+                (WriteTarget::Config, "added".to_string(), status.clone()),
+                (WriteTarget::Config, "existing".to_string(), status.clone()),
+                (WriteTarget::Secret, "token".to_string(), status.clone()),
+                (WriteTarget::Secret, "webhook".to_string(), status.clone()),
+                (WriteTarget::InstallRecord, "install".to_string(), status),
+            ]
+        };
+        assert_eq!(summary(&planned), expected(OperationStatus::Planned));
+        assert_eq!(summary(&applied), expected(OperationStatus::Applied));
+
+        let json = serde_json::to_value(&applied.operations[2]).expect("operation json");
+        assert_eq!(json["status"], "applied");
+        assert_eq!(json["op"], "set");
+        assert!(!json.to_string().contains("rotated"));
+    }
+
+    #[test]
+    fn oauth_finish_codes_resolve_before_any_write() {
+        let mut result = rollback_plan();
+        result.plan.oauth_ops.push(OAuthOp::Finish {
+            state: "state".to_string(),
+            code: RedactedValue::reference("answers://oauth_code"),
+        });
+        let mut applier = ProvisionApplier::new(
+            inputs(),
+            InMemoryConfigStore::default(),
+            InMemorySecretsStore::default(),
+            NoopOAuthHandler,
+            InMemoryInstallStore::default(),
+        );
+        let failure = applier
+            .apply(result.clone(), ApplyMode::Apply)
+            .expect_err("code is not in the answers");
+        assert_eq!(failure.phase, "resolve");
+        assert!(matches!(failure.error, ApplyError::NotFound(_)));
+        assert!(failure.operations.is_empty());
+
+        let planned = applier
+            .apply(result, ApplyMode::DryRun)
+            .expect("dry-runs never resolve codes");
+        assert_eq!(planned.oauth_ops.len(), 1);
+        assert!(planned.pending_authorizations.is_empty());
+    }
+
+    #[test]
+    fn io_errors_map_to_apply_error_kinds() {
+        use std::io::{Error, ErrorKind};
+
+        let kind = |kind: ErrorKind| ApplyError::from(Error::from(kind)).kind();
+        assert_eq!(kind(ErrorKind::NotFound), ApplyErrorKind::NotFound);
+        assert_eq!(
+            kind(ErrorKind::PermissionDenied),
+            ApplyErrorKind::PermissionDenied
+        );
+        assert_eq!(kind(ErrorKind::AlreadyExists), ApplyErrorKind::Backend);
+        assert_eq!(kind(ErrorKind::StorageFull), ApplyErrorKind::Backend);
+    }
+
+    #[test]
+    fn file_install_store_put_reports_persist_errors() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let blocker = dir.path().join("blocker");
+        std::fs::write(&blocker, "not a directory").expect("blocker file");
+        let mut store = FileInstallStore::new(blocker.join("installs.json")).expect("store");
+
+        let record = ProviderInstallRecord {
+            tenant: TenantContext::default(),
+            provider_id: "provider".to_string(),
+            install_id: "install".to_string(),
+            config_namespace: "config".to_string(),
+            secrets_namespace: "secrets".to_string(),
+            subscriptions: Vec::new(),
+        };
+        let err = store.put(record).expect_err("parent is a file");
+        assert_eq!(err.kind(), ApplyErrorKind::Backend);
+    }
+
+    #[test]
+    fn secret_values_never_show_in_debug_output() {
+        let mut store = InMemorySecretsStore::default();
+        store.set_secret("ns", "token", "hunter2").expect("set");
+        let tokens = OAuthTokenSet {
+            access_token: SecretString::new("access-hunter2"),
+            refresh_token: Some(SecretString::new("refresh-hunter2")),
+        };
+        for debug in [format!("{store:?}"), format!("{tokens:?}")] {
+            assert!(!debug.contains("hunter2"), "{debug}");
+        }
+        assert_eq!(
+            store
+                .get_secret("ns", "token")
+                .expect("read secret")
+                .as_ref()
+                .map(SecretString::expose_secret),
+            Some("hunter2")
+        );
+    }
 }
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+use crate::types::StepLayout;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct PackManifest {
     #[serde(alias = "pack_id", alias = "packId")]
@@ -20,6 +22,20 @@ pub struct PackMeta {
     pub requires_public_base_url: bool,
     #[serde(default)]
     pub capabilities: Vec<String>,
+    /// Hosts the `http` capability may reach when applying with a real transport.
+    #[serde(default)]
+    pub http_hosts: Vec<String>,
+    /// Whether the pack's components were built for WASI and expect `wasi_snapshot_preview1`.
+    #[serde(default)]
+    pub wasi: bool,
+    /// Component layout of the setup flow; when omitted the executor tries per-step components
+    /// first and falls back to a single `setup_default` component.
+    #[serde(default)]
+    pub step_layout: Option<StepLayout>,
+    /// JSON Schema of the setup answers. Fields marked `"secret": true` or
+    /// `"format": "password"` are redacted from everything the CLI prints or writes.
+    #[serde(default)]
+    pub answers_schema: Option<serde_json::Value>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
@@ -57,6 +73,7 @@ pub struct ProvisionDescriptor {
     pub subscriptions_flow: Option<String>,
     pub requires_public_base_url: bool,
     pub outputs: Vec<String>,
+    pub step_layout: Option<StepLayout>,
 }
 
 pub trait ProvisionPackDiscovery {
@@ -79,6 +96,7 @@ impl ProvisionPackDiscovery for DefaultProvisionPackDiscovery {
             subscriptions_flow,
             requires_public_base_url: pack.meta.requires_public_base_url,
             outputs: pack.meta.capabilities.clone(),
+            step_layout: pack.meta.step_layout,
         })
     }
 }
@@ -153,6 +171,22 @@ mod tests {
         assert_eq!(descriptor.pack_id, "pack-1");
     }
 
+    #[test]
+    fn discover_reports_declared_step_layout() {
+        let manifest = manifest_from_value(serde_json::json!({
+            "id": "pack-3",
+            "version": "1.0.0",
+            "meta": {
+                "entry_flows": { "setup": "setup_default" },
+                "step_layout": "single_component"
+            }
+        }));
+
+        let descriptor =
+            DefaultProvisionPackDiscovery::discover(&manifest).expect("missing descriptor");
+        assert_eq!(descriptor.step_layout, Some(StepLayout::SingleComponent));
+    }
+
     #[test]
     fn discover_returns_none_without_setup() {
         let manifest = manifest_from_value(serde_json::json!({
@@ -0,0 +1,441 @@
+//! A file-backed [`SecretsStore`] that keeps values encrypted at rest.
+//!
+//! Each secrets namespace is one JSON document, named like [`FileConfigStore`] documents. Every
+//! value is sealed with XChaCha20-Poly1305 under a 256-bit [`SecretsKey`] and a random nonce, and
+//! the namespace and key are bound in as associated data, so a sealed value cannot be moved to
+//! another entry. Keys and namespace names stay readable; only values are encrypted.
+//!
+//! Documents record the id of the key that sealed them. [`EncryptedFileSecretsStore::rotate`]
+//! re-encrypts every document under a new key, and a store opened with
+//! [`EncryptedFileSecretsStore::with_previous_key`] can still read documents an interrupted
+//! rotation did not reach.
+//!
+//! [`FileConfigStore`]: crate::apply::FileConfigStore
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use argon2::Argon2;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+use crate::apply::{
+    ApplyError, SecretsStore, load_document, lock_for_write, namespace_file_name, sidecar_path,
+    write_atomic,
+};
+use crate::secret::SecretString;
+
+const DOCUMENT_VERSION: u32 = 1;
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const PASSPHRASE_SALT_FILE: &str = "passphrase.salt";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecretsKeyError {
+    #[error("failed to read or write key material: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("invalid key: {0}")]
+    Invalid(String),
+    #[error("key derivation failed: {0}")]
+    Derivation(String),
+}
+
+/// A 256-bit key for [`EncryptedFileSecretsStore`]. The bytes are wiped on drop and never printed.
+#[derive(Clone)]
+pub struct SecretsKey {
+    bytes: Zeroizing<[u8; KEY_LEN]>,
+}
+
+impl SecretsKey {
+    pub fn generate() -> Self {
+        let mut bytes = Zeroizing::new([0u8; KEY_LEN]);
+        OsRng.fill_bytes(bytes.as_mut());
+        Self { bytes }
+    }
+
+    pub fn from_bytes(bytes: [u8; KEY_LEN]) -> Self {
+        Self {
+            bytes: Zeroizing::new(bytes),
+        }
+    }
+
+    /// Derives a key from a passphrase with Argon2id. `salt` must be at least 8 bytes.
+    pub fn from_passphrase(passphrase: &str, salt: &[u8]) -> Result<Self, SecretsKeyError> {
+        let mut bytes = Zeroizing::new([0u8; KEY_LEN]);
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, bytes.as_mut())
+            .map_err(|err| SecretsKeyError::Derivation(err.to_string()))?;
+        Ok(Self { bytes })
+    }
+
+    /// Reads a key file written by [`SecretsKey::save`]: the key as base64 on one line.
+    pub fn load(path: &Path) -> Result<Self, SecretsKeyError> {
+        let contents = Zeroizing::new(std::fs::read_to_string(path)?);
+        let decoded = Zeroizing::new(
+            BASE64
+                .decode(contents.trim())
+                .map_err(|err| SecretsKeyError::Invalid(format!("{}: {err}", path.display())))?,
+        );
+        let bytes: [u8; KEY_LEN] = decoded.as_slice().try_into().map_err(|_| {
+            SecretsKeyError::Invalid(format!(
+                "{}: expected {KEY_LEN} bytes, found {}",
+                path.display(),
+                decoded.len()
+            ))
+        })?;
+        Ok(Self::from_bytes(bytes))
+    }
+
+    /// Writes the key as base64, readable only by the current user on Unix.
+    pub fn save(&self, path: &Path) -> Result<(), SecretsKeyError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let encoded = Zeroizing::new(format!("{}\n", BASE64.encode(self.bytes.as_ref())));
+        write_private(path, encoded.as_bytes())?;
+        Ok(())
+    }
+
+    /// A short, stable fingerprint recorded in every document this key seals.
+    pub fn id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(b"greentic-provision secrets key id\0");
+        hasher.update(self.bytes.as_ref());
+        hasher.finalize()[..8]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(self.bytes.as_ref().into())
+    }
+}
+
+impl fmt::Debug for SecretsKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretsKey")
+            .field("id", &self.id())
+            .finish()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SecretsDocument {
+    version: u32,
+    namespace: String,
+    key_id: String,
+    entries: BTreeMap<String, SealedValue>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SealedValue {
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Persists secrets namespaces as encrypted JSON documents, by default under
+/// `.greentic/provision/secrets/`.
+///
+/// Writes lock, re-read and atomically replace a document like the other file stores.
+#[derive(Debug, Clone)]
+pub struct EncryptedFileSecretsStore {
+    dir: PathBuf,
+    key: SecretsKey,
+    previous: Vec<SecretsKey>,
+}
+
+impl EncryptedFileSecretsStore {
+    pub fn new(dir: impl Into<PathBuf>, key: SecretsKey) -> Self {
+        Self {
+            dir: dir.into(),
+            key,
+            previous: Vec::new(),
+        }
+    }
+
+    pub fn default_dir() -> PathBuf {
+        PathBuf::from(".greentic/provision/secrets")
+    }
+
+    /// Also accepts documents sealed with `key`. They are re-encrypted with the current key the
+    /// next time they are written or rotated.
+    pub fn with_previous_key(mut self, key: SecretsKey) -> Self {
+        self.previous.push(key);
+        self
+    }
+
+    /// Derives the key for `passphrase` using the salt stored in `dir`, creating the salt on
+    /// first use. The salt never changes, so rotating to a new passphrase only changes the key.
+    pub fn passphrase_key(dir: &Path, passphrase: &str) -> Result<SecretsKey, SecretsKeyError> {
+        let path = dir.join(PASSPHRASE_SALT_FILE);
+        let salt = match std::fs::read(&path) {
+            Ok(salt) => salt,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                std::fs::create_dir_all(dir)?;
+                let mut salt = vec![0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                // Publish the salt with a hard link so concurrent first uses agree on one salt
+                // and never see a partly written file.
+                let temp = sidecar_path(&path, &format!("{:016x}.tmp", OsRng.next_u64()));
+                std::fs::write(&temp, &salt)?;
+                let linked = std::fs::hard_link(&temp, &path);
+                std::fs::remove_file(&temp)?;
+                match linked {
+                    Ok(()) => salt,
+                    Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                        std::fs::read(&path)?
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Err(err) => return Err(err.into()),
+        };
+        SecretsKey::from_passphrase(passphrase, &salt)
+    }
+
+    pub fn key_id(&self) -> String {
+        self.key.id()
+    }
+
+    pub fn namespace_path(&self, namespace: &str) -> PathBuf {
+        self.dir.join(namespace_file_name(namespace))
+    }
+
+    /// Re-encrypts every namespace under `new_key` and makes it the current key, returning how
+    /// many documents were rewritten.
+    ///
+    /// The old key stays usable as a previous key, so a rotation interrupted part way can be
+    /// finished by calling this again.
+    pub fn rotate(&mut self, new_key: SecretsKey) -> Result<usize, ApplyError> {
+        let new_id = new_key.id();
+        let mut rewritten = 0;
+        for path in self.documents()? {
+            let _lock = lock_for_write(&path)?;
+            let mut document: SecretsDocument = load_document(&path)?;
+            if document.entries.is_empty() || document.key_id == new_id {
+                continue;
+            }
+            let values = self.open_all(&document)?;
+            document.entries = seal_all(&new_key, &document.namespace, &values)?;
+            document.key_id = new_id.clone();
+            write_atomic(&path, &document)?;
+            rewritten += 1;
+        }
+        let old = std::mem::replace(&mut self.key, new_key);
+        self.previous.retain(|key| key.id() != new_id);
+        self.previous.push(old);
+        Ok(rewritten)
+    }
+
+    /// Paths of every namespace document in the store directory.
+    fn documents(&self) -> Result<Vec<PathBuf>, ApplyError> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn key_for(&self, key_id: &str) -> Option<&SecretsKey> {
+        std::iter::once(&self.key)
+            .chain(&self.previous)
+            .find(|key| key.id() == key_id)
+    }
+
+    fn open_all(
+        &self,
+        document: &SecretsDocument,
+    ) -> Result<BTreeMap<String, SecretString>, ApplyError> {
+        let key = self.key_for(&document.key_id).ok_or_else(|| {
+            ApplyError::PermissionDenied(format!(
+                "secrets namespace {} is encrypted with unknown key {}",
+                document.namespace, document.key_id
+            ))
+        })?;
+        document
+            .entries
+            .iter()
+            .map(|(name, sealed)| {
+                let value = open(key, &document.namespace, name, sealed)?;
+                Ok((name.clone(), value))
+            })
+            .collect()
+    }
+
+    /// Applies `change` to the namespace's document under its write lock, moving it to the
+    /// current key first if needed.
+    fn update(
+        &mut self,
+        namespace: &str,
+        change: impl FnOnce(&SecretsKey, &mut BTreeMap<String, SealedValue>) -> Result<(), ApplyError>,
+    ) -> Result<(), ApplyError> {
+        let path = self.namespace_path(namespace);
+        let _lock = lock_for_write(&path)?;
+        let mut document: SecretsDocument = load_document(&path)?;
+        let current = self.key.id();
+        if document.entries.is_empty() {
+            document = SecretsDocument {
+                version: DOCUMENT_VERSION,
+                namespace: namespace.to_string(),
+                key_id: current,
+                entries: BTreeMap::new(),
+            };
+        } else if document.key_id != current {
+            let values = self.open_all(&document)?;
+            document.entries = seal_all(&self.key, namespace, &values)?;
+            document.key_id = current;
+        }
+        change(&self.key, &mut document.entries)?;
+        if document.entries.is_empty() {
+            match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        } else {
+            write_atomic(&path, &document)?;
+        }
+        Ok(())
+    }
+}
+
+impl SecretsStore for EncryptedFileSecretsStore {
+    fn set_secret(&mut self, namespace: &str, key: &str, value: &str) -> Result<(), ApplyError> {
+        self.update(namespace, |secrets_key, entries| {
+            entries.insert(key.to_string(), seal(secrets_key, namespace, key, value)?);
+            Ok(())
+        })
+    }
+
+    fn delete_secret(&mut self, namespace: &str, key: &str) -> Result<(), ApplyError> {
+        if !self.namespace_path(namespace).exists() {
+            return Ok(());
+        }
+        self.update(namespace, |_, entries| {
+            entries.remove(key);
+            Ok(())
+        })
+    }
+
+    fn get_secret(&self, namespace: &str, key: &str) -> Result<Option<SecretString>, ApplyError> {
+        let document: SecretsDocument = load_document(&self.namespace_path(namespace))?;
+        let Some(sealed) = document.entries.get(key) else {
+            return Ok(None);
+        };
+        let secrets_key = self.key_for(&document.key_id).ok_or_else(|| {
+            ApplyError::PermissionDenied(format!(
+                "secrets namespace {namespace} is encrypted with unknown key {}",
+                document.key_id
+            ))
+        })?;
+        open(secrets_key, namespace, key, sealed).map(Some)
+    }
+
+    fn list_keys(&self, namespace: &str) -> Vec<String> {
+        load_document::<SecretsDocument>(&self.namespace_path(namespace))
+            .map(|document| document.entries.into_keys().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Binds a sealed value to its namespace and key.
+fn associated_data(namespace: &str, key: &str) -> Vec<u8> {
+    format!("greentic-provision:v{DOCUMENT_VERSION}\0{namespace}\0{key}").into_bytes()
+}
+
+fn seal(
+    secrets_key: &SecretsKey,
+    namespace: &str,
+    key: &str,
+    value: &str,
+) -> Result<SealedValue, ApplyError> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let aad = associated_data(namespace, key);
+    let ciphertext = secrets_key
+        .cipher()
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: value.as_bytes(),
+                aad: &aad,
+            },
+        )
+        .map_err(|_| ApplyError::Backend(format!("failed to encrypt secret {namespace}/{key}")))?;
+    Ok(SealedValue {
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    })
+}
+
+fn seal_all(
+    secrets_key: &SecretsKey,
+    namespace: &str,
+    values: &BTreeMap<String, SecretString>,
+) -> Result<BTreeMap<String, SealedValue>, ApplyError> {
+    values
+        .iter()
+        .map(|(key, value)| {
+            let sealed = seal(secrets_key, namespace, key, value.expose_secret())?;
+            Ok((key.clone(), sealed))
+        })
+        .collect()
+}
+
+fn open(
+    secrets_key: &SecretsKey,
+    namespace: &str,
+    key: &str,
+    sealed: &SealedValue,
+) -> Result<SecretString, ApplyError> {
+    let corrupt = || ApplyError::Backend(format!("secret {namespace}/{key} cannot be decrypted"));
+    let nonce = BASE64.decode(&sealed.nonce).map_err(|_| corrupt())?;
+    if nonce.len() != 24 {
+        return Err(corrupt());
+    }
+    let ciphertext = BASE64.decode(&sealed.ciphertext).map_err(|_| corrupt())?;
+    let aad = associated_data(namespace, key);
+    let plaintext = Zeroizing::new(
+        secrets_key
+            .cipher()
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: &aad,
+                },
+            )
+            .map_err(|_| corrupt())?,
+    );
+    let value = std::str::from_utf8(&plaintext).map_err(|_| corrupt())?;
+    Ok(SecretString::new(value))
+}
+
+/// Like `write_atomic`, but the file is created with owner-only permissions on Unix.
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), std::io::Error> {
+    let temp = sidecar_path(path, "tmp");
+    let mut options = std::fs::File::options();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&temp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&temp, path)
+}
@@ -1,8 +1,20 @@
 use std::fs::File;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
+use greentic_types::validate::{Diagnostic, Severity};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
+use crate::observer::{
+    DiagnosticEmitted, PlanMerged, ProvisionObserver, RunFinished, RunStarted, StepFinished,
+    StepStarted,
+};
+use crate::telemetry;
 use crate::types::{
     ProvisionInputs, ProvisionMode, ProvisionPlan, ProvisionResult, ProvisionStep, StepOutput,
     StepResult,
@@ -18,6 +30,21 @@ pub struct ProvisionContext {
 
 pub trait ProvisionExecutor {
     fn run_step(&self, step: ProvisionStep, ctx: &ProvisionContext) -> StepOutput;
+
+    /// Steps the executor can run at once, when it is bounded. `run_many` never starts more
+    /// workers than this.
+    fn max_concurrency(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Executor for hosts running on an async runtime. Dropping the returned future cancels the step.
+pub trait AsyncProvisionExecutor: Send + Sync {
+    fn run_step_async(
+        &self,
+        step: ProvisionStep,
+        ctx: &ProvisionContext,
+    ) -> impl Future<Output = StepOutput> + Send;
 }
 
 #[derive(Debug, Default)]
@@ -29,45 +56,52 @@ impl ProvisionExecutor for NoopExecutor {
     }
 }
 
-pub struct ProvisionEngine<E: ProvisionExecutor> {
+impl AsyncProvisionExecutor for NoopExecutor {
+    async fn run_step_async(&self, _step: ProvisionStep, _ctx: &ProvisionContext) -> StepOutput {
+        StepOutput::default()
+    }
+}
+
+const STEPS: [ProvisionStep; 4] = [
+    ProvisionStep::Collect,
+    ProvisionStep::Validate,
+    ProvisionStep::Apply,
+    ProvisionStep::Summary,
+];
+
+pub struct ProvisionEngine<E> {
     executor: E,
+    observers: Vec<Arc<dyn ProvisionObserver>>,
 }
 
-impl<E: ProvisionExecutor> ProvisionEngine<E> {
+impl<E> ProvisionEngine<E> {
     pub fn new(executor: E) -> Self {
-        Self { executor }
+        Self {
+            executor,
+            observers: Vec::new(),
+        }
     }
 
-    pub fn run(&self, mode: ProvisionMode, inputs: ProvisionInputs) -> ProvisionResult {
-        let mut step_results = Vec::new();
-        let mut plan = ProvisionPlan::default();
-        let mut diagnostics = Vec::new();
+    /// Notifies `observer` of progress in every run; observers are called in the order added.
+    pub fn with_observer(mut self, observer: Arc<dyn ProvisionObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+}
 
-        for step in [
-            ProvisionStep::Collect,
-            ProvisionStep::Validate,
-            ProvisionStep::Apply,
-            ProvisionStep::Summary,
-        ] {
-            let ctx = ProvisionContext {
-                inputs: inputs.clone(),
-                mode: mode.clone(),
-                step: step.clone(),
-                prior_results: step_results.clone(),
+impl<E: ProvisionExecutor> ProvisionEngine<E> {
+    pub fn run(&self, mode: ProvisionMode, inputs: ProvisionInputs) -> ProvisionResult {
+        let _span = telemetry::run(&inputs, mode.as_str()).entered();
+        let mut run = EngineRun::new(mode, inputs, &self.observers);
+        for step in STEPS {
+            let ctx = run.context(&step);
+            let output = {
+                let _span = telemetry::step(&ctx.inputs, step.as_str()).entered();
+                self.executor.run_step(step.clone(), &ctx)
             };
-            let output = self.executor.run_step(step.clone(), &ctx);
-            if let Some(patch) = output.plan_patch.clone() {
-                plan.merge_patch(patch);
-            }
-            diagnostics.extend(output.diagnostics.clone());
-            step_results.push(StepResult { step, output });
-        }
-
-        ProvisionResult {
-            plan,
-            diagnostics,
-            step_results: Some(step_results),
+            run.record(step, output);
         }
+        run.finish()
     }
 
     pub fn plan_from_fixtures(
@@ -95,6 +129,294 @@ impl<E: ProvisionExecutor> ProvisionEngine<E> {
     }
 }
 
+impl<E: AsyncProvisionExecutor> ProvisionEngine<E> {
+    /// Async counterpart of [`ProvisionEngine::run`]; dropping the future cancels the running
+    /// step and skips the remaining ones.
+    pub async fn run_async(&self, mode: ProvisionMode, inputs: ProvisionInputs) -> ProvisionResult {
+        let span = telemetry::run(&inputs, mode.as_str());
+        telemetry::instrument(
+            async {
+                let mut run = EngineRun::new(mode, inputs, &self.observers);
+                for step in STEPS {
+                    let ctx = run.context(&step);
+                    let output = telemetry::instrument(
+                        self.executor.run_step_async(step.clone(), &ctx),
+                        telemetry::step(&ctx.inputs, step.as_str()),
+                    )
+                    .await;
+                    run.record(step, output);
+                }
+                run.finish()
+            },
+            span,
+        )
+        .await
+    }
+}
+
+impl<E: ProvisionExecutor + Sync> ProvisionEngine<E> {
+    /// Runs independent installs on a bounded pool of worker threads. Results keep the order of
+    /// `installs`. A run that panics is reported as that install's result with a
+    /// `PROVISION_RUN_PANICKED` diagnostic; the other installs are unaffected.
+    pub fn run_many(
+        &self,
+        mode: ProvisionMode,
+        installs: Vec<ProvisionInputs>,
+        options: &BatchOptions,
+    ) -> BatchResult {
+        let started = Instant::now();
+        let next = AtomicUsize::new(0);
+        let workers = options
+            .concurrency
+            .min(self.executor.max_concurrency().unwrap_or(usize::MAX))
+            .clamp(1, installs.len().max(1));
+
+        let mut runs: Vec<(usize, InstallRun)> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut finished = Vec::new();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(inputs) = installs.get(index) else {
+                                break;
+                            };
+                            let run_started = Instant::now();
+                            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                                self.run(mode.clone(), inputs.clone())
+                            }))
+                            .unwrap_or_else(|payload| panicked_run(payload.as_ref()));
+                            finished.push((
+                                index,
+                                InstallRun {
+                                    provider_id: inputs.provider_id.clone(),
+                                    install_id: inputs.install_id.clone(),
+                                    result,
+                                    elapsed: run_started.elapsed(),
+                                },
+                            ));
+                        }
+                        finished
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| {
+                    handle
+                        .join()
+                        .expect("provision worker panicked outside a run")
+                })
+                .collect()
+        });
+        runs.sort_by_key(|(index, _)| *index);
+
+        BatchResult {
+            runs: runs.into_iter().map(|(_, run)| run).collect(),
+            elapsed: started.elapsed(),
+        }
+    }
+}
+
+/// Result reported for an install whose run panicked.
+fn panicked_run(payload: &(dyn std::any::Any + Send)) -> ProvisionResult {
+    let reason = payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic");
+    ProvisionResult {
+        plan: ProvisionPlan::default(),
+        diagnostics: vec![Diagnostic {
+            severity: Severity::Error,
+            code: "PROVISION_RUN_PANICKED".to_string(),
+            message: format!("provision run panicked: {reason}"),
+            path: None,
+            hint: None,
+            data: Value::Null,
+        }],
+        step_results: None,
+    }
+}
+
+/// Options for [`ProvisionEngine::run_many`].
+#[derive(Debug, Clone)]
+pub struct BatchOptions {
+    /// Installs provisioned at the same time. `run_many` lowers it to the executor's
+    /// `max_concurrency`, such as the slots of a pooling allocator.
+    pub concurrency: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: thread::available_parallelism().map_or(1, usize::from),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InstallRun {
+    pub provider_id: String,
+    pub install_id: String,
+    pub result: ProvisionResult,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BatchResult {
+    pub runs: Vec<InstallRun>,
+    /// Wall-clock time of the whole batch.
+    pub elapsed: Duration,
+}
+
+impl BatchResult {
+    /// Time spent provisioning summed over all installs.
+    pub fn busy(&self) -> Duration {
+        self.runs.iter().map(|run| run.elapsed).sum()
+    }
+
+    pub fn slowest(&self) -> Option<&InstallRun> {
+        self.runs.iter().max_by_key(|run| run.elapsed)
+    }
+}
+
+/// Plan, diagnostics and step results accumulated over one engine run, reported to the
+/// engine's observers as they change.
+struct EngineRun<'a> {
+    mode: ProvisionMode,
+    inputs: ProvisionInputs,
+    plan: ProvisionPlan,
+    diagnostics: Vec<Diagnostic>,
+    step_results: Vec<StepResult>,
+    observers: &'a [Arc<dyn ProvisionObserver>],
+    started: Instant,
+    step_started: Instant,
+}
+
+impl<'a> EngineRun<'a> {
+    fn new(
+        mode: ProvisionMode,
+        inputs: ProvisionInputs,
+        observers: &'a [Arc<dyn ProvisionObserver>],
+    ) -> Self {
+        let event = RunStarted {
+            provider_id: inputs.provider_id.clone(),
+            install_id: inputs.install_id.clone(),
+            mode: mode.clone(),
+        };
+        observers
+            .iter()
+            .for_each(|observer| observer.run_started(&event));
+        let started = Instant::now();
+        Self {
+            mode,
+            inputs,
+            plan: ProvisionPlan::default(),
+            diagnostics: Vec::new(),
+            step_results: Vec::new(),
+            observers,
+            started,
+            step_started: started,
+        }
+    }
+
+    fn context(&mut self, step: &ProvisionStep) -> ProvisionContext {
+        self.step_started = Instant::now();
+        let event = StepStarted {
+            install_id: self.inputs.install_id.clone(),
+            step: step.clone(),
+            offset_ms: millis(self.started.elapsed()),
+        };
+        self.notify(|observer| observer.step_started(&event));
+
+        ProvisionContext {
+            inputs: self.inputs.clone(),
+            mode: self.mode.clone(),
+            step: step.clone(),
+            prior_results: self.step_results.clone(),
+        }
+    }
+
+    fn record(&mut self, step: ProvisionStep, output: StepOutput) {
+        let observed = !self.observers.is_empty();
+        if observed {
+            let event = StepFinished {
+                provider_id: self.inputs.provider_id.clone(),
+                install_id: self.inputs.install_id.clone(),
+                step: step.clone(),
+                duration_ms: millis(self.step_started.elapsed()),
+                output_bytes: serialized_len(&output),
+                diagnostics: output.diagnostics.len(),
+                failed: output.data.get("error").is_some(),
+            };
+            self.notify(|observer| observer.step_finished(&event));
+        }
+
+        if let Some(patch) = output.plan_patch.clone() {
+            let patch_bytes = if observed { serialized_len(&patch) } else { 0 };
+            self.plan.merge_patch(patch);
+            if observed {
+                let event = PlanMerged {
+                    install_id: self.inputs.install_id.clone(),
+                    step: step.clone(),
+                    patch_bytes,
+                    plan_bytes: serialized_len(&self.plan),
+                };
+                self.notify(|observer| observer.plan_merged(&event));
+            }
+        }
+        for diagnostic in &output.diagnostics {
+            let event = DiagnosticEmitted {
+                install_id: self.inputs.install_id.clone(),
+                step: step.clone(),
+                diagnostic: diagnostic.clone(),
+            };
+            self.notify(|observer| observer.diagnostic_emitted(&event));
+        }
+        self.diagnostics.extend(output.diagnostics.clone());
+        self.step_results.push(StepResult { step, output });
+    }
+
+    fn finish(self) -> ProvisionResult {
+        if !self.observers.is_empty() {
+            let event = RunFinished {
+                provider_id: self.inputs.provider_id.clone(),
+                install_id: self.inputs.install_id.clone(),
+                duration_ms: millis(self.started.elapsed()),
+                steps: self.step_results.len(),
+                failed_steps: self
+                    .step_results
+                    .iter()
+                    .filter(|result| result.output.data.get("error").is_some())
+                    .count(),
+                diagnostics: self.diagnostics.len(),
+                plan_bytes: serialized_len(&self.plan),
+            };
+            self.notify(|observer| observer.run_finished(&event));
+        }
+        ProvisionResult {
+            plan: self.plan,
+            diagnostics: self.diagnostics,
+            step_results: Some(self.step_results),
+        }
+    }
+
+    fn notify(&self, callback: impl Fn(&dyn ProvisionObserver)) {
+        for observer in self.observers {
+            callback(observer.as_ref());
+        }
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+fn serialized_len(value: &impl Serialize) -> usize {
+    serde_json::to_vec(value).map_or(0, |bytes| bytes.len())
+}
+
 #[derive(Debug)]
 pub struct FixturePaths {
     pub collect: Option<std::path::PathBuf>,
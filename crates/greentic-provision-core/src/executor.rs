@@ -1,20 +1,38 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use greentic_types::validate::{Diagnostic, Severity};
 use serde_json::{Value, json};
-use wasmtime::{Config, Engine, Instance, MemoryAccessError, Module, Store};
+use wasmtime::component::{self, Component};
+use wasmtime::{
+    Config, Engine, InstanceAllocationStrategy, Linker, MemoryAccessError, Module,
+    PoolingAllocationConfig, Store, UpdateDeadline,
+};
 use wasmtime::{StoreLimits, StoreLimitsBuilder};
+use wasmtime_wasi::{WasiCtxView, WasiView};
 
-use crate::engine::{ProvisionContext, ProvisionExecutor};
-use crate::types::{ProvisionPlanPatch, ProvisionStep, StepOutput};
+use crate::engine::{AsyncProvisionExecutor, ProvisionContext, ProvisionExecutor};
+use crate::host::{self, Capability, HostBindings, HostState, ImportViolation};
+use crate::metrics::ProvisionMetrics;
+use crate::telemetry;
+use crate::types::{
+    ConsoleOutput, HostLogEntry, ProvisionPlanPatch, ProvisionStep, StepLayout, StepOutput,
+};
+use crate::wasi::{WasiOptions, WasiState};
 
 #[derive(Debug, Clone)]
 pub struct ExecutionLimits {
     pub max_output_bytes: usize,
     pub memory_limit_bytes: usize,
     pub timeout_ms: u64,
+    /// Fuel each step may burn, roughly one unit per wasm instruction. A step that runs out traps
+    /// with [`ExecutorError::OutOfFuel`], independently of wall-clock time.
     pub fuel: u64,
 }
 
@@ -24,7 +42,26 @@ impl Default for ExecutionLimits {
             max_output_bytes: 64 * 1024,
             memory_limit_bytes: 8 * 1024 * 1024,
             timeout_ms: 500,
-            fuel: 10_000,
+            fuel: 100_000_000,
+        }
+    }
+}
+
+/// Sizing of the wasmtime pooling allocator used when many steps run concurrently.
+#[derive(Debug, Clone)]
+pub struct PoolingOptions {
+    /// Instances that may be alive at once across all concurrent steps.
+    pub max_instances: u32,
+    /// Upper bound on linear memory across all live instances. Each instance reserves
+    /// `ExecutionLimits::memory_limit_bytes`, so this caps the number of concurrent memories.
+    pub total_memory_bytes: usize,
+}
+
+impl Default for PoolingOptions {
+    fn default() -> Self {
+        Self {
+            max_instances: 32,
+            total_memory_bytes: 256 * 1024 * 1024,
         }
     }
 }
@@ -33,6 +70,12 @@ impl Default for ExecutionLimits {
 pub enum ExecutorError {
     #[error("component not found for step: {0}")]
     ComponentNotFound(String),
+    #[error("component {component} not found for step {step} in {layout} layout")]
+    LayoutComponentMissing {
+        step: String,
+        layout: &'static str,
+        component: String,
+    },
     #[error("failed to read component: {0}")]
     Io(#[from] std::io::Error),
     #[error("failed to compile component: {0}")]
@@ -43,18 +86,79 @@ pub enum ExecutorError {
     Memory(#[from] MemoryAccessError),
     #[error("execution trap: {0}")]
     Trap(String),
+    #[error("step timed out after {0}ms")]
+    Timeout(u64),
+    #[error("step ran out of fuel after {0} units")]
+    OutOfFuel(u64),
     #[error("output too large: {0} bytes")]
     OutputTooLarge(usize),
     #[error("input too large: {0} bytes")]
     InputTooLarge(usize),
     #[error("invalid output JSON: {0}")]
     OutputJson(#[from] serde_json::Error),
+    #[error("import {import} requires undeclared capability `{capability}`")]
+    CapabilityDenied {
+        import: String,
+        capability: Capability,
+    },
+    #[error("unsupported import: {0}")]
+    UnsupportedImport(String),
+}
+
+impl ExecutorError {
+    /// Diagnostic surfaced on the step output for errors pack authors can act on.
+    pub fn diagnostic(&self, step_name: &str) -> Option<Diagnostic> {
+        let (code, hint) = match self {
+            ExecutorError::CapabilityDenied { capability, .. } => (
+                "PROVISION_CAPABILITY_DENIED",
+                format!("declare \"{capability}\" in meta.capabilities"),
+            ),
+            ExecutorError::UnsupportedImport(_) => (
+                "PROVISION_UNSUPPORTED_IMPORT",
+                format!("only {} functions can be imported", host::HOST_MODULE),
+            ),
+            ExecutorError::LayoutComponentMissing { component, .. } => (
+                "PROVISION_COMPONENT_MISSING",
+                format!("add {component}.wasm or change meta.step_layout"),
+            ),
+            _ => return None,
+        };
+        Some(Diagnostic {
+            severity: Severity::Error,
+            code: code.to_string(),
+            message: self.to_string(),
+            path: Some(step_name.to_string()),
+            hint: Some(hint),
+            data: Value::Null,
+        })
+    }
+}
+
+impl From<ImportViolation> for ExecutorError {
+    fn from(violation: ImportViolation) -> Self {
+        match violation {
+            ImportViolation::Undeclared { import, capability } => {
+                ExecutorError::CapabilityDenied { import, capability }
+            }
+            ImportViolation::Unsupported { import } => ExecutorError::UnsupportedImport(import),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct WasmtimeExecutor {
     pack_root: PathBuf,
     limits: ExecutionLimits,
+    engine: Engine,
+    ticker: Arc<EpochTicker>,
+    modules: Arc<Mutex<HashMap<PathBuf, Guest>>>,
+    host: HostBindings,
+    capabilities: BTreeSet<Capability>,
+    wasi: Option<WasiOptions>,
+    layout: Option<StepLayout>,
+    metrics: Option<ProvisionMetrics>,
+    /// Instances the pooling allocator can hold at once; `None` without pooling.
+    pool_slots: Option<usize>,
 }
 
 impl WasmtimeExecutor {
@@ -69,7 +173,85 @@ impl WasmtimeExecutor {
                 "pack root not found",
             )));
         }
-        Ok(Self { pack_root, limits })
+        let engine = Engine::new(&engine_config(None, &limits))?;
+        Ok(Self {
+            pack_root,
+            limits,
+            ticker: Arc::new(EpochTicker::start(engine.clone())),
+            engine,
+            modules: Arc::default(),
+            host: HostBindings::default(),
+            capabilities: BTreeSet::new(),
+            wasi: None,
+            layout: None,
+            metrics: None,
+            pool_slots: None,
+        })
+    }
+
+    /// Allocates instances from a shared pool so concurrent steps reuse memory slots instead of
+    /// mapping fresh ones. Instantiation fails once the pool is exhausted, so `run_many` runs at
+    /// most one install per slot.
+    pub fn with_pooling(mut self, options: PoolingOptions) -> Result<Self, ExecutorError> {
+        self.engine = Engine::new(&engine_config(Some(&options), &self.limits))?;
+        self.pool_slots = Some(pool_slots(&options, &self.limits) as usize);
+        self.ticker = Arc::new(EpochTicker::start(self.engine.clone()));
+        self.modules = Arc::default();
+        Ok(self)
+    }
+
+    /// Records fuel consumption and compile cache lookups.
+    pub fn with_metrics(mut self, metrics: ProvisionMetrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Grants the capabilities a pack declared; host functions for any other capability are not
+    /// linked and modules importing them fail to instantiate.
+    pub fn with_capabilities(mut self, capabilities: impl IntoIterator<Item = Capability>) -> Self {
+        self.capabilities = capabilities.into_iter().collect();
+        self
+    }
+
+    /// Backs the `greentic:host` imports with the given stores and clock.
+    pub fn with_host(mut self, host: HostBindings) -> Self {
+        self.host = host;
+        self
+    }
+
+    /// Links `wasi_snapshot_preview1` for core modules and the WASI preview 2 interfaces for
+    /// components built with WASI toolchains, and captures their stdout/stderr on the step output.
+    pub fn with_wasi(mut self, options: WasiOptions) -> Self {
+        self.wasi = Some(options);
+        self
+    }
+
+    /// Restricts component resolution to the layout the pack declared. Without it, per-step
+    /// components are tried first and `setup_default` is used as a fallback.
+    pub fn with_step_layout(mut self, layout: StepLayout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    /// Component path and layout that `step_name` resolves to.
+    pub fn resolve_step(&self, step_name: &str) -> Result<(PathBuf, StepLayout), ExecutorError> {
+        let layouts = match self.layout {
+            Some(layout) => vec![layout],
+            None => vec![StepLayout::PerStep, StepLayout::SingleComponent],
+        };
+        for layout in layouts {
+            if let Some(path) = self.find_component(&layout.component_name(step_name)) {
+                return Ok((path, layout));
+            }
+        }
+        Err(match self.layout {
+            Some(layout) => ExecutorError::LayoutComponentMissing {
+                step: step_name.to_string(),
+                layout: layout.as_str(),
+                component: layout.component_name(step_name),
+            },
+            None => ExecutorError::ComponentNotFound(step_name.to_string()),
+        })
     }
 
     pub fn run_named_step(
@@ -77,83 +259,210 @@ impl WasmtimeExecutor {
         step_name: &str,
         ctx: &ProvisionContext,
     ) -> Result<StepOutput, ExecutorError> {
-        let component_path = self.resolve_component(step_name)?;
-        let output_json = self.execute_component(&component_path, step_name, ctx)?;
-        step_output_from_json(output_json)
+        futures::executor::block_on(self.run_named_step_async(step_name, ctx))
     }
 
-    fn resolve_component(&self, step_name: &str) -> Result<PathBuf, ExecutorError> {
-        let candidates = vec![
-            format!("setup_default__{}", step_name),
-            "setup_default".to_string(),
-        ];
+    pub async fn run_named_step_async(
+        &self,
+        step_name: &str,
+        ctx: &ProvisionContext,
+    ) -> Result<StepOutput, ExecutorError> {
+        let (result, capture) = self.run_captured(step_name, ctx).await;
+        let mut output = step_output_from_json(result?)?;
+        output.logs = capture.logs;
+        output.console = capture.console;
+        output.layout = capture.layout;
+        Ok(output)
+    }
+
+    /// Runs a step, returning what the guest logged alongside the result so it is kept even when
+    /// the step fails.
+    async fn run_captured(
+        &self,
+        step_name: &str,
+        ctx: &ProvisionContext,
+    ) -> (Result<Value, ExecutorError>, StepCapture) {
+        match self.resolve_step(step_name) {
+            Ok((component_path, layout)) => {
+                let (result, mut capture) = self
+                    .execute_component(&component_path, step_name, ctx)
+                    .await;
+                capture.layout = Some(layout);
+                (result, capture)
+            }
+            Err(err) => (Err(err), StepCapture::default()),
+        }
+    }
+
+    fn find_component(&self, name: &str) -> Option<PathBuf> {
         let roots = [
             self.pack_root.join("components"),
             self.pack_root.join("wasm"),
             self.pack_root.clone(),
         ];
 
-        for candidate in candidates {
-            for root in &roots {
-                let wasm = root.join(format!("{}.wasm", candidate));
-                if wasm.exists() {
-                    return Ok(wasm);
-                }
-                let wat = root.join(format!("{}.wat", candidate));
-                if wat.exists() {
-                    return Ok(wat);
-                }
+        for root in &roots {
+            let wasm = root.join(format!("{}.wasm", name));
+            if wasm.exists() {
+                return Some(wasm);
+            }
+            let wat = root.join(format!("{}.wat", name));
+            if wat.exists() {
+                return Some(wat);
             }
         }
 
-        Err(ExecutorError::ComponentNotFound(step_name.to_string()))
+        None
     }
 
-    fn execute_component(
+    async fn execute_component(
         &self,
         component_path: &Path,
         step_name: &str,
         ctx: &ProvisionContext,
-    ) -> Result<Value, ExecutorError> {
-        let wasm_bytes = load_component_bytes(component_path)?;
-
-        let mut config = Config::new();
-        config.epoch_interruption(true);
+    ) -> (Result<Value, ExecutorError>, StepCapture) {
+        let compiled = {
+            let _span = telemetry::compile(&ctx.inputs, step_name).entered();
+            self.compile(component_path)
+        };
+        let (engine, guest) = match compiled {
+            Ok(compiled) => compiled,
+            Err(err) => return (Err(err), StepCapture::default()),
+        };
 
-        let engine = Engine::new(&config)?;
         let limits = StoreLimitsBuilder::new()
             .memory_size(self.limits.memory_limit_bytes)
             .build();
-        let mut store = Store::new(&engine, StoreState { limits });
+        let mut host = HostState::new(&self.host, ctx, step_name);
+        let wasi = match &self.wasi {
+            Some(options) => match host.wasi_context() {
+                Ok((clock_ms, random_seed)) => Some(WasiState::new(options, clock_ms, random_seed)),
+                Err(err) => return (Err(map_call_error(err)), StepCapture::default()),
+            },
+            None => None,
+        };
+        let mut store = Store::new(&engine, StoreState { limits, host, wasi });
 
         store.limiter(|state| &mut state.limits);
+        let _ = store.set_fuel(self.limits.fuel);
 
-        let epoch_engine = engine.clone();
+        // Guests yield to the async runtime on every epoch tick, so dropping the future cancels
+        // the step between ticks. The deadline check turns the tick into the step timeout.
         let timeout = self.limits.timeout_ms;
-        let epoch_handle = thread::spawn(move || {
-            thread::sleep(Duration::from_millis(timeout));
-            epoch_engine.increment_epoch();
-        });
+        let deadline = Instant::now() + Duration::from_millis(timeout);
         store.set_epoch_deadline(1);
+        store.epoch_deadline_callback(move |_| {
+            if Instant::now() >= deadline {
+                Err(wasmtime::Error::new(ExecutorError::Timeout(timeout)))
+            } else {
+                Ok(UpdateDeadline::Yield(1))
+            }
+        });
+        let result = match &guest {
+            Guest::Module(module) => {
+                self.invoke(&engine, module, &mut store, step_name, ctx)
+                    .await
+            }
+            Guest::Component(component) => {
+                self.invoke_component(&engine, component, &mut store, step_name, ctx)
+                    .await
+            }
+        };
+        let result = out_of_fuel(result, &store, self.limits.fuel);
+        if let (Some(metrics), Ok(remaining)) = (&self.metrics, store.get_fuel()) {
+            metrics.record_fuel(
+                &ctx.inputs.provider_id,
+                step_name,
+                self.limits.fuel - remaining,
+            );
+        }
 
-        let module = Module::new(&engine, wasm_bytes)?;
-        let instance = Instance::new(&mut store, &module, &[])?;
+        let state = store.data_mut();
+        let capture = StepCapture {
+            logs: state.host.take_logs(),
+            console: state.wasi.as_ref().map(WasiState::console),
+            layout: None,
+        };
+        (result, capture)
+    }
+
+    fn compile(&self, component_path: &Path) -> Result<(Engine, Guest), ExecutorError> {
+        let cached = lock(&self.modules).get(component_path).cloned();
+        if let Some(metrics) = &self.metrics {
+            metrics.record_compile(cached.is_some());
+        }
+        let guest = match cached {
+            Some(guest) => guest,
+            None => {
+                let wasm_bytes = load_component_bytes(component_path)?;
+                let guest = if is_component(&wasm_bytes) {
+                    Guest::Component(Component::new(&self.engine, wasm_bytes)?)
+                } else {
+                    Guest::Module(Module::new(&self.engine, wasm_bytes)?)
+                };
+                lock(&self.modules).insert(component_path.to_path_buf(), guest.clone());
+                guest
+            }
+        };
+        let allow_wasi = self.wasi.is_some();
+        match &guest {
+            Guest::Module(module) => host::check_imports(module, &self.capabilities, allow_wasi)?,
+            Guest::Component(component) => host::check_component_imports(
+                component,
+                &self.engine,
+                &self.capabilities,
+                allow_wasi,
+            )?,
+        }
+        Ok((self.engine.clone(), guest))
+    }
+
+    async fn invoke(
+        &self,
+        engine: &Engine,
+        module: &Module,
+        store: &mut Store<StoreState>,
+        step_name: &str,
+        ctx: &ProvisionContext,
+    ) -> Result<Value, ExecutorError> {
+        let mut linker = Linker::new(engine);
+        host::add_to_linker(
+            &mut linker,
+            |state: &mut StoreState| &mut state.host,
+            &self.capabilities,
+        )?;
+        if self.wasi.is_some() {
+            wasmtime_wasi::p1::add_to_linker_async(&mut linker, |state: &mut StoreState| {
+                state
+                    .wasi
+                    .as_mut()
+                    .expect("wasi linked without a context")
+                    .ctx()
+            })?;
+        }
+        let instance = telemetry::instrument(
+            linker.instantiate_async(&mut *store, module),
+            telemetry::instantiate(&ctx.inputs, step_name),
+        )
+        .await
+        .map_err(map_call_error)?;
+
+        // Reactor modules produced by WASI toolchains initialise their runtime here.
+        if let Some(initialize) = instance.get_func(&mut *store, "_initialize") {
+            initialize
+                .typed::<(), ()>(&*store)?
+                .call_async(&mut *store, ())
+                .await
+                .map_err(map_call_error)?;
+        }
 
         let memory = instance
-            .get_memory(&mut store, "memory")
+            .get_memory(&mut *store, "memory")
             .ok_or_else(|| ExecutorError::Trap("missing exported memory".to_string()))?;
 
-        let input = json!({
-            "step": step_name,
-            "inputs": ctx.inputs,
-            "state": {
-                "answers": ctx.inputs.answers,
-                "previous": ctx.prior_results,
-            }
-        });
-        let input_bytes = serde_json::to_vec(&input)?;
+        let input_bytes = serde_json::to_vec(&step_input(step_name, ctx))?;
 
-        let memory_size = memory.data_size(&store);
+        let memory_size = memory.data_size(&*store);
         if input_bytes.len() > memory_size {
             return Err(ExecutorError::InputTooLarge(input_bytes.len()));
         }
@@ -162,16 +471,17 @@ impl WasmtimeExecutor {
         if input_ptr + input_bytes.len() > memory_size {
             return Err(ExecutorError::InputTooLarge(input_bytes.len()));
         }
-        memory.write(&mut store, input_ptr, &input_bytes)?;
+        memory.write(&mut *store, input_ptr, &input_bytes)?;
 
         let func = instance
-            .get_func(&mut store, "run")
+            .get_func(&mut *store, "run")
             .ok_or_else(|| ExecutorError::Trap("missing run export".to_string()))?;
-        let func = func.typed::<(i32, i32), (i32, i32)>(&store)?;
+        let func = func.typed::<(i32, i32), (i32, i32)>(&*store)?;
 
         let (output_ptr, output_len) = func
-            .call(&mut store, (input_ptr as i32, input_bytes.len() as i32))
-            .map_err(|err| ExecutorError::Trap(err.to_string()))?;
+            .call_async(&mut *store, (input_ptr as i32, input_bytes.len() as i32))
+            .await
+            .map_err(map_call_error)?;
 
         let output_len = output_len as usize;
         if output_len > self.limits.max_output_bytes {
@@ -179,31 +489,194 @@ impl WasmtimeExecutor {
         }
 
         let mut buffer = vec![0u8; output_len];
-        memory.read(&mut store, output_ptr as usize, &mut buffer)?;
-        let output_json: Value = serde_json::from_slice(&buffer)?;
+        memory.read(&*store, output_ptr as usize, &mut buffer)?;
+        Ok(serde_json::from_slice(&buffer)?)
+    }
+
+    /// Runs a component step. Components export `run: func(input: string) -> string` taking and
+    /// returning the same JSON documents as the core module ABI.
+    async fn invoke_component(
+        &self,
+        engine: &Engine,
+        component: &Component,
+        store: &mut Store<StoreState>,
+        step_name: &str,
+        ctx: &ProvisionContext,
+    ) -> Result<Value, ExecutorError> {
+        let mut linker = component::Linker::new(engine);
+        host::add_to_component_linker(
+            &mut linker,
+            |state: &mut StoreState| &mut state.host,
+            &self.capabilities,
+        )?;
+        if self.wasi.is_some() {
+            wasmtime_wasi::p2::add_to_linker_async(&mut linker)?;
+        }
+        let instance = telemetry::instrument(
+            linker.instantiate_async(&mut *store, component),
+            telemetry::instantiate(&ctx.inputs, step_name),
+        )
+        .await
+        .map_err(map_call_error)?;
 
-        let _ = epoch_handle.join();
+        let func = instance
+            .get_typed_func::<(String,), (String,)>(&mut *store, "run")
+            .map_err(|_| ExecutorError::Trap("missing run export".to_string()))?;
+        let input = serde_json::to_string(&step_input(step_name, ctx))?;
+        let (output,) = func
+            .call_async(&mut *store, (input,))
+            .await
+            .map_err(map_call_error)?;
+        func.post_return_async(&mut *store)
+            .await
+            .map_err(map_call_error)?;
+
+        if output.len() > self.limits.max_output_bytes {
+            return Err(ExecutorError::OutputTooLarge(output.len()));
+        }
+        Ok(serde_json::from_str(&output)?)
+    }
+}
+
+/// JSON document handed to a step's `run` export.
+fn step_input(step_name: &str, ctx: &ProvisionContext) -> Value {
+    json!({
+        "step": step_name,
+        "inputs": ctx.inputs,
+        "state": {
+            "answers": ctx.inputs.answers,
+            "previous": ctx.prior_results,
+        }
+    })
+}
+
+/// Components share the wasm magic with core modules but carry layer 1 in the header.
+fn is_component(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"\0asm") && bytes.get(6..8) == Some(&[1, 0])
+}
+
+fn engine_config(pooling: Option<&PoolingOptions>, limits: &ExecutionLimits) -> Config {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    config.consume_fuel(true);
+    config.async_support(true);
+    if let Some(options) = pooling {
+        let memories = pool_slots(options, limits);
+        let mut pool = PoolingAllocationConfig::new();
+        pool.total_core_instances(options.max_instances)
+            .total_component_instances(options.max_instances)
+            .total_tables(options.max_instances)
+            .total_stacks(options.max_instances)
+            .total_memories(memories)
+            .max_memory_size(limits.memory_limit_bytes);
+        config
+            .allocation_strategy(InstanceAllocationStrategy::Pooling(pool))
+            .memory_reservation(limits.memory_limit_bytes as u64);
+    }
+    config
+}
+
+/// Instances that fit in the pool at once: one memory each, up to `max_instances`.
+fn pool_slots(options: &PoolingOptions, limits: &ExecutionLimits) -> u32 {
+    (options.total_memory_bytes / limits.memory_limit_bytes.max(1))
+        .clamp(1, options.max_instances as usize) as u32
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
 
-        Ok(output_json)
+/// Maps guest failures, keeping the timeout raised by the epoch deadline callback.
+fn map_call_error(err: wasmtime::Error) -> ExecutorError {
+    match err.downcast::<ExecutorError>() {
+        Ok(err) => err,
+        Err(err) => ExecutorError::Trap(format!("{err:#}")),
     }
 }
 
+/// Reports fuel exhaustion against the step's fuel limit rather than as a bare trap.
+fn out_of_fuel(
+    result: Result<Value, ExecutorError>,
+    store: &Store<StoreState>,
+    fuel: u64,
+) -> Result<Value, ExecutorError> {
+    match result {
+        Err(ExecutorError::Trap(_)) if store.get_fuel().is_ok_and(|remaining| remaining == 0) => {
+            Err(ExecutorError::OutOfFuel(fuel))
+        }
+        result => result,
+    }
+}
+
+/// Advances the engine epoch on one background thread per engine, shared by every clone of the
+/// executor. Dropping the last clone stops the thread at its next tick without waiting for it.
+#[derive(Debug)]
+struct EpochTicker {
+    stop: Arc<AtomicBool>,
+}
+
+impl EpochTicker {
+    const TICK: Duration = Duration::from_millis(10);
+
+    fn start(engine: Engine) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let flag = stop.clone();
+        thread::spawn(move || {
+            while !flag.load(Ordering::Relaxed) {
+                thread::sleep(Self::TICK);
+                engine.increment_epoch();
+            }
+        });
+        Self { stop }
+    }
+}
+
+impl Drop for EpochTicker {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Guest-emitted output collected from the store after a step ran.
+#[derive(Default)]
+struct StepCapture {
+    logs: Vec<HostLogEntry>,
+    console: Option<ConsoleOutput>,
+    layout: Option<StepLayout>,
+}
+
 impl ProvisionExecutor for WasmtimeExecutor {
     fn run_step(&self, step: ProvisionStep, ctx: &ProvisionContext) -> StepOutput {
-        let step_name = match step {
-            ProvisionStep::Collect => "collect",
-            ProvisionStep::Validate => "validate",
-            ProvisionStep::Apply => "apply",
-            ProvisionStep::Summary => "summary",
-        };
+        futures::executor::block_on(self.run_step_async(step, ctx))
+    }
 
-        match self.run_named_step(step_name, ctx) {
-            Ok(output) => output,
+    fn max_concurrency(&self) -> Option<usize> {
+        self.pool_slots
+    }
+}
+
+impl AsyncProvisionExecutor for WasmtimeExecutor {
+    async fn run_step_async(&self, step: ProvisionStep, ctx: &ProvisionContext) -> StepOutput {
+        let step_name = step.as_str();
+
+        let (result, capture) = self.run_captured(step_name, ctx).await;
+        match result.and_then(step_output_from_json) {
+            Ok(mut output) => {
+                output.logs = capture.logs;
+                output.console = capture.console;
+                output.layout = capture.layout;
+                output
+            }
             Err(err) => StepOutput {
                 data: json!({ "error": err.to_string(), "step": step_name }),
-                diagnostics: Vec::new(),
+                diagnostics: err.diagnostic(step_name).into_iter().collect(),
                 plan_patch: None,
                 questions: None,
+                logs: capture.logs,
+                console: capture.console,
+                layout: capture.layout,
             },
         }
     }
@@ -233,6 +706,9 @@ fn step_output_from_json(value: Value) -> Result<StepOutput, ExecutorError> {
         diagnostics: Vec::new(),
         plan_patch,
         questions,
+        logs: Vec::new(),
+        console: None,
+        layout: None,
     })
 }
 
@@ -293,7 +769,34 @@ pub fn timestamp_label() -> String {
     format!("{}-{}", now.as_secs(), now.subsec_millis())
 }
 
-#[derive(Debug)]
+/// Compiled step binary; core modules and components are cached side by side.
+#[derive(Clone)]
+enum Guest {
+    Module(Module),
+    Component(Component),
+}
+
+impl std::fmt::Debug for Guest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Guest::Module(module) => f.debug_tuple("Module").field(module).finish(),
+            Guest::Component(_) => f.write_str("Component"),
+        }
+    }
+}
+
 struct StoreState {
     limits: StoreLimits,
+    host: HostState,
+    wasi: Option<WasiState>,
+}
+
+impl WasiView for StoreState {
+    fn ctx(&mut self) -> WasiCtxView<'_> {
+        self.wasi
+            .as_mut()
+            .expect("wasi linked without a context")
+            .ctx()
+            .ctx()
+    }
 }
@@ -0,0 +1,898 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use greentic_types::validate::{Diagnostic, Severity};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use wasmtime::component::types::ComponentItem;
+use wasmtime::component::{self, Component};
+use wasmtime::{Caller, Engine, Extern, ExternType, Linker, Memory, Module, StoreContextMut};
+
+use crate::apply::{ConfigStore, SecretsStore, provision_namespace, secrets_namespace};
+use crate::engine::ProvisionContext;
+use crate::http::{HttpError, HttpRequest, HttpResponse, HttpTransport};
+use crate::recording::{HostCall, HostRecorder, HostReplay, RecordedStep};
+use crate::types::{HostLogEntry, LogLevel, ProvisionMode};
+use crate::wasi::WASI_MODULE;
+
+/// Import module name under which host functions are linked.
+pub const HOST_MODULE: &str = "greentic:host";
+
+/// Interface under which host functions are linked for components.
+pub const COMPONENT_HOST_INTERFACE: &str = "greentic:host/step";
+
+/// Prefix of the WASI preview 2 interfaces linked for components.
+const WASI_INTERFACE_PREFIX: &str = "wasi:";
+
+/// Largest buffer a single `random_fill` call may request. Larger requests trap before any bytes
+/// are generated or recorded.
+pub const MAX_RANDOM_FILL_BYTES: usize = 64 * 1024;
+
+/// Privileged host capability a pack declares in `meta.capabilities`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    Config,
+    Secrets,
+    #[serde(rename = "oauth")]
+    OAuth,
+    Http,
+    Subscriptions,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 5] = [
+        Capability::Config,
+        Capability::Secrets,
+        Capability::OAuth,
+        Capability::Http,
+        Capability::Subscriptions,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Config => "config",
+            Capability::Secrets => "secrets",
+            Capability::OAuth => "oauth",
+            Capability::Http => "http",
+            Capability::Subscriptions => "subscriptions",
+        }
+    }
+
+    /// Parses the names a pack declared in `meta.capabilities`. Every name this host does not
+    /// know is reported as a `PROVISION_UNKNOWN_CAPABILITY` error diagnostic.
+    pub fn parse_declared(names: &[String]) -> Result<BTreeSet<Capability>, Vec<Diagnostic>> {
+        let mut capabilities = BTreeSet::new();
+        let mut unknown = Vec::new();
+        for (index, name) in names.iter().enumerate() {
+            match name.parse() {
+                Ok(capability) => {
+                    capabilities.insert(capability);
+                }
+                Err(message) => unknown.push(Diagnostic {
+                    severity: Severity::Error,
+                    code: "PROVISION_UNKNOWN_CAPABILITY".to_string(),
+                    message,
+                    path: Some(format!("meta.capabilities[{index}]")),
+                    hint: Some(format!(
+                        "known capabilities: {}",
+                        Capability::ALL
+                            .map(|capability| capability.as_str())
+                            .join(", ")
+                    )),
+                    data: Value::Null,
+                }),
+            }
+        }
+        if unknown.is_empty() {
+            Ok(capabilities)
+        } else {
+            Err(unknown)
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Capability {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "config" => Ok(Capability::Config),
+            "secrets" => Ok(Capability::Secrets),
+            "oauth" => Ok(Capability::OAuth),
+            "http" => Ok(Capability::Http),
+            "subscriptions" => Ok(Capability::Subscriptions),
+            other => Err(format!("unknown capability: {other}")),
+        }
+    }
+}
+
+/// Capability required to link a `greentic:host` function. `None` for functions every step may
+/// import (logging and the clock); `Err` for names the host does not provide.
+fn required_capability(function: &str) -> Result<Option<Capability>, ()> {
+    match function {
+        "config_get" => Ok(Some(Capability::Config)),
+        "secret_exists" => Ok(Some(Capability::Secrets)),
+        "http_request" => Ok(Some(Capability::Http)),
+        "log" | "clock_now_ms" | "random_fill" => Ok(None),
+        _ => Err(()),
+    }
+}
+
+/// Import a module requests that the host will not link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportViolation {
+    /// A known host function whose capability was not declared by the pack.
+    Undeclared {
+        import: String,
+        capability: Capability,
+    },
+    /// A function or module the host does not provide at all.
+    Unsupported { import: String },
+}
+
+/// Checks every function import of `module` against the granted capabilities. WASI imports are
+/// only accepted when `allow_wasi` is set.
+pub fn check_imports(
+    module: &Module,
+    capabilities: &BTreeSet<Capability>,
+    allow_wasi: bool,
+) -> Result<(), ImportViolation> {
+    for import in module.imports() {
+        let name = format!("{}::{}", import.module(), import.name());
+        if allow_wasi && import.module() == WASI_MODULE {
+            continue;
+        }
+        if import.module() != HOST_MODULE || !matches!(import.ty(), ExternType::Func(_)) {
+            return Err(ImportViolation::Unsupported { import: name });
+        }
+        check_function(import.name(), name, capabilities)?;
+    }
+    Ok(())
+}
+
+/// Component counterpart of [`check_imports`]: only functions of [`COMPONENT_HOST_INTERFACE`]
+/// and, when `allow_wasi` is set, WASI preview 2 interfaces may be imported.
+pub fn check_component_imports(
+    component: &Component,
+    engine: &Engine,
+    capabilities: &BTreeSet<Capability>,
+    allow_wasi: bool,
+) -> Result<(), ImportViolation> {
+    for (interface, item) in component.component_type().imports(engine) {
+        if allow_wasi && interface.starts_with(WASI_INTERFACE_PREFIX) {
+            continue;
+        }
+        let ComponentItem::ComponentInstance(instance) = item else {
+            return Err(ImportViolation::Unsupported {
+                import: interface.to_string(),
+            });
+        };
+        if interface != COMPONENT_HOST_INTERFACE {
+            return Err(ImportViolation::Unsupported {
+                import: interface.to_string(),
+            });
+        }
+        for (function, item) in instance.exports(engine) {
+            let name = format!("{interface}::{function}");
+            if !matches!(item, ComponentItem::ComponentFunc(_)) {
+                return Err(ImportViolation::Unsupported { import: name });
+            }
+            check_function(&function.replace('-', "_"), name, capabilities)?;
+        }
+    }
+    Ok(())
+}
+
+fn check_function(
+    function: &str,
+    import: String,
+    capabilities: &BTreeSet<Capability>,
+) -> Result<(), ImportViolation> {
+    match required_capability(function) {
+        Ok(Some(capability)) if !capabilities.contains(&capability) => {
+            Err(ImportViolation::Undeclared { import, capability })
+        }
+        Ok(_) => Ok(()),
+        Err(()) => Err(ImportViolation::Unsupported { import }),
+    }
+}
+
+pub type SharedConfigStore = Arc<Mutex<dyn ConfigStore + Send>>;
+pub type SharedSecretsStore = Arc<Mutex<dyn SecretsStore + Send>>;
+
+/// Clock served to guests through `clock_now_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostClock {
+    /// Always returns the given unix timestamp in milliseconds.
+    Fixed(u64),
+    System,
+}
+
+impl Default for HostClock {
+    fn default() -> Self {
+        HostClock::Fixed(0)
+    }
+}
+
+impl HostClock {
+    fn now_ms(&self) -> u64 {
+        match self {
+            HostClock::Fixed(ms) => *ms,
+            HostClock::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        }
+    }
+}
+
+/// Stores, transport and clock backing the host functions available to pack steps.
+#[derive(Clone, Default)]
+pub struct HostBindings {
+    config: Option<SharedConfigStore>,
+    secrets: Option<SharedSecretsStore>,
+    http: Option<Arc<dyn HttpTransport>>,
+    clock: HostClock,
+    random_seed: u64,
+    recorder: Option<HostRecorder>,
+    replay: Option<HostReplay>,
+}
+
+impl HostBindings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_config_store(mut self, store: SharedConfigStore) -> Self {
+        self.config = Some(store);
+        self
+    }
+
+    pub fn with_secrets_store(mut self, store: SharedSecretsStore) -> Self {
+        self.secrets = Some(store);
+        self
+    }
+
+    pub fn with_http_transport(mut self, transport: Arc<dyn HttpTransport>) -> Self {
+        self.http = Some(transport);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: HostClock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Seeds the deterministic generator behind `random_fill`.
+    pub fn with_random_seed(mut self, seed: u64) -> Self {
+        self.random_seed = seed;
+        self
+    }
+
+    /// Records every host call into `recorder`.
+    pub fn with_recorder(mut self, recorder: HostRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Answers every host call from a recording instead of the configured stores, transport,
+    /// clock and random source. A guest that deviates from the recording traps; calls it never
+    /// makes are reported by [`HostReplay::unconsumed`].
+    pub fn with_replay(mut self, replay: HostReplay) -> Self {
+        self.replay = Some(replay);
+        self
+    }
+
+    pub fn clock(&self) -> HostClock {
+        self.clock
+    }
+
+    pub fn random_seed(&self) -> u64 {
+        self.random_seed
+    }
+}
+
+impl fmt::Debug for HostBindings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostBindings")
+            .field("config", &self.config.is_some())
+            .field("secrets", &self.secrets.is_some())
+            .field(
+                "http",
+                &self.http.as_ref().map(|transport| transport.name()),
+            )
+            .field("clock", &self.clock)
+            .field("random_seed", &self.random_seed)
+            .field("recording", &self.recorder.is_some())
+            .field("replaying", &self.replay.is_some())
+            .finish()
+    }
+}
+
+/// Per-step host state stored alongside the wasmtime store.
+pub(crate) struct HostState {
+    view: HostView,
+    http: Option<Arc<dyn HttpTransport>>,
+    dry_run: bool,
+    /// Response kept for a guest that retries `http_request` with a larger buffer, so the request
+    /// is not sent twice.
+    pending_http: Option<(Vec<u8>, Vec<u8>)>,
+    clock: HostClock,
+    random_state: u64,
+    logs: Vec<HostLogEntry>,
+    step: String,
+    calls: Vec<HostCall>,
+    recorder: Option<HostRecorder>,
+    replay: Option<VecDeque<HostCall>>,
+    /// Source of `replay`, told about recorded calls the guest did not make.
+    replay_source: Option<HostReplay>,
+}
+
+enum HostView {
+    /// Dry-run view: values are captured once when the step starts and the stores are never touched
+    /// again.
+    Snapshot {
+        /// The error message when the namespace could not be read; `config_get` then traps.
+        config: Result<BTreeMap<String, Value>, String>,
+        secret_keys: BTreeSet<String>,
+    },
+    Live {
+        config_namespace: String,
+        secrets_namespace: String,
+        config: Option<SharedConfigStore>,
+        secrets: Option<SharedSecretsStore>,
+    },
+}
+
+impl HostState {
+    pub(crate) fn new(bindings: &HostBindings, ctx: &ProvisionContext, step: &str) -> Self {
+        let config_namespace = provision_namespace(
+            &ctx.inputs.tenant,
+            &ctx.inputs.provider_id,
+            &ctx.inputs.install_id,
+        );
+        let secrets_namespace = secrets_namespace(&config_namespace);
+
+        let dry_run = ctx.mode == ProvisionMode::DryRun;
+        let view = if dry_run {
+            let config = bindings
+                .config
+                .as_ref()
+                .map(|store| lock(store).read_namespace(&config_namespace))
+                .unwrap_or_else(|| Ok(BTreeMap::new()))
+                .map_err(|err| err.to_string());
+            let secret_keys = bindings
+                .secrets
+                .as_ref()
+                .map(|store| lock(store).list_keys(&secrets_namespace))
+                .unwrap_or_default()
+                .into_iter()
+                .collect();
+            HostView::Snapshot {
+                config,
+                secret_keys,
+            }
+        } else {
+            HostView::Live {
+                config_namespace,
+                secrets_namespace,
+                config: bindings.config.clone(),
+                secrets: bindings.secrets.clone(),
+            }
+        };
+
+        Self {
+            view,
+            http: bindings.http.clone(),
+            dry_run,
+            pending_http: None,
+            clock: bindings.clock,
+            random_state: bindings.random_seed,
+            logs: Vec::new(),
+            step: step.to_string(),
+            calls: Vec::new(),
+            recorder: bindings.recorder.clone(),
+            replay: bindings
+                .replay
+                .as_ref()
+                .map(|replay| replay.take_step(step)),
+            replay_source: bindings.replay.clone(),
+        }
+    }
+
+    pub(crate) fn take_logs(&mut self) -> Vec<HostLogEntry> {
+        std::mem::take(&mut self.logs)
+    }
+
+    fn config_get(&mut self, key: &str) -> wasmtime::Result<Option<Value>> {
+        if let Some(call) = self.next_replayed("config_get")? {
+            return match call {
+                HostCall::ConfigGet {
+                    key: recorded,
+                    value,
+                } if recorded == key => Ok(value),
+                _ => Err(self.diverged(format!("config_get({key})"))),
+            };
+        }
+        let value = match &self.view {
+            HostView::Snapshot { config, .. } => config
+                .as_ref()
+                .map_err(|err| wasmtime::Error::msg(format!("config_get({key}): {err}")))?
+                .get(key)
+                .cloned(),
+            HostView::Live {
+                config_namespace,
+                config,
+                ..
+            } => match config {
+                Some(store) => lock(store)
+                    .read_namespace(config_namespace)
+                    .map_err(|err| wasmtime::Error::msg(format!("config_get({key}): {err}")))?
+                    .remove(key),
+                None => None,
+            },
+        };
+        self.calls.push(HostCall::ConfigGet {
+            key: key.to_string(),
+            value: value.clone(),
+        });
+        Ok(value)
+    }
+
+    /// Sends `request_bytes` through the configured transport and returns the JSON-encoded
+    /// response, or `{"error": ...}` when the request cannot be served.
+    fn http_request(&mut self, request_bytes: &[u8]) -> wasmtime::Result<Vec<u8>> {
+        if let Some((pending_request, response)) = self.pending_http.take()
+            && pending_request == request_bytes
+        {
+            return Ok(response);
+        }
+        let request = String::from_utf8_lossy(request_bytes).into_owned();
+        if let Some(call) = self.next_replayed("http_request")? {
+            return match call {
+                HostCall::HttpRequest {
+                    request: recorded,
+                    response,
+                } if recorded == request => Ok(response.into_bytes()),
+                _ => Err(self.diverged(format!("http_request({request})"))),
+            };
+        }
+        let response = match self.send_http(request_bytes) {
+            Ok(response) => serde_json::to_vec(&response),
+            Err(err) => serde_json::to_vec(&serde_json::json!({ "error": err.to_string() })),
+        }
+        .unwrap_or_default();
+        self.calls.push(HostCall::HttpRequest {
+            request,
+            response: String::from_utf8_lossy(&response).into_owned(),
+        });
+        Ok(response)
+    }
+
+    fn send_http(&self, request_bytes: &[u8]) -> Result<HttpResponse, HttpError> {
+        let request: HttpRequest = serde_json::from_slice(request_bytes)?;
+        let transport = self.http.as_ref().ok_or(HttpError::NoTransport)?;
+        if self.dry_run && !transport.is_sandboxed() {
+            return Err(HttpError::NotSandboxed(transport.name()));
+        }
+        transport.send(&request)
+    }
+
+    fn secret_exists(&mut self, key: &str) -> wasmtime::Result<bool> {
+        if let Some(call) = self.next_replayed("secret_exists")? {
+            return match call {
+                HostCall::SecretExists {
+                    key: recorded,
+                    exists,
+                } if recorded == key => Ok(exists),
+                _ => Err(self.diverged(format!("secret_exists({key})"))),
+            };
+        }
+        let exists = match &self.view {
+            HostView::Snapshot { secret_keys, .. } => secret_keys.contains(key),
+            HostView::Live {
+                secrets_namespace,
+                secrets,
+                ..
+            } => secrets.as_ref().is_some_and(|store| {
+                lock(store)
+                    .list_keys(secrets_namespace)
+                    .iter()
+                    .any(|existing| existing == key)
+            }),
+        };
+        self.calls.push(HostCall::SecretExists {
+            key: key.to_string(),
+            exists,
+        });
+        Ok(exists)
+    }
+
+    fn clock_now_ms(&mut self) -> wasmtime::Result<u64> {
+        if let Some(call) = self.next_replayed("clock_now_ms")? {
+            return match call {
+                HostCall::ClockNowMs { value } => Ok(value),
+                _ => Err(self.diverged("clock_now_ms()".to_string())),
+            };
+        }
+        let value = self.clock.now_ms();
+        self.calls.push(HostCall::ClockNowMs { value });
+        Ok(value)
+    }
+
+    fn random_fill(&mut self, len: usize) -> wasmtime::Result<Vec<u8>> {
+        if len > MAX_RANDOM_FILL_BYTES {
+            return Err(wasmtime::Error::msg(format!(
+                "random_fill length {len} exceeds the limit of {MAX_RANDOM_FILL_BYTES} bytes"
+            )));
+        }
+        if let Some(call) = self.next_replayed("random_fill")? {
+            return match call {
+                HostCall::RandomFill { bytes } if bytes.len() == len => Ok(bytes),
+                _ => Err(self.diverged(format!("random_fill({len})"))),
+            };
+        }
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let chunk = splitmix64(&mut self.random_state).to_le_bytes();
+            let take = chunk.len().min(len - bytes.len());
+            bytes.extend_from_slice(&chunk[..take]);
+        }
+        self.calls.push(HostCall::RandomFill {
+            bytes: bytes.clone(),
+        });
+        Ok(bytes)
+    }
+
+    /// Clock reading and random seed that a WASI guest's clocks and random source are pinned to for
+    /// the step. Read once per step, even under [`HostClock::System`], and recorded like any other
+    /// host call so a replay sees the same time and random bytes.
+    pub(crate) fn wasi_context(&mut self) -> wasmtime::Result<(u64, u64)> {
+        if let Some(call) = self.next_replayed("wasi_context")? {
+            return match call {
+                HostCall::WasiContext {
+                    clock_ms,
+                    random_seed,
+                } => Ok((clock_ms, random_seed)),
+                _ => Err(self.diverged("wasi_context()".to_string())),
+            };
+        }
+        let clock_ms = self.clock.now_ms();
+        let random_seed = self.random_state;
+        self.calls.push(HostCall::WasiContext {
+            clock_ms,
+            random_seed,
+        });
+        Ok((clock_ms, random_seed))
+    }
+
+    /// Pops the next recorded call when replaying; errors when the guest calls something other
+    /// than what was recorded.
+    fn next_replayed(&mut self, name: &str) -> wasmtime::Result<Option<HostCall>> {
+        let Some(replay) = self.replay.as_mut() else {
+            return Ok(None);
+        };
+        match replay.pop_front() {
+            Some(call) if call.name() == name => Ok(Some(call)),
+            Some(call) => Err(self.diverged(format!("{name} (recorded {})", call.name()))),
+            None => Err(self.diverged(format!("{name} (recording exhausted)"))),
+        }
+    }
+
+    fn diverged(&self, call: String) -> wasmtime::Error {
+        wasmtime::Error::msg(format!(
+            "replay diverged in step {}: unexpected {call}",
+            self.step
+        ))
+    }
+}
+
+impl Drop for HostState {
+    /// Hands the step's calls to the recorder, including steps that trapped part-way. When
+    /// replaying, hands recorded calls the guest did not make back to the replay instead.
+    fn drop(&mut self) {
+        if let (Some(source), Some(replay)) = (&self.replay_source, &mut self.replay) {
+            if !replay.is_empty() {
+                source.leave(RecordedStep {
+                    step: self.step.clone(),
+                    calls: replay.drain(..).collect(),
+                });
+            }
+            return;
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.push(RecordedStep {
+                step: std::mem::take(&mut self.step),
+                calls: std::mem::take(&mut self.calls),
+            });
+        }
+    }
+}
+
+pub(crate) fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Links the `greentic:host` functions permitted by `capabilities` into `linker`.
+///
+/// Guest ABI (all pointers/lengths are offsets into the exported `memory`):
+/// - `config_get(key_ptr, key_len, out_ptr, out_cap) -> i32`: writes the JSON-encoded value and
+///   returns its length, `-1` when the key is missing. When the value is larger than `out_cap`
+///   nothing is written and the required length is returned.
+/// - `secret_exists(key_ptr, key_len) -> i32`: `1` when the secret key is set, `0` otherwise.
+/// - `http_request(req_ptr, req_len, out_ptr, out_cap) -> i32`: sends a JSON
+///   `{method, url, headers, body}` request through the configured transport and writes the JSON
+///   `{status, headers, body}` response (or `{error}`) using the same length protocol as
+///   `config_get`.
+/// - `log(level, ptr, len)`: records a log line; JSON payloads are kept structured.
+/// - `clock_now_ms() -> i64`: current time according to the configured [`HostClock`].
+/// - `random_fill(ptr, len)`: fills the buffer from a seeded, deterministic generator. Traps when
+///   the buffer is outside guest memory or longer than [`MAX_RANDOM_FILL_BYTES`].
+pub(crate) fn add_to_linker<T: 'static>(
+    linker: &mut Linker<T>,
+    get: fn(&mut T) -> &mut HostState,
+    capabilities: &BTreeSet<Capability>,
+) -> wasmtime::Result<()> {
+    if capabilities.contains(&Capability::Config) {
+        link_config(linker, get)?;
+    }
+    if capabilities.contains(&Capability::Secrets) {
+        link_secrets(linker, get)?;
+    }
+    if capabilities.contains(&Capability::Http) {
+        link_http(linker, get)?;
+    }
+    link_log(linker, get)?;
+    link_clock(linker, get)?;
+    link_random(linker, get)?;
+    Ok(())
+}
+
+/// Links the host functions permitted by `capabilities` into `linker` as the
+/// [`COMPONENT_HOST_INTERFACE`] instance. The functions mirror [`add_to_linker`] with canonical
+/// ABI types instead of pointers:
+///
+/// ```wit
+/// config-get: func(key: string) -> option<string>;      // JSON-encoded value
+/// secret-exists: func(key: string) -> bool;
+/// http-request: func(request: string) -> string;        // JSON request and response
+/// log: func(level: s32, line: string);
+/// clock-now-ms: func() -> u64;
+/// random-fill: func(len: u32) -> list<u8>;             // at most MAX_RANDOM_FILL_BYTES
+/// ```
+pub(crate) fn add_to_component_linker<T: 'static>(
+    linker: &mut component::Linker<T>,
+    get: fn(&mut T) -> &mut HostState,
+    capabilities: &BTreeSet<Capability>,
+) -> wasmtime::Result<()> {
+    let mut instance = linker.instance(COMPONENT_HOST_INTERFACE)?;
+    if capabilities.contains(&Capability::Config) {
+        instance.func_wrap(
+            "config-get",
+            move |mut store: StoreContextMut<'_, T>, (key,): (String,)| {
+                let value = get(store.data_mut()).config_get(&key)?;
+                Ok((value
+                    .map(|value| serde_json::to_string(&value))
+                    .transpose()?,))
+            },
+        )?;
+    }
+    if capabilities.contains(&Capability::Secrets) {
+        instance.func_wrap(
+            "secret-exists",
+            move |mut store: StoreContextMut<'_, T>, (key,): (String,)| {
+                Ok((get(store.data_mut()).secret_exists(&key)?,))
+            },
+        )?;
+    }
+    if capabilities.contains(&Capability::Http) {
+        instance.func_wrap(
+            "http-request",
+            move |mut store: StoreContextMut<'_, T>, (request,): (String,)| {
+                let response = get(store.data_mut()).http_request(request.as_bytes())?;
+                Ok((String::from_utf8_lossy(&response).into_owned(),))
+            },
+        )?;
+    }
+    instance.func_wrap(
+        "log",
+        move |mut store: StoreContextMut<'_, T>, (level, line): (i32, String)| {
+            let data = serde_json::from_str(&line).unwrap_or(Value::String(line));
+            get(store.data_mut()).logs.push(HostLogEntry {
+                level: LogLevel::from_raw(level),
+                data,
+            });
+            Ok(())
+        },
+    )?;
+    instance.func_wrap(
+        "clock-now-ms",
+        move |mut store: StoreContextMut<'_, T>, (): ()| {
+            Ok((get(store.data_mut()).clock_now_ms()?,))
+        },
+    )?;
+    instance.func_wrap(
+        "random-fill",
+        move |mut store: StoreContextMut<'_, T>, (len,): (u32,)| {
+            let len = usize::try_from(len)?;
+            Ok((get(store.data_mut()).random_fill(len)?,))
+        },
+    )?;
+    Ok(())
+}
+
+fn link_config<T: 'static>(
+    linker: &mut Linker<T>,
+    get: fn(&mut T) -> &mut HostState,
+) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "config_get",
+        move |mut caller: Caller<'_, T>,
+              key_ptr: i32,
+              key_len: i32,
+              out_ptr: i32,
+              out_cap: i32|
+              -> wasmtime::Result<i32> {
+            let memory = guest_memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let key = read_str(data, key_ptr, key_len)?;
+            let Some(value) = get(state).config_get(&key)? else {
+                return Ok(-1);
+            };
+            let encoded = serde_json::to_vec(&value)?;
+            if encoded.len() <= out_cap.max(0) as usize {
+                slice_mut(data, out_ptr, encoded.len())?.copy_from_slice(&encoded);
+            }
+            Ok(encoded.len() as i32)
+        },
+    )?;
+    Ok(())
+}
+
+fn link_secrets<T: 'static>(
+    linker: &mut Linker<T>,
+    get: fn(&mut T) -> &mut HostState,
+) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "secret_exists",
+        move |mut caller: Caller<'_, T>, key_ptr: i32, key_len: i32| -> wasmtime::Result<i32> {
+            let memory = guest_memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let key = read_str(data, key_ptr, key_len)?;
+            Ok(get(state).secret_exists(&key)? as i32)
+        },
+    )?;
+    Ok(())
+}
+
+fn link_http<T: 'static>(
+    linker: &mut Linker<T>,
+    get: fn(&mut T) -> &mut HostState,
+) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "http_request",
+        move |mut caller: Caller<'_, T>,
+              req_ptr: i32,
+              req_len: i32,
+              out_ptr: i32,
+              out_cap: i32|
+              -> wasmtime::Result<i32> {
+            let memory = guest_memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let request = read_str(data, req_ptr, req_len)?.into_bytes();
+            let state = get(state);
+            let encoded = state.http_request(&request)?;
+            if encoded.len() <= out_cap.max(0) as usize {
+                slice_mut(data, out_ptr, encoded.len())?.copy_from_slice(&encoded);
+            } else {
+                state.pending_http = Some((request, encoded.clone()));
+            }
+            Ok(encoded.len() as i32)
+        },
+    )?;
+    Ok(())
+}
+
+fn link_log<T: 'static>(
+    linker: &mut Linker<T>,
+    get: fn(&mut T) -> &mut HostState,
+) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "log",
+        move |mut caller: Caller<'_, T>, level: i32, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let memory = guest_memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let line = read_str(data, ptr, len)?;
+            let data = serde_json::from_str(&line).unwrap_or(Value::String(line));
+            get(state).logs.push(HostLogEntry {
+                level: LogLevel::from_raw(level),
+                data,
+            });
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+fn link_clock<T: 'static>(
+    linker: &mut Linker<T>,
+    get: fn(&mut T) -> &mut HostState,
+) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "clock_now_ms",
+        move |mut caller: Caller<'_, T>| -> wasmtime::Result<i64> {
+            Ok(get(caller.data_mut()).clock_now_ms()? as i64)
+        },
+    )?;
+    Ok(())
+}
+
+fn link_random<T: 'static>(
+    linker: &mut Linker<T>,
+    get: fn(&mut T) -> &mut HostState,
+) -> wasmtime::Result<()> {
+    linker.func_wrap(
+        HOST_MODULE,
+        "random_fill",
+        move |mut caller: Caller<'_, T>, ptr: i32, len: i32| -> wasmtime::Result<()> {
+            let len = usize::try_from(len).map_err(|_| wasmtime::Error::msg("negative length"))?;
+            let memory = guest_memory(&mut caller)?;
+            let (data, state) = memory.data_and_store_mut(&mut caller);
+            let buffer = slice_mut(data, ptr, len)?;
+            let bytes = get(state).random_fill(len)?;
+            buffer.copy_from_slice(&bytes);
+            Ok(())
+        },
+    )?;
+    Ok(())
+}
+
+fn guest_memory<T>(caller: &mut Caller<'_, T>) -> wasmtime::Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmtime::Error::msg("missing exported memory"))
+}
+
+fn slice_mut(data: &mut [u8], ptr: i32, len: usize) -> wasmtime::Result<&mut [u8]> {
+    let start = usize::try_from(ptr).map_err(|_| wasmtime::Error::msg("negative pointer"))?;
+    start
+        .checked_add(len)
+        .and_then(|end| data.get_mut(start..end))
+        .ok_or_else(|| wasmtime::Error::msg("guest pointer out of bounds"))
+}
+
+fn read_str(data: &[u8], ptr: i32, len: i32) -> wasmtime::Result<String> {
+    let start = usize::try_from(ptr).map_err(|_| wasmtime::Error::msg("negative pointer"))?;
+    let len = usize::try_from(len).map_err(|_| wasmtime::Error::msg("negative length"))?;
+    let bytes = start
+        .checked_add(len)
+        .and_then(|end| data.get(start..end))
+        .ok_or_else(|| wasmtime::Error::msg("guest pointer out of bounds"))?;
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
@@ -0,0 +1,343 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HttpExchange {
+    pub request: HttpRequest,
+    pub response: HttpResponse,
+}
+
+/// Canned exchanges served by [`ReplayTransport`] and written by [`RecordingTransport`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct HttpFixtures {
+    pub exchanges: Vec<HttpExchange>,
+}
+
+impl HttpFixtures {
+    pub fn load(path: &Path) -> Result<Self, HttpError> {
+        let contents = std::fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), HttpError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HttpError {
+    #[error("no http transport configured")]
+    NoTransport,
+    #[error("transport {0} is not sandboxed and cannot be used in dry-run mode")]
+    NotSandboxed(&'static str),
+    #[error("no fixture for {method} {url}")]
+    NoFixture { method: String, url: String },
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+    #[error("host not allowed: {0}")]
+    HostNotAllowed(String),
+    #[error("transport error: {0}")]
+    Transport(String),
+    #[error("failed to read fixtures: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("failed to parse fixtures: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Outbound HTTP used by the `http_request` host function. Packs never get direct network access;
+/// every request is routed through the transport the host configured.
+pub trait HttpTransport: Send + Sync {
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError>;
+
+    /// Short name used in diagnostics.
+    fn name(&self) -> &'static str;
+
+    /// Whether the transport never reaches the network. Only sandboxed transports are used in
+    /// dry-run mode.
+    fn is_sandboxed(&self) -> bool {
+        false
+    }
+}
+
+/// Serves responses from fixtures, matching on method and URL.
+#[derive(Debug, Default)]
+pub struct ReplayTransport {
+    fixtures: HttpFixtures,
+}
+
+impl ReplayTransport {
+    pub fn new(fixtures: HttpFixtures) -> Self {
+        Self { fixtures }
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, HttpError> {
+        Ok(Self::new(HttpFixtures::load(path)?))
+    }
+}
+
+impl HttpTransport for ReplayTransport {
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError> {
+        self.fixtures
+            .exchanges
+            .iter()
+            .find(|exchange| {
+                exchange
+                    .request
+                    .method
+                    .eq_ignore_ascii_case(&request.method)
+                    && exchange.request.url == request.url
+            })
+            .map(|exchange| exchange.response.clone())
+            .ok_or_else(|| HttpError::NoFixture {
+                method: request.method.clone(),
+                url: request.url.clone(),
+            })
+    }
+
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    fn is_sandboxed(&self) -> bool {
+        true
+    }
+}
+
+/// Records every successful exchange sent through the inner transport.
+#[derive(Debug)]
+pub struct RecordingTransport<T> {
+    inner: T,
+    exchanges: Mutex<Vec<HttpExchange>>,
+}
+
+impl<T: HttpTransport> RecordingTransport<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            exchanges: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn fixtures(&self) -> HttpFixtures {
+        HttpFixtures {
+            exchanges: self
+                .exchanges
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone(),
+        }
+    }
+}
+
+impl<T: HttpTransport> HttpTransport for RecordingTransport<T> {
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError> {
+        let response = self.inner.send(request)?;
+        self.exchanges
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .push(HttpExchange {
+                request: request.clone(),
+                response: response.clone(),
+            });
+        Ok(response)
+    }
+
+    fn name(&self) -> &'static str {
+        "recording"
+    }
+
+    fn is_sandboxed(&self) -> bool {
+        self.inner.is_sandboxed()
+    }
+}
+
+/// Sends requests over the network with a blocking client. Redirects are not followed, so a
+/// wrapping [`AllowlistTransport`] sees every host that is contacted. Never sandboxed.
+#[derive(Debug)]
+pub struct NetworkTransport {
+    client: reqwest::blocking::Client,
+}
+
+impl NetworkTransport {
+    pub fn new() -> Result<Self, HttpError> {
+        let client = reqwest::blocking::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .timeout(Duration::from_secs(30))
+            .build()
+            .map_err(|err| HttpError::Transport(err.to_string()))?;
+        Ok(Self { client })
+    }
+}
+
+impl HttpTransport for NetworkTransport {
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError> {
+        let method = reqwest::Method::from_bytes(request.method.to_ascii_uppercase().as_bytes())
+            .map_err(|err| HttpError::Transport(format!("method {}: {err}", request.method)))?;
+        let url = url::Url::parse(&request.url)
+            .map_err(|err| HttpError::InvalidUrl(format!("{}: {err}", request.url)))?;
+        let mut builder = self.client.request(method, url);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = &request.body {
+            builder = builder.body(body.clone());
+        }
+        let response = builder
+            .send()
+            .map_err(|err| HttpError::Transport(err.without_url().to_string()))?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((name.as_str().to_string(), value.to_str().ok()?.to_string()))
+            })
+            .collect();
+        let body = response
+            .text()
+            .map_err(|err| HttpError::Transport(err.without_url().to_string()))?;
+        Ok(HttpResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        "network"
+    }
+}
+
+/// Restricts the inner transport to the hosts a pack declared in `meta.http_hosts`.
+#[derive(Debug)]
+pub struct AllowlistTransport<T> {
+    inner: T,
+    allowed_hosts: BTreeSet<String>,
+}
+
+impl<T: HttpTransport> AllowlistTransport<T> {
+    pub fn new(inner: T, allowed_hosts: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            inner,
+            allowed_hosts: allowed_hosts
+                .into_iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+}
+
+impl<T: HttpTransport> HttpTransport for AllowlistTransport<T> {
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError> {
+        let url = url::Url::parse(&request.url)
+            .map_err(|err| HttpError::InvalidUrl(format!("{}: {err}", request.url)))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(HttpError::InvalidUrl(request.url.clone()));
+        }
+        let host = url
+            .host_str()
+            .ok_or_else(|| HttpError::InvalidUrl(request.url.clone()))?
+            .to_ascii_lowercase();
+        if !self.allowed_hosts.contains(&host) {
+            return Err(HttpError::HostNotAllowed(host));
+        }
+        self.inner.send(request)
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn is_sandboxed(&self) -> bool {
+        self.inner.is_sandboxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: BTreeMap::new(),
+            body: None,
+        }
+    }
+
+    fn fixtures() -> HttpFixtures {
+        HttpFixtures {
+            exchanges: vec![HttpExchange {
+                request: request("https://api.example.com/me"),
+                response: HttpResponse {
+                    status: 200,
+                    headers: BTreeMap::new(),
+                    body: "{\"ok\":true}".to_string(),
+                },
+            }],
+        }
+    }
+
+    #[test]
+    fn replay_serves_matching_fixture() {
+        let transport = RecordingTransport::new(ReplayTransport::new(fixtures()));
+        let response = transport
+            .send(&request("https://api.example.com/me"))
+            .expect("fixture response");
+        assert_eq!(response.status, 200);
+        assert!(matches!(
+            transport.send(&request("https://api.example.com/other")),
+            Err(HttpError::NoFixture { .. })
+        ));
+        assert_eq!(transport.fixtures(), fixtures());
+    }
+
+    #[test]
+    fn allowlist_rejects_undeclared_hosts() {
+        let transport = AllowlistTransport::new(
+            ReplayTransport::new(fixtures()),
+            ["API.example.com".to_string()],
+        );
+        assert!(
+            transport
+                .send(&request("https://api.example.com/me"))
+                .is_ok()
+        );
+        assert!(matches!(
+            transport.send(&request("https://evil.example.com/me")),
+            Err(HttpError::HostNotAllowed(host)) if host == "evil.example.com"
+        ));
+        assert!(matches!(
+            transport.send(&request("file:///etc/passwd")),
+            Err(HttpError::InvalidUrl(_))
+        ));
+    }
+}
@@ -1,18 +1,66 @@
 pub mod apply;
 pub mod discovery;
+pub mod encrypted_secrets;
 pub mod engine;
 pub mod executor;
+pub mod host;
+pub mod http;
+pub mod leaks;
+pub mod metrics;
+pub mod oauth;
+pub mod observer;
+pub mod recording;
+pub mod secret;
+pub mod secret_refs;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+mod telemetry;
 pub mod types;
+pub mod wasi;
 
 pub use apply::{
-    ApplyMode, ApplyReport, ConfigApplier, ConfigStore, FileInstallStore, InMemoryConfigStore,
-    InMemoryInstallStore, InMemorySecretsStore, InstallStore, NoopOAuthHandler, OAuthHandler,
-    OAuthTokenSet, ProviderInstallRecord, ProvisionApplier, SecretsStore, SubscriptionState,
+    ApplyError, ApplyErrorKind, ApplyFailure, ApplyMode, ApplyReport, ConfigApplier, ConfigStore,
+    FileConfigStore, FileInstallStore, InMemoryConfigStore, InMemoryInstallStore,
+    InMemorySecretsStore, InstallStore, NoopOAuthHandler, OAUTH_ACCESS_TOKEN_KEY,
+    OAUTH_REFRESH_TOKEN_KEY, OAuthHandler, OAuthTokenSet, OperationOutcome, OperationStatus,
+    ProviderInstallRecord, ProvisionApplier, RevertAction, RevertedWrite, SecretsStore,
+    SubscriptionState, WriteOp, WriteTarget,
 };
 pub use discovery::{DefaultProvisionPackDiscovery, ProvisionDescriptor, ProvisionPackDiscovery};
-pub use engine::{NoopExecutor, ProvisionContext, ProvisionEngine, ProvisionExecutor};
-pub use executor::{ExecutionLimits, WasmtimeExecutor};
+pub use encrypted_secrets::{EncryptedFileSecretsStore, SecretsKey, SecretsKeyError};
+pub use engine::{
+    AsyncProvisionExecutor, BatchOptions, BatchResult, InstallRun, NoopExecutor, ProvisionContext,
+    ProvisionEngine, ProvisionExecutor,
+};
+pub use executor::{ExecutionLimits, PoolingOptions, WasmtimeExecutor};
+pub use host::{
+    Capability, HostBindings, HostClock, MAX_RANDOM_FILL_BYTES, SharedConfigStore,
+    SharedSecretsStore,
+};
+pub use http::{
+    AllowlistTransport, HttpError, HttpExchange, HttpFixtures, HttpRequest, HttpResponse,
+    HttpTransport, NetworkTransport, RecordingTransport, ReplayTransport,
+};
+pub use leaks::{LeakEncoding, LeakScanner, REDACTED, SecretLeak};
+pub use metrics::ProvisionMetrics;
+pub use oauth::{
+    AuthorizationCallback, AuthorizationRequest, OAuthProviderConfig,
+    PENDING_AUTHORIZATIONS_NAMESPACE, PkceOAuthHandler, PkcePair,
+};
+pub use observer::{
+    DiagnosticEmitted, JsonLinesObserver, PlanMerged, ProgressObserver, ProvisionObserver,
+    RunFinished, RunStarted, StepFinished, StepStarted,
+};
+pub use recording::{HostCall, HostRecorder, HostRecording, HostReplay, RecordedStep};
+pub use secret::{SecretString, expose_secrets};
+pub use secret_refs::{
+    DefaultSecretResolver, ResolveContext, SecretRef, SecretRefError, SecretResolver,
+};
+#[cfg(feature = "sqlite")]
+pub use sqlite::{SqliteConfigStore, SqliteInstallStore, SqliteSecretsStore, SqliteStore};
 pub use types::{
-    OAuthOp, ProvisionInputs, ProvisionMode, ProvisionPlan, ProvisionPlanPatch, ProvisionResult,
-    ProvisionStep, StepOutput, StepResult, TenantContext,
+    ConsoleOutput, HostLogEntry, LogLevel, OAuthOp, ProvisionInputs, ProvisionMode, ProvisionPlan,
+    ProvisionPlanPatch, ProvisionResult, ProvisionStep, StepLayout, StepOutput, StepResult,
+    TenantContext,
 };
+pub use wasi::WasiOptions;
@@ -0,0 +1,244 @@
+use std::sync::{Arc, Mutex};
+
+use prometheus_client::encoding::EncodeLabelSet;
+use prometheus_client::encoding::text::encode;
+use prometheus_client::metrics::counter::Counter;
+use prometheus_client::metrics::family::Family;
+use prometheus_client::metrics::histogram::{Histogram, exponential_buckets};
+use prometheus_client::registry::Registry;
+
+use crate::observer::{ProvisionObserver, RunFinished, StepFinished};
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct RunLabels {
+    provider: String,
+    outcome: &'static str,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct StepLabels {
+    provider: String,
+    step: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct ProviderLabels {
+    provider: String,
+}
+
+#[derive(Debug, Clone, Hash, PartialEq, Eq, EncodeLabelSet)]
+struct CacheLabels {
+    result: &'static str,
+}
+
+/// Prometheus metrics for provisioning hosts.
+///
+/// Cheap to clone; clones share the same registry. Register it as an engine observer for run and
+/// step metrics, and pass it to the executor and applier for fuel, compile cache and apply
+/// metrics.
+#[derive(Clone)]
+pub struct ProvisionMetrics {
+    registry: Arc<Mutex<Registry>>,
+    runs: Family<RunLabels, Counter>,
+    step_duration: Family<StepLabels, Histogram>,
+    fuel: Family<StepLabels, Histogram>,
+    compile_cache: Family<CacheLabels, Counter>,
+    applies: Family<RunLabels, Counter>,
+    secret_writes: Family<ProviderLabels, Counter>,
+    secret_write_failures: Family<ProviderLabels, Counter>,
+}
+
+impl ProvisionMetrics {
+    pub fn new() -> Self {
+        let mut registry = Registry::with_prefix("greentic_provision");
+        let runs = Family::<RunLabels, Counter>::default();
+        registry.register(
+            "runs",
+            "Provisioning runs by provider and outcome",
+            runs.clone(),
+        );
+        let step_duration =
+            Family::<StepLabels, Histogram>::new_with_constructor(duration_histogram);
+        registry.register(
+            "step_duration_seconds",
+            "Step execution time",
+            step_duration.clone(),
+        );
+        let fuel = Family::<StepLabels, Histogram>::new_with_constructor(fuel_histogram);
+        registry.register("step_fuel", "Wasm fuel consumed per step", fuel.clone());
+        let compile_cache = Family::<CacheLabels, Counter>::default();
+        registry.register(
+            "compile_cache",
+            "Component compile cache lookups by result",
+            compile_cache.clone(),
+        );
+        let applies = Family::<RunLabels, Counter>::default();
+        registry.register(
+            "applies",
+            "Plan applies by provider and outcome",
+            applies.clone(),
+        );
+        let secret_writes = Family::<ProviderLabels, Counter>::default();
+        registry.register(
+            "secret_writes",
+            "Secrets written or deleted by applies",
+            secret_writes.clone(),
+        );
+        let secret_write_failures = Family::<ProviderLabels, Counter>::default();
+        registry.register(
+            "secret_write_failures",
+            "Secret writes or deletes rejected by the secrets store",
+            secret_write_failures.clone(),
+        );
+
+        Self {
+            registry: Arc::new(Mutex::new(registry)),
+            runs,
+            step_duration,
+            fuel,
+            compile_cache,
+            applies,
+            secret_writes,
+            secret_write_failures,
+        }
+    }
+
+    /// Renders every recorded metric in the OpenMetrics text format Prometheus scrapes.
+    pub fn encode(&self) -> String {
+        let registry = self
+            .registry
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut output = String::new();
+        // Writing into a String cannot fail.
+        let _ = encode(&mut output, &registry);
+        output
+    }
+
+    pub(crate) fn record_fuel(&self, provider: &str, step: &str, fuel: u64) {
+        self.fuel
+            .get_or_create(&StepLabels {
+                provider: provider.to_string(),
+                step: step.to_string(),
+            })
+            .observe(fuel as f64);
+    }
+
+    pub(crate) fn record_compile(&self, cache_hit: bool) {
+        self.compile_cache
+            .get_or_create(&CacheLabels {
+                result: if cache_hit { "hit" } else { "miss" },
+            })
+            .inc();
+    }
+
+    pub(crate) fn record_apply(&self, provider: &str, succeeded: bool, secret_writes: usize) {
+        self.applies
+            .get_or_create(&RunLabels {
+                provider: provider.to_string(),
+                outcome: outcome(succeeded),
+            })
+            .inc();
+        self.secret_writes
+            .get_or_create(&ProviderLabels {
+                provider: provider.to_string(),
+            })
+            .inc_by(secret_writes as u64);
+    }
+
+    pub(crate) fn record_secret_failures(&self, provider: &str, failures: usize) {
+        if failures == 0 {
+            return;
+        }
+        self.secret_write_failures
+            .get_or_create(&ProviderLabels {
+                provider: provider.to_string(),
+            })
+            .inc_by(failures as u64);
+    }
+}
+
+impl Default for ProvisionMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for ProvisionMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProvisionMetrics").finish_non_exhaustive()
+    }
+}
+
+impl ProvisionObserver for ProvisionMetrics {
+    fn step_finished(&self, event: &StepFinished) {
+        self.step_duration
+            .get_or_create(&StepLabels {
+                provider: event.provider_id.clone(),
+                step: event.step.as_str().to_string(),
+            })
+            .observe(event.duration_ms as f64 / 1000.0);
+    }
+
+    fn run_finished(&self, event: &RunFinished) {
+        self.runs
+            .get_or_create(&RunLabels {
+                provider: event.provider_id.clone(),
+                outcome: outcome(event.failed_steps == 0),
+            })
+            .inc();
+    }
+}
+
+fn outcome(succeeded: bool) -> &'static str {
+    if succeeded { "success" } else { "failure" }
+}
+
+fn duration_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(0.001, 2.0, 14))
+}
+
+fn fuel_histogram() -> Histogram {
+    Histogram::new(exponential_buckets(1_000.0, 4.0, 12))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ProvisionStep;
+
+    #[test]
+    fn encodes_recorded_metrics() {
+        let metrics = ProvisionMetrics::new();
+        metrics.step_finished(&StepFinished {
+            provider_id: "telegram".to_string(),
+            install_id: "install".to_string(),
+            step: ProvisionStep::Apply,
+            duration_ms: 12,
+            output_bytes: 64,
+            diagnostics: 0,
+            failed: false,
+        });
+        metrics.run_finished(&RunFinished {
+            provider_id: "telegram".to_string(),
+            install_id: "install".to_string(),
+            duration_ms: 40,
+            steps: 4,
+            failed_steps: 1,
+            diagnostics: 1,
+            plan_bytes: 128,
+        });
+        metrics.record_compile(true);
+        metrics.record_apply("telegram", true, 2);
+
+        let text = metrics.encode();
+        assert!(text.contains(
+            "greentic_provision_runs_total{provider=\"telegram\",outcome=\"failure\"} 1"
+        ));
+        assert!(text.contains(
+            "greentic_provision_step_duration_seconds_count{provider=\"telegram\",step=\"apply\"} 1"
+        ));
+        assert!(text.contains("greentic_provision_compile_cache_total{result=\"hit\"} 1"));
+        assert!(text.contains("greentic_provision_secret_writes_total{provider=\"telegram\"} 2"));
+    }
+}
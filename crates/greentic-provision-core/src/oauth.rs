@@ -0,0 +1,515 @@
+//! An [`OAuthHandler`] running the OAuth 2.0 authorization-code flow with PKCE (RFC 7636).
+//!
+//! Provider endpoints come from `greentic-oauth-client` presets (`google`, `microsoft`,
+//! `github`) or are configured directly, and the token endpoint can always be overridden.
+//! `start` builds the authorize URL with an `S256` code challenge and a random `state`, and
+//! keeps the code verifier as a pending authorization. `finish` takes the code the provider
+//! redirected back with and exchanges it at the token endpoint over an [`HttpTransport`], the
+//! same extension point the service adapters use.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL;
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use greentic_oauth_client::ProviderPreset;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use url::Url;
+use url::form_urlencoded;
+use zeroize::Zeroizing;
+
+use crate::apply::{ApplyError, OAuthHandler, OAuthTokenSet, SecretsStore};
+use crate::http::{HttpRequest, HttpResponse, HttpTransport};
+use crate::secret::{SecretString, expose_secrets};
+
+/// Endpoints and client registration for one provider.
+#[derive(Debug, Clone)]
+pub struct OAuthProviderConfig {
+    authorize_url: String,
+    token_url: String,
+    client_id: String,
+    client_secret: Option<SecretString>,
+    default_scopes: Vec<String>,
+    redirect_url: Option<String>,
+    extra_params: BTreeMap<String, String>,
+}
+
+impl OAuthProviderConfig {
+    pub fn new(
+        authorize_url: impl Into<String>,
+        token_url: impl Into<String>,
+        client_id: impl Into<String>,
+    ) -> Self {
+        Self {
+            authorize_url: authorize_url.into(),
+            token_url: token_url.into(),
+            client_id: client_id.into(),
+            client_secret: None,
+            default_scopes: Vec::new(),
+            redirect_url: None,
+            extra_params: BTreeMap::new(),
+        }
+    }
+
+    /// The endpoints, default scopes, `resource` and `prompt` of a `greentic-oauth-client`
+    /// preset. Fails with `NotFound` for names without built-in endpoints.
+    pub fn preset(name: &str, client_id: impl Into<String>) -> Result<Self, ApplyError> {
+        let preset = greentic_oauth_client::providers::resolve(name);
+        if preset.authorize_url.is_empty() || preset.token_url.is_empty() {
+            return Err(ApplyError::NotFound(format!(
+                "no built-in oauth endpoints for provider {name}"
+            )));
+        }
+        Ok(Self::from_preset(preset, client_id))
+    }
+
+    pub fn from_preset(preset: ProviderPreset, client_id: impl Into<String>) -> Self {
+        let mut config = Self::new(preset.authorize_url, preset.token_url, client_id);
+        config.default_scopes = preset.default_scopes;
+        if let Some(resource) = preset.resource {
+            config.extra_params.insert("resource".to_string(), resource);
+        }
+        if let Some(prompt) = preset.prompt {
+            config.extra_params.insert("prompt".to_string(), prompt);
+        }
+        config
+    }
+
+    pub fn with_authorize_url(mut self, url: impl Into<String>) -> Self {
+        self.authorize_url = url.into();
+        self
+    }
+
+    pub fn with_token_url(mut self, url: impl Into<String>) -> Self {
+        self.token_url = url.into();
+        self
+    }
+
+    /// Where codes are redeemed. Hosts allowlist its host on the handler's transport.
+    pub fn token_url(&self) -> &str {
+        &self.token_url
+    }
+
+    /// Sent to the token endpoint for confidential clients. Public clients rely on PKCE alone.
+    pub fn with_client_secret(mut self, secret: impl Into<SecretString>) -> Self {
+        self.client_secret = Some(secret.into());
+        self
+    }
+
+    /// Used when an `OAuthOp` names no `redirect_url`.
+    pub fn with_redirect_url(mut self, url: impl Into<String>) -> Self {
+        self.redirect_url = Some(url.into());
+        self
+    }
+
+    /// Used when an `OAuthOp` asks for no scopes.
+    pub fn with_default_scopes(mut self, scopes: impl IntoIterator<Item = String>) -> Self {
+        self.default_scopes = scopes.into_iter().collect();
+        self
+    }
+
+    /// Adds a parameter to every authorize URL, e.g. `access_type=offline`.
+    pub fn with_authorize_param(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Self {
+        self.extra_params.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// A PKCE code verifier and its `S256` challenge.
+#[derive(Debug, Clone)]
+pub struct PkcePair {
+    verifier: SecretString,
+    challenge: String,
+}
+
+impl PkcePair {
+    /// A verifier of 32 random bytes, base64url-encoded to 43 characters.
+    pub fn generate() -> Self {
+        let verifier = SecretString::new(BASE64_URL.encode(random_bytes::<32>()));
+        let challenge = pkce_challenge(verifier.expose_secret());
+        Self {
+            verifier,
+            challenge,
+        }
+    }
+
+    pub fn verifier(&self) -> &SecretString {
+        &self.verifier
+    }
+
+    pub fn challenge(&self) -> &str {
+        &self.challenge
+    }
+}
+
+/// The `S256` code challenge for `verifier`: base64url of its SHA-256, without padding.
+pub fn pkce_challenge(verifier: &str) -> String {
+    BASE64_URL.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// Where to send the user to grant access.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthorizationRequest {
+    pub provider: String,
+    pub url: String,
+    /// Echoed back by the provider on the redirect, and what the authorization is finished with.
+    pub state: String,
+    pub redirect_url: String,
+}
+
+/// What the provider redirected back with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizationCallback {
+    pub code: SecretString,
+    pub state: String,
+}
+
+impl AuthorizationCallback {
+    /// Reads `code` and `state` from the redirect URL. A redirect carrying `error` (for example
+    /// `access_denied` when the user declines) fails with `PermissionDenied`.
+    pub fn from_redirect(url: &str) -> Result<Self, ApplyError> {
+        let url =
+            Url::parse(url).map_err(|err| ApplyError::Invalid(format!("oauth redirect: {err}")))?;
+        let params: BTreeMap<String, String> = url.query_pairs().into_owned().collect();
+        if let Some(error) = params.get("error") {
+            return Err(ApplyError::PermissionDenied(format!(
+                "authorization failed: {error}"
+            )));
+        }
+        match (params.get("code"), params.get("state")) {
+            (Some(code), Some(state)) => Ok(Self {
+                code: SecretString::new(code.as_str()),
+                state: state.clone(),
+            }),
+            _ => Err(ApplyError::Invalid(
+                "oauth redirect carries no code and state".to_string(),
+            )),
+        }
+    }
+}
+
+/// Secrets namespace where [`PkceOAuthHandler`] keeps one pending authorization per `state`.
+pub const PENDING_AUTHORIZATIONS_NAMESPACE: &str = "provision:oauth:pending";
+
+/// What `finish` needs from `start`. Stored as a secret because it holds the code verifier.
+#[derive(Serialize, Deserialize)]
+struct PendingAuthorization {
+    /// Secrets namespace of the install that started the authorization. Only that install can
+    /// finish it.
+    secrets_namespace: String,
+    provider: String,
+    redirect_url: String,
+    code_verifier: SecretString,
+}
+
+/// Runs the authorization-code flow with PKCE for the providers it knows. Pending
+/// authorizations live in a [`SecretsStore`], so `start` and `finish` can run in different
+/// processes.
+pub struct PkceOAuthHandler<S> {
+    providers: BTreeMap<String, OAuthProviderConfig>,
+    transport: Arc<dyn HttpTransport>,
+    pending: S,
+}
+
+impl<S: SecretsStore> PkceOAuthHandler<S> {
+    pub fn new(transport: Arc<dyn HttpTransport>, pending: S) -> Self {
+        Self {
+            providers: BTreeMap::new(),
+            transport,
+            pending,
+        }
+    }
+
+    /// Handles `OAuthOp`s whose `provider` is `name`.
+    pub fn with_provider(mut self, name: impl Into<String>, config: OAuthProviderConfig) -> Self {
+        self.providers.insert(name.into(), config);
+        self
+    }
+
+    pub fn into_pending_store(self) -> S {
+        self.pending
+    }
+
+    fn provider(&self, name: &str) -> Result<&OAuthProviderConfig, ApplyError> {
+        self.providers
+            .get(name)
+            .ok_or_else(|| ApplyError::NotFound(format!("oauth provider {name} is not configured")))
+    }
+
+    /// The authorize URL for `provider`, with a fresh state and PKCE pair.
+    fn authorization_request(
+        &self,
+        provider: &str,
+        scopes: &[String],
+        redirect_url: Option<&str>,
+    ) -> Result<(AuthorizationRequest, PkcePair), ApplyError> {
+        let config = self.provider(provider)?;
+        let redirect_url = redirect_url
+            .or(config.redirect_url.as_deref())
+            .ok_or_else(|| {
+                ApplyError::Backend(format!("oauth provider {provider} has no redirect url"))
+            })?;
+        let scopes = if scopes.is_empty() {
+            &config.default_scopes
+        } else {
+            scopes
+        };
+        let pkce = PkcePair::generate();
+        let state = BASE64_URL.encode(random_bytes::<16>());
+
+        let mut url = Url::parse(&config.authorize_url).map_err(|err| {
+            ApplyError::Backend(format!("invalid authorize url for {provider}: {err}"))
+        })?;
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &config.client_id)
+                .append_pair("redirect_uri", redirect_url)
+                .append_pair("state", &state)
+                .append_pair("code_challenge", pkce.challenge())
+                .append_pair("code_challenge_method", "S256");
+            if !scopes.is_empty() {
+                query.append_pair("scope", &scopes.join(" "));
+            }
+            for (name, value) in &config.extra_params {
+                query.append_pair(name, value);
+            }
+        }
+        let request = AuthorizationRequest {
+            provider: provider.to_string(),
+            url: url.into(),
+            state,
+            redirect_url: redirect_url.to_string(),
+        };
+        Ok((request, pkce))
+    }
+
+    /// Removes and returns the pending authorization for `state`, so it can only be finished once.
+    /// An authorization started for another install is refused and left pending.
+    fn take_pending(
+        &mut self,
+        secrets_namespace: &str,
+        state: &str,
+    ) -> Result<PendingAuthorization, ApplyError> {
+        let record = self
+            .pending
+            .get_secret(PENDING_AUTHORIZATIONS_NAMESPACE, state)?
+            .ok_or_else(|| {
+                ApplyError::NotFound(format!("no pending oauth authorization for state {state}"))
+            })?;
+        let pending: PendingAuthorization =
+            serde_json::from_str(record.expose_secret()).map_err(|err| {
+                ApplyError::Backend(format!(
+                    "pending oauth authorization for state {state} is corrupt: {err}"
+                ))
+            })?;
+        if pending.secrets_namespace != secrets_namespace {
+            return Err(ApplyError::PermissionDenied(format!(
+                "oauth authorization for state {state} was started for another install"
+            )));
+        }
+        self.pending
+            .delete_secret(PENDING_AUTHORIZATIONS_NAMESPACE, state)?;
+        Ok(pending)
+    }
+
+    /// Redeems `code` at the provider's token endpoint.
+    fn exchange(
+        &self,
+        provider: &str,
+        redirect_url: &str,
+        verifier: &SecretString,
+        code: &SecretString,
+    ) -> Result<OAuthTokenSet, ApplyError> {
+        let config = self.provider(provider)?;
+        let mut form = form_urlencoded::Serializer::new(String::new());
+        form.append_pair("grant_type", "authorization_code")
+            .append_pair("code", code.expose_secret())
+            .append_pair("redirect_uri", redirect_url)
+            .append_pair("client_id", &config.client_id)
+            .append_pair("code_verifier", verifier.expose_secret());
+        if let Some(secret) = &config.client_secret {
+            form.append_pair("client_secret", secret.expose_secret());
+        }
+        let body = Zeroizing::new(form.finish());
+        let request = HttpRequest {
+            method: "POST".to_string(),
+            url: config.token_url.clone(),
+            headers: BTreeMap::from([
+                (
+                    "content-type".to_string(),
+                    "application/x-www-form-urlencoded".to_string(),
+                ),
+                ("accept".to_string(), "application/json".to_string()),
+            ]),
+            body: Some(body.to_string()),
+        };
+        let response = self
+            .transport
+            .send(&request)
+            .map_err(|err| ApplyError::Backend(format!("POST {}: {err}", config.token_url)))?;
+        if (200..300).contains(&response.status)
+            && let Ok(tokens) = serde_json::from_str::<TokenResponse>(&response.body)
+        {
+            return Ok(OAuthTokenSet {
+                access_token: tokens.access_token,
+                refresh_token: tokens.refresh_token,
+            });
+        }
+        Err(token_error(provider, &response))
+    }
+}
+
+impl<S: SecretsStore> OAuthHandler for PkceOAuthHandler<S> {
+    fn start(
+        &mut self,
+        secrets_namespace: &str,
+        provider: &str,
+        scopes: &[String],
+        redirect_url: Option<&str>,
+    ) -> Result<Option<AuthorizationRequest>, ApplyError> {
+        let (request, pkce) = self.authorization_request(provider, scopes, redirect_url)?;
+        let pending = PendingAuthorization {
+            secrets_namespace: secrets_namespace.to_string(),
+            provider: request.provider.clone(),
+            redirect_url: request.redirect_url.clone(),
+            code_verifier: pkce.verifier().clone(),
+        };
+        let record = expose_secrets(|| serde_json::to_string(&pending))
+            .map(Zeroizing::new)
+            .map_err(|err| ApplyError::Backend(format!("pending oauth authorization: {err}")))?;
+        self.pending
+            .set_secret(PENDING_AUTHORIZATIONS_NAMESPACE, &request.state, &record)?;
+        Ok(Some(request))
+    }
+
+    fn finish(
+        &mut self,
+        secrets_namespace: &str,
+        state: &str,
+        code: &SecretString,
+    ) -> Result<OAuthTokenSet, ApplyError> {
+        let pending = self.take_pending(secrets_namespace, state)?;
+        self.exchange(
+            &pending.provider,
+            &pending.redirect_url,
+            &pending.code_verifier,
+            code,
+        )
+    }
+}
+
+impl<S> fmt::Debug for PkceOAuthHandler<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PkceOAuthHandler")
+            .field("providers", &self.providers)
+            .field("transport", &self.transport.name())
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: SecretString,
+    #[serde(default)]
+    refresh_token: Option<SecretString>,
+}
+
+#[derive(Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+/// Maps an RFC 6749 error response to an [`ApplyError`]. The body is never echoed, since a
+/// malformed success response may still carry tokens.
+fn token_error(provider: &str, response: &HttpResponse) -> ApplyError {
+    let Ok(error) = serde_json::from_str::<TokenErrorResponse>(&response.body) else {
+        let message = format!(
+            "token endpoint for {provider} returned HTTP {} without tokens",
+            response.status
+        );
+        return match response.status {
+            401 | 403 => ApplyError::PermissionDenied(message),
+            _ => ApplyError::Backend(message),
+        };
+    };
+    let message = match error.error_description {
+        Some(description) => format!("{provider}: {}: {description}", error.error),
+        None => format!("{provider}: {}", error.error),
+    };
+    match error.error.as_str() {
+        "invalid_grant" | "invalid_client" | "unauthorized_client" | "access_denied" => {
+            ApplyError::PermissionDenied(message)
+        }
+        _ => ApplyError::Backend(message),
+    }
+}
+
+fn random_bytes<const N: usize>() -> Zeroizing<[u8; N]> {
+    let mut bytes = Zeroizing::new([0u8; N]);
+    OsRng.fill_bytes(bytes.as_mut());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pkce_matches_the_rfc_7636_example() {
+        assert_eq!(
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+        let pair = PkcePair::generate();
+        assert_eq!(pair.verifier().expose_secret().len(), 43);
+        assert_eq!(
+            pair.challenge(),
+            pkce_challenge(pair.verifier().expose_secret())
+        );
+        assert!(!format!("{pair:?}").contains(pair.verifier().expose_secret()));
+    }
+
+    #[test]
+    fn presets_come_from_the_oauth_client() {
+        let config = OAuthProviderConfig::preset("microsoft", "client").unwrap();
+        assert!(config.token_url.ends_with("/oauth2/v2.0/token"));
+        assert!(
+            config
+                .default_scopes
+                .contains(&"offline_access".to_string())
+        );
+        assert_eq!(
+            config.extra_params.get("resource").map(String::as_str),
+            Some("https://graph.microsoft.com")
+        );
+        assert!(matches!(
+            OAuthProviderConfig::preset("custom", "client"),
+            Err(ApplyError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn callbacks_are_read_from_the_redirect() {
+        let callback =
+            AuthorizationCallback::from_redirect("http://127.0.0.1:8765/cb?code=abc&state=xyz")
+                .unwrap();
+        assert_eq!(callback.code.expose_secret(), "abc");
+        assert_eq!(callback.state, "xyz");
+        assert!(matches!(
+            AuthorizationCallback::from_redirect("http://127.0.0.1/cb?error=access_denied&state=x"),
+            Err(ApplyError::PermissionDenied(_))
+        ));
+    }
+}
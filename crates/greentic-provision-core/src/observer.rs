@@ -0,0 +1,203 @@
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use greentic_types::validate::{Diagnostic, Severity};
+use serde::Serialize;
+
+use crate::types::{ProvisionMode, ProvisionStep};
+
+/// Progress callbacks invoked by [`crate::ProvisionEngine`] while a run executes. Every method
+/// defaults to doing nothing so observers only implement the events they need.
+///
+/// Callbacks run inline on the engine's thread and should return quickly.
+pub trait ProvisionObserver: Send + Sync {
+    fn run_started(&self, _event: &RunStarted) {}
+    fn step_started(&self, _event: &StepStarted) {}
+    fn step_finished(&self, _event: &StepFinished) {}
+    fn plan_merged(&self, _event: &PlanMerged) {}
+    fn diagnostic_emitted(&self, _event: &DiagnosticEmitted) {}
+    fn run_finished(&self, _event: &RunFinished) {}
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RunStarted {
+    pub provider_id: String,
+    pub install_id: String,
+    pub mode: ProvisionMode,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct StepStarted {
+    pub install_id: String,
+    pub step: ProvisionStep,
+    /// Time since the run started.
+    pub offset_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct StepFinished {
+    pub provider_id: String,
+    pub install_id: String,
+    pub step: ProvisionStep,
+    pub duration_ms: u64,
+    /// Size of the serialized step output.
+    pub output_bytes: usize,
+    pub diagnostics: usize,
+    /// Whether the executor reported the step as failed.
+    pub failed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PlanMerged {
+    pub install_id: String,
+    pub step: ProvisionStep,
+    /// Size of the serialized patch merged into the plan.
+    pub patch_bytes: usize,
+    /// Size of the serialized plan after the merge.
+    pub plan_bytes: usize,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DiagnosticEmitted {
+    pub install_id: String,
+    pub step: ProvisionStep,
+    pub diagnostic: Diagnostic,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RunFinished {
+    pub provider_id: String,
+    pub install_id: String,
+    pub duration_ms: u64,
+    pub steps: usize,
+    pub failed_steps: usize,
+    pub diagnostics: usize,
+    /// Size of the serialized final plan.
+    pub plan_bytes: usize,
+}
+
+/// Appends every event as one JSON object per line, tagged with an `event` field. Write errors
+/// are ignored so a full disk never fails a provisioning run.
+pub struct JsonLinesObserver {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonLinesObserver {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    pub fn create(path: &Path) -> Result<Self, std::io::Error> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(Self::new(LineWriter::new(File::create(path)?)))
+    }
+
+    fn write(&self, event: &'static str, payload: &impl Serialize) {
+        let Ok(serde_json::Value::Object(fields)) = serde_json::to_value(payload) else {
+            return;
+        };
+        let mut line = serde_json::Map::new();
+        line.insert("event".to_string(), event.into());
+        line.extend(fields);
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = writeln!(writer, "{}", serde_json::Value::Object(line));
+    }
+}
+
+impl ProvisionObserver for JsonLinesObserver {
+    fn run_started(&self, event: &RunStarted) {
+        self.write("run_started", event);
+    }
+
+    fn step_started(&self, event: &StepStarted) {
+        self.write("step_started", event);
+    }
+
+    fn step_finished(&self, event: &StepFinished) {
+        self.write("step_finished", event);
+    }
+
+    fn plan_merged(&self, event: &PlanMerged) {
+        self.write("plan_merged", event);
+    }
+
+    fn diagnostic_emitted(&self, event: &DiagnosticEmitted) {
+        self.write("diagnostic_emitted", event);
+    }
+
+    fn run_finished(&self, event: &RunFinished) {
+        self.write("run_finished", event);
+    }
+}
+
+/// Human-readable progress lines, one per step and diagnostic.
+pub struct ProgressObserver {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl ProgressObserver {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    pub fn stderr() -> Self {
+        Self::new(std::io::stderr())
+    }
+
+    fn line(&self, line: std::fmt::Arguments<'_>) {
+        let mut writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let _ = writeln!(writer, "{line}");
+    }
+}
+
+impl ProvisionObserver for ProgressObserver {
+    fn run_started(&self, event: &RunStarted) {
+        self.line(format_args!(
+            "provisioning {} / {}",
+            event.provider_id, event.install_id
+        ));
+    }
+
+    fn step_finished(&self, event: &StepFinished) {
+        self.line(format_args!(
+            "  {} {:<8} {:>5}ms {:>7} bytes",
+            if event.failed { "x" } else { "+" },
+            event.step.as_str(),
+            event.duration_ms,
+            event.output_bytes
+        ));
+    }
+
+    fn diagnostic_emitted(&self, event: &DiagnosticEmitted) {
+        let severity = match event.diagnostic.severity {
+            Severity::Error => "error",
+            Severity::Warn => "warning",
+            Severity::Info => "info",
+        };
+        self.line(format_args!(
+            "    {severity} {}: {}",
+            event.diagnostic.code, event.diagnostic.message
+        ));
+    }
+
+    fn run_finished(&self, event: &RunFinished) {
+        self.line(format_args!(
+            "done in {}ms: {} steps, {} diagnostics, plan {} bytes",
+            event.duration_ms, event.steps, event.diagnostics, event.plan_bytes
+        ));
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A single host function call observed by a guest, with the value the host returned.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "call", rename_all = "snake_case")]
+pub enum HostCall {
+    ConfigGet {
+        key: String,
+        value: Option<Value>,
+    },
+    SecretExists {
+        key: String,
+        exists: bool,
+    },
+    HttpRequest {
+        request: String,
+        response: String,
+    },
+    ClockNowMs {
+        value: u64,
+    },
+    RandomFill {
+        bytes: Vec<u8>,
+    },
+    /// Clock reading and random seed a WASI guest's clocks and random source were pinned to.
+    WasiContext {
+        clock_ms: u64,
+        random_seed: u64,
+    },
+}
+
+impl HostCall {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HostCall::ConfigGet { .. } => "config_get",
+            HostCall::SecretExists { .. } => "secret_exists",
+            HostCall::HttpRequest { .. } => "http_request",
+            HostCall::ClockNowMs { .. } => "clock_now_ms",
+            HostCall::RandomFill { .. } => "random_fill",
+            HostCall::WasiContext { .. } => "wasi_context",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RecordedStep {
+    pub step: String,
+    pub calls: Vec<HostCall>,
+}
+
+/// Every host call made during a run, grouped by step in execution order.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct HostRecording {
+    pub steps: Vec<RecordedStep>,
+}
+
+impl HostRecording {
+    pub fn load(path: &Path) -> Result<Self, std::io::Error> {
+        let contents = std::fs::read_to_string(path)?;
+        serde_json::from_str(&contents)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), std::io::Error> {
+        let payload = serde_json::to_string_pretty(self).map_err(std::io::Error::other)?;
+        std::fs::write(path, payload)
+    }
+}
+
+/// Shared sink collecting the calls of every step an executor runs.
+#[derive(Debug, Clone, Default)]
+pub struct HostRecorder {
+    recording: Arc<Mutex<HostRecording>>,
+}
+
+impl HostRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn recording(&self) -> HostRecording {
+        lock(&self.recording).clone()
+    }
+
+    pub(crate) fn push(&self, step: RecordedStep) {
+        lock(&self.recording).steps.push(step);
+    }
+}
+
+/// Recorded steps served back to guests instead of the live stores, transport, clock and
+/// random source.
+#[derive(Debug, Clone, Default)]
+pub struct HostReplay {
+    steps: Arc<Mutex<VecDeque<RecordedStep>>>,
+    left_over: Arc<Mutex<Vec<RecordedStep>>>,
+}
+
+impl HostReplay {
+    pub fn new(recording: HostRecording) -> Self {
+        Self {
+            steps: Arc::new(Mutex::new(recording.steps.into())),
+            left_over: Arc::default(),
+        }
+    }
+
+    /// Recorded calls the replayed guests never made: the tail of every step that stopped early,
+    /// followed by the steps that never ran. Empty when the replay consumed the whole recording.
+    pub fn unconsumed(&self) -> Vec<RecordedStep> {
+        let mut unconsumed = lock(&self.left_over).clone();
+        unconsumed.extend(lock(&self.steps).iter().cloned());
+        unconsumed
+    }
+
+    pub(crate) fn leave(&self, step: RecordedStep) {
+        lock(&self.left_over).push(step);
+    }
+
+    /// Takes the calls recorded for the next run of `step`.
+    pub(crate) fn take_step(&self, step: &str) -> VecDeque<HostCall> {
+        let mut steps = lock(&self.steps);
+        steps
+            .iter()
+            .position(|recorded| recorded.step == step)
+            .and_then(|index| steps.remove(index))
+            .map(|recorded| recorded.calls.into())
+            .unwrap_or_default()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
@@ -0,0 +1,124 @@
+//! [`SecretString`], the type secret values travel in between packs, resolvers and stores.
+
+use std::cell::Cell;
+use std::fmt;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroizing;
+
+thread_local! {
+    static EXPOSED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` with secret values serializable on this thread.
+///
+/// Outside this scope, serializing a [`SecretString`] fails and a plaintext
+/// [`RedactedValue`](crate::types::RedactedValue) serializes as redacted. Use it only where the
+/// output is itself protected, such as a request to a secrets service.
+pub fn expose_secrets<T>(f: impl FnOnce() -> T) -> T {
+    struct Restore(bool);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            EXPOSED.with(|exposed| exposed.set(self.0));
+        }
+    }
+    let _restore = Restore(EXPOSED.with(|exposed| exposed.replace(true)));
+    f()
+}
+
+/// Whether the current thread is inside [`expose_secrets`].
+pub fn secrets_exposed() -> bool {
+    EXPOSED.with(Cell::get)
+}
+
+/// A secret value that is wiped from memory on drop, prints as `<redacted>` and only serializes
+/// inside [`expose_secrets`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretString(Zeroizing<String>);
+
+impl SecretString {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(Zeroizing::new(value.into()))
+    }
+
+    /// The plaintext. Call sites are where secrets leave the type, so keep them few and obvious.
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretString(<redacted>)")
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if secrets_exposed() {
+            serializer.serialize_str(self.expose_secret())
+        } else {
+            Err(serde::ser::Error::custom(
+                "secret values are only serialized inside `expose_secrets`",
+            ))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formatting_never_shows_the_value() {
+        let secret = SecretString::new("hunter2");
+        assert_eq!(format!("{secret}"), "<redacted>");
+        assert_eq!(format!("{secret:?}"), "SecretString(<redacted>)");
+        assert_eq!(
+            format!("{:?}", Some(secret.clone())),
+            "Some(SecretString(<redacted>))"
+        );
+        assert_eq!(secret.expose_secret(), "hunter2");
+    }
+
+    #[test]
+    fn serializing_requires_opt_in() {
+        let secret = SecretString::new("hunter2");
+        let err = serde_json::to_string(&secret).expect_err("not exposed");
+        assert!(err.to_string().contains("expose_secrets"));
+
+        let json = expose_secrets(|| serde_json::to_string(&secret)).expect("exposed");
+        assert_eq!(json, "\"hunter2\"");
+        assert!(!secrets_exposed());
+
+        let nested = expose_secrets(|| expose_secrets(|| 1) + i32::from(secrets_exposed()));
+        assert_eq!(nested, 2);
+
+        let back: SecretString = serde_json::from_str(&json).expect("deserialize");
+        assert_eq!(back, secret);
+    }
+}
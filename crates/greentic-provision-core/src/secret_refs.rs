@@ -0,0 +1,317 @@
+//! Secret references: plan entries that name where a secret comes from instead of carrying it.
+//!
+//! A pack sets `{"redacted": true, "ref": "answers://api_token"}` in `secrets_patch.set`, and the
+//! applier asks a [`SecretResolver`] for the value only when it applies the plan. Plans with
+//! references can be written to disk, logged and reviewed without exposing secrets.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use serde_json::Value;
+use zeroize::Zeroizing;
+
+use crate::apply::{ApplyError, SecretsStore, provision_namespace, secrets_namespace};
+use crate::secret::SecretString;
+use crate::types::ProvisionInputs;
+
+/// A parsed secret reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SecretRef {
+    /// `answers://bot/token`: a string in the setup answers, `/` separating nested fields.
+    Answers { path: Vec<String> },
+    /// `env-file://config/.env#API_TOKEN`: a variable in a dotenv-style file, relative to the
+    /// resolver's base directory.
+    EnvFile { path: PathBuf, variable: String },
+    /// `secrets://namespace/key`: a secret already held by the applier's secrets store.
+    Secrets { namespace: String, key: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SecretRefError {
+    #[error("unsupported secret reference scheme in {0}")]
+    UnsupportedScheme(String),
+    #[error("malformed secret reference {reference}: {reason}")]
+    Malformed {
+        reference: String,
+        reason: &'static str,
+    },
+}
+
+impl SecretRef {
+    pub fn parse(reference: &str) -> Result<Self, SecretRefError> {
+        let malformed = |reason| SecretRefError::Malformed {
+            reference: reference.to_string(),
+            reason,
+        };
+        let (scheme, rest) = reference
+            .split_once("://")
+            .ok_or_else(|| SecretRefError::UnsupportedScheme(reference.to_string()))?;
+        match scheme {
+            "answers" => {
+                let path: Vec<String> = rest.split('/').map(str::to_string).collect();
+                if path.iter().any(String::is_empty) {
+                    return Err(malformed("expected answers://field[/nested]"));
+                }
+                Ok(SecretRef::Answers { path })
+            }
+            "env-file" => {
+                let (path, variable) = rest
+                    .rsplit_once('#')
+                    .filter(|(path, variable)| !path.is_empty() && !variable.is_empty())
+                    .ok_or_else(|| malformed("expected env-file://path#VARIABLE"))?;
+                let path = PathBuf::from(path);
+                if !path
+                    .components()
+                    .all(|part| matches!(part, Component::Normal(_) | Component::CurDir))
+                {
+                    return Err(malformed(
+                        "env-file paths must be relative and must not use ..",
+                    ));
+                }
+                Ok(SecretRef::EnvFile {
+                    path,
+                    variable: variable.to_string(),
+                })
+            }
+            "secrets" => {
+                let (namespace, key) = rest
+                    .split_once('/')
+                    .filter(|(namespace, key)| !namespace.is_empty() && !key.is_empty())
+                    .ok_or_else(|| malformed("expected secrets://namespace/key"))?;
+                Ok(SecretRef::Secrets {
+                    namespace: namespace.to_string(),
+                    key: key.to_string(),
+                })
+            }
+            _ => Err(SecretRefError::UnsupportedScheme(reference.to_string())),
+        }
+    }
+}
+
+impl FromStr for SecretRef {
+    type Err = SecretRefError;
+
+    fn from_str(reference: &str) -> Result<Self, Self::Err> {
+        Self::parse(reference)
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretRef::Answers { path } => write!(f, "answers://{}", path.join("/")),
+            SecretRef::EnvFile { path, variable } => {
+                write!(f, "env-file://{}#{variable}", path.display())
+            }
+            SecretRef::Secrets { namespace, key } => write!(f, "secrets://{namespace}/{key}"),
+        }
+    }
+}
+
+/// What a resolver can read while the applier resolves references.
+pub struct ResolveContext<'a> {
+    pub inputs: &'a ProvisionInputs,
+    pub secrets: &'a dyn SecretsStore,
+}
+
+/// Turns a [`SecretRef`] into the secret value at apply time.
+pub trait SecretResolver {
+    fn resolve(
+        &self,
+        reference: &SecretRef,
+        ctx: &ResolveContext<'_>,
+    ) -> Result<SecretString, ApplyError>;
+}
+
+/// Resolves every built-in scheme: answers from the apply inputs, dotenv files inside a base
+/// directory, and secrets from the applier's secrets store.
+///
+/// A plan comes from a pack, so references are confined: `env-file://` only reads files inside
+/// the directory given to [`with_base_dir`](Self::with_base_dir), and is refused without one, and
+/// `secrets://` only reads the install's own secrets namespace plus the namespaces the host
+/// allows with [`with_secrets_namespace`](Self::with_secrets_namespace).
+#[derive(Debug, Clone, Default)]
+pub struct DefaultSecretResolver {
+    base_dir: Option<PathBuf>,
+    secrets_namespaces: Vec<String>,
+}
+
+impl DefaultSecretResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows `env-file://` references to files inside `dir`.
+    pub fn with_base_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.base_dir = Some(dir.into());
+        self
+    }
+
+    /// Allows `secrets://` references to `namespace` as well as the install's own.
+    pub fn with_secrets_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.secrets_namespaces.push(namespace.into());
+        self
+    }
+
+    /// The file `path` names inside the base directory, after following symlinks.
+    fn env_file_path(&self, reference: &SecretRef, path: &Path) -> Result<PathBuf, ApplyError> {
+        let base = self.base_dir.as_ref().ok_or_else(|| {
+            ApplyError::PermissionDenied(format!(
+                "secret reference {reference} needs a base directory for env files"
+            ))
+        })?;
+        let base = base.canonicalize()?;
+        let file = base.join(path).canonicalize()?;
+        if !file.starts_with(&base) {
+            return Err(ApplyError::PermissionDenied(format!(
+                "secret reference {reference} points outside the base directory"
+            )));
+        }
+        Ok(file)
+    }
+
+    fn check_secrets_namespace(
+        &self,
+        reference: &SecretRef,
+        namespace: &str,
+        inputs: &ProvisionInputs,
+    ) -> Result<(), ApplyError> {
+        let own = secrets_namespace(&provision_namespace(
+            &inputs.tenant,
+            &inputs.provider_id,
+            &inputs.install_id,
+        ));
+        if namespace == own
+            || self
+                .secrets_namespaces
+                .iter()
+                .any(|allowed| allowed == namespace)
+        {
+            Ok(())
+        } else {
+            Err(ApplyError::PermissionDenied(format!(
+                "secret reference {reference} reads a namespace this install may not read"
+            )))
+        }
+    }
+}
+
+impl SecretResolver for DefaultSecretResolver {
+    fn resolve(
+        &self,
+        reference: &SecretRef,
+        ctx: &ResolveContext<'_>,
+    ) -> Result<SecretString, ApplyError> {
+        let missing = || ApplyError::NotFound(format!("secret reference {reference} is not set"));
+        match reference {
+            SecretRef::Answers { path } => {
+                let value = path
+                    .iter()
+                    .try_fold(&ctx.inputs.answers, |value, field| value.get(field))
+                    .ok_or_else(missing)?;
+                match value {
+                    Value::String(value) => Ok(SecretString::new(value.as_str())),
+                    Value::Null => Err(missing()),
+                    _ => Err(ApplyError::Invalid(format!(
+                        "secret reference {reference} is not a string"
+                    ))),
+                }
+            }
+            SecretRef::EnvFile { path, variable } => {
+                let contents = self
+                    .env_file_path(reference, path)
+                    .and_then(|file| Ok(std::fs::read_to_string(file).map(Zeroizing::new)?))
+                    .map_err(|err| match err {
+                        ApplyError::NotFound(_) => missing(),
+                        other => other,
+                    })?;
+                parse_env_file(&contents)
+                    .remove(variable)
+                    .ok_or_else(missing)
+            }
+            SecretRef::Secrets { namespace, key } => {
+                self.check_secrets_namespace(reference, namespace, ctx.inputs)?;
+                ctx.secrets.get_secret(namespace, key)?.ok_or_else(missing)
+            }
+        }
+    }
+}
+
+/// Parses `KEY=value` lines, skipping blanks and `#` comments, allowing an `export ` prefix and
+/// stripping one layer of matching quotes.
+fn parse_env_file(contents: &str) -> BTreeMap<String, SecretString> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (name, value) = line.split_once('=')?;
+            let value = value.trim();
+            let value = ['"', '\'']
+                .into_iter()
+                .find_map(|quote| value.strip_prefix(quote)?.strip_suffix(quote))
+                .unwrap_or(value);
+            Some((name.trim().to_string(), SecretString::new(value)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_displays_each_scheme() {
+        for reference in [
+            "answers://slack/bot_token",
+            "env-file://config/.env#API_TOKEN",
+            "secrets://provision:dev:acme:unknown:slack:main:secrets/oauth/refresh",
+        ] {
+            assert_eq!(SecretRef::parse(reference).unwrap().to_string(), reference);
+        }
+        assert_eq!(
+            SecretRef::parse("secrets://ns/oauth/refresh").unwrap(),
+            SecretRef::Secrets {
+                namespace: "ns".to_string(),
+                key: "oauth/refresh".to_string(),
+            }
+        );
+        assert!(matches!(
+            SecretRef::parse("vault://kv/token"),
+            Err(SecretRefError::UnsupportedScheme(_))
+        ));
+        for reference in [
+            "env-file://.env",
+            "env-file:///etc/passwd#ROOT",
+            "env-file://../.env#TOKEN",
+            "env-file://config/../../.env#TOKEN",
+        ] {
+            assert!(
+                matches!(
+                    SecretRef::parse(reference),
+                    Err(SecretRefError::Malformed { .. })
+                ),
+                "{reference}"
+            );
+        }
+        assert!(matches!(
+            SecretRef::parse("answers://"),
+            Err(SecretRefError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn env_files_follow_dotenv_conventions() {
+        let vars = parse_env_file(
+            "# comment\n\nexport API_TOKEN=\"quoted value\"\nPLAIN = bare\nSINGLE='single'\nnot a var\n",
+        );
+        let var = |name| vars.get(name).map(SecretString::expose_secret);
+        assert_eq!(var("API_TOKEN"), Some("quoted value"));
+        assert_eq!(var("PLAIN"), Some("bare"));
+        assert_eq!(var("SINGLE"), Some("single"));
+        assert_eq!(vars.len(), 3);
+    }
+}
@@ -0,0 +1,415 @@
+//! SQLite-backed install, config and secrets stores, enabled with the `sqlite` feature.
+//!
+//! One [`SqliteStore`] owns a connection and hands out store handles that share it, so a single
+//! database file holds every install, config namespace and secret. Secret values are stored as
+//! given; protect the database file accordingly.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use rusqlite::{Connection, ErrorCode, OptionalExtension, params};
+use serde_json::Value;
+
+use crate::apply::{
+    ApplyError, ApplyFailure, ApplyMode, ApplyReport, ConfigStore, InstallStore, OAuthHandler,
+    ProviderInstallRecord, ProvisionApplier, SecretsStore,
+};
+use crate::secret::SecretString;
+use crate::types::{ProvisionInputs, ProvisionResult, TenantContext};
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how many have run.
+const MIGRATIONS: &[&str] = &[INITIAL_SCHEMA];
+
+const INITIAL_SCHEMA: &str = "
+CREATE TABLE installs (
+    tenant_key TEXT NOT NULL,
+    environment TEXT,
+    tenant TEXT,
+    team TEXT,
+    provider_id TEXT NOT NULL,
+    install_id TEXT NOT NULL,
+    record TEXT NOT NULL,
+    PRIMARY KEY (tenant_key, provider_id, install_id)
+);
+CREATE INDEX installs_by_provider ON installs (provider_id, install_id);
+CREATE INDEX installs_by_tenant ON installs (environment, tenant, team);
+CREATE TABLE config (
+    namespace TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (namespace, key)
+);
+CREATE TABLE secrets (
+    namespace TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (namespace, key)
+);
+";
+
+/// A SQLite database holding installs, config and secrets.
+///
+/// Handles returned by [`SqliteStore::install_store`], [`SqliteStore::config_store`] and
+/// [`SqliteStore::secrets_store`] share this store's connection. Separate processes, or separate
+/// `SqliteStore`s, may open the same file; SQLite serializes their writes.
+#[derive(Clone)]
+pub struct SqliteStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// Opens or creates the database at `path` and runs pending migrations.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ApplyError> {
+        let path = path.as_ref();
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Self::with_connection(conn)
+    }
+
+    pub fn open_in_memory() -> Result<Self, ApplyError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(mut conn: Connection) -> Result<Self, ApplyError> {
+        conn.busy_timeout(Duration::from_secs(5))?;
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// The number of migrations applied to the database.
+    pub fn schema_version(&self) -> Result<usize, ApplyError> {
+        Ok(schema_version(&self.lock())?)
+    }
+
+    pub fn install_store(&self) -> SqliteInstallStore {
+        SqliteInstallStore {
+            store: self.clone(),
+        }
+    }
+
+    pub fn config_store(&self) -> SqliteConfigStore {
+        SqliteConfigStore {
+            store: self.clone(),
+        }
+    }
+
+    pub fn secrets_store(&self) -> SqliteSecretsStore {
+        SqliteSecretsStore {
+            store: self.clone(),
+        }
+    }
+
+    /// Applies `result` to this database's config, secrets and install stores in one SQLite
+    /// transaction. The transaction commits only if every phase succeeds; otherwise the
+    /// applier's compensations run and the transaction is rolled back, so no partial apply is
+    /// ever visible to other connections.
+    ///
+    /// Other handles on this `SqliteStore` join the transaction while it runs; concurrent writers
+    /// should open their own `SqliteStore`.
+    pub fn apply<O: OAuthHandler>(
+        &self,
+        inputs: ProvisionInputs,
+        result: ProvisionResult,
+        mode: ApplyMode,
+        oauth_handler: O,
+    ) -> Result<ApplyReport, ApplyFailure> {
+        let begin = |phase: &'static str, error: ApplyError| ApplyFailure {
+            phase,
+            error,
+            operations: Vec::new(),
+            reverted: Vec::new(),
+        };
+        self.lock()
+            .execute_batch("BEGIN IMMEDIATE")
+            .map_err(|err| begin("begin", err.into()))?;
+
+        let mut applier = ProvisionApplier::new(
+            inputs,
+            self.config_store(),
+            self.secrets_store(),
+            oauth_handler,
+            self.install_store(),
+        );
+        match applier.apply(result, mode) {
+            Ok(report) => match self.lock().execute_batch("COMMIT") {
+                Ok(()) => Ok(report),
+                Err(err) => {
+                    let _ = self.lock().execute_batch("ROLLBACK");
+                    Err(ApplyFailure {
+                        phase: "commit",
+                        error: err.into(),
+                        operations: report.operations,
+                        reverted: Vec::new(),
+                    })
+                }
+            },
+            Err(failure) => {
+                let _ = self.lock().execute_batch("ROLLBACK");
+                Err(failure)
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.conn
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl std::fmt::Debug for SqliteStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqliteStore").finish_non_exhaustive()
+    }
+}
+
+fn schema_version(conn: &Connection) -> rusqlite::Result<usize> {
+    conn.pragma_query_value(None, "user_version", |row| row.get::<_, u32>(0))
+        .map(|version| version as usize)
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), ApplyError> {
+    let current = schema_version(conn)?;
+    if current > MIGRATIONS.len() {
+        return Err(ApplyError::Conflict(format!(
+            "database schema version {current} is newer than the supported {}",
+            MIGRATIONS.len()
+        )));
+    }
+    let tx = conn.transaction()?;
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(current) {
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", (index + 1) as u32)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+impl From<rusqlite::Error> for ApplyError {
+    fn from(err: rusqlite::Error) -> Self {
+        let message = err.to_string();
+        match err.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => {
+                ApplyError::Conflict(message)
+            }
+            Some(
+                ErrorCode::ReadOnly
+                | ErrorCode::PermissionDenied
+                | ErrorCode::AuthorizationForStatementDenied,
+            ) => ApplyError::PermissionDenied(message),
+            Some(ErrorCode::CannotOpen | ErrorCode::NotFound) => ApplyError::NotFound(message),
+            _ => ApplyError::Backend(message),
+        }
+    }
+}
+
+/// Canonical JSON of the whole tenant context, so `None` and `Some("")` stay distinct.
+fn tenant_key(tenant: &TenantContext) -> String {
+    serde_json::to_string(tenant).unwrap_or_default()
+}
+
+fn decode_record(json: String) -> rusqlite::Result<ProviderInstallRecord> {
+    serde_json::from_str(&json).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err))
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct SqliteInstallStore {
+    store: SqliteStore,
+}
+
+impl SqliteInstallStore {
+    /// Every install of `provider_id` across tenants.
+    pub fn list_by_provider(
+        &self,
+        provider_id: &str,
+    ) -> Result<Vec<ProviderInstallRecord>, ApplyError> {
+        let conn = self.store.lock();
+        let mut statement = conn.prepare_cached(
+            "SELECT record FROM installs WHERE provider_id = ?1 ORDER BY install_id",
+        )?;
+        let records = statement
+            .query_map(params![provider_id], |row| decode_record(row.get(0)?))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(records)
+    }
+}
+
+impl InstallStore for SqliteInstallStore {
+    fn get(
+        &self,
+        tenant: &TenantContext,
+        provider_id: &str,
+        install_id: &str,
+    ) -> Result<Option<ProviderInstallRecord>, ApplyError> {
+        Ok(self
+            .store
+            .lock()
+            .query_row(
+                "SELECT record FROM installs
+                 WHERE tenant_key = ?1 AND provider_id = ?2 AND install_id = ?3",
+                params![tenant_key(tenant), provider_id, install_id],
+                |row| decode_record(row.get(0)?),
+            )
+            .optional()?)
+    }
+
+    fn put(&mut self, record: ProviderInstallRecord) -> Result<(), ApplyError> {
+        let json =
+            serde_json::to_string(&record).map_err(|err| ApplyError::Backend(err.to_string()))?;
+        self.store.lock().execute(
+            "INSERT INTO installs
+                 (tenant_key, environment, tenant, team, provider_id, install_id, record)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             ON CONFLICT (tenant_key, provider_id, install_id) DO UPDATE SET record = excluded.record",
+            params![
+                tenant_key(&record.tenant),
+                record.tenant.environment,
+                record.tenant.tenant,
+                record.tenant.team,
+                record.provider_id,
+                record.install_id,
+                json,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn list(&self, tenant: &TenantContext) -> Result<Vec<ProviderInstallRecord>, ApplyError> {
+        let conn = self.store.lock();
+        let mut statement = conn.prepare_cached(
+            "SELECT record FROM installs WHERE tenant_key = ?1 ORDER BY provider_id, install_id",
+        )?;
+        let records = statement
+            .query_map(params![tenant_key(tenant)], |row| {
+                decode_record(row.get(0)?)
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(records)
+    }
+
+    fn delete(
+        &mut self,
+        tenant: &TenantContext,
+        provider_id: &str,
+        install_id: &str,
+    ) -> Result<bool, ApplyError> {
+        let removed = self.store.lock().execute(
+            "DELETE FROM installs WHERE tenant_key = ?1 AND provider_id = ?2 AND install_id = ?3",
+            params![tenant_key(tenant), provider_id, install_id],
+        )?;
+        Ok(removed > 0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SqliteConfigStore {
+    store: SqliteStore,
+}
+
+impl ConfigStore for SqliteConfigStore {
+    fn apply_patch(
+        &mut self,
+        namespace: &str,
+        patch: &BTreeMap<String, Value>,
+    ) -> Result<Vec<String>, ApplyError> {
+        let conn = self.store.lock();
+        let mut statement = conn.prepare_cached(
+            "INSERT INTO config (namespace, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (namespace, key) DO UPDATE SET value = excluded.value",
+        )?;
+        for (key, value) in patch {
+            statement.execute(params![namespace, key, value.to_string()])?;
+        }
+        Ok(patch.keys().cloned().collect())
+    }
+
+    fn remove_keys(&mut self, namespace: &str, keys: &[String]) -> Result<(), ApplyError> {
+        let conn = self.store.lock();
+        let mut statement =
+            conn.prepare_cached("DELETE FROM config WHERE namespace = ?1 AND key = ?2")?;
+        for key in keys {
+            statement.execute(params![namespace, key])?;
+        }
+        Ok(())
+    }
+
+    fn read_namespace(&self, namespace: &str) -> Result<BTreeMap<String, Value>, ApplyError> {
+        let conn = self.store.lock();
+        let mut statement =
+            conn.prepare_cached("SELECT key, value FROM config WHERE namespace = ?1")?;
+        let rows = statement
+            .query_map(params![namespace], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter()
+            .map(|(key, value)| {
+                let value = serde_json::from_str(&value).map_err(|err| {
+                    ApplyError::Backend(format!("config {namespace}/{key} is corrupt: {err}"))
+                })?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SqliteSecretsStore {
+    store: SqliteStore,
+}
+
+impl SecretsStore for SqliteSecretsStore {
+    fn set_secret(&mut self, namespace: &str, key: &str, value: &str) -> Result<(), ApplyError> {
+        self.store.lock().execute(
+            "INSERT INTO secrets (namespace, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (namespace, key) DO UPDATE SET value = excluded.value",
+            params![namespace, key, value],
+        )?;
+        Ok(())
+    }
+
+    fn delete_secret(&mut self, namespace: &str, key: &str) -> Result<(), ApplyError> {
+        self.store.lock().execute(
+            "DELETE FROM secrets WHERE namespace = ?1 AND key = ?2",
+            params![namespace, key],
+        )?;
+        Ok(())
+    }
+
+    fn get_secret(&self, namespace: &str, key: &str) -> Result<Option<SecretString>, ApplyError> {
+        Ok(self
+            .store
+            .lock()
+            .query_row(
+                "SELECT value FROM secrets WHERE namespace = ?1 AND key = ?2",
+                params![namespace, key],
+                |row| row.get::<_, String>(0).map(SecretString::from),
+            )
+            .optional()?)
+    }
+
+    fn list_keys(&self, namespace: &str) -> Vec<String> {
+        let conn = self.store.lock();
+        let Ok(mut statement) =
+            conn.prepare_cached("SELECT key FROM secrets WHERE namespace = ?1 ORDER BY key")
+        else {
+            return Vec::new();
+        };
+        statement
+            .query_map(params![namespace], |row| row.get(0))
+            .and_then(|rows| rows.collect())
+            .unwrap_or_default()
+    }
+}
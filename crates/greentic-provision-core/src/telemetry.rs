@@ -0,0 +1,79 @@
+//! Spans emitted when the `tracing` feature is enabled. Without it every span is a no-op, so call
+//! sites stay free of `cfg` attributes.
+//!
+//! Spans only carry identifiers (tenant, provider, install, step, phase), never answers, config
+//! values or secrets.
+
+use std::future::Future;
+
+use crate::types::ProvisionInputs;
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+#[cfg(not(feature = "tracing"))]
+#[derive(Debug, Clone)]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn entered(self) -> Self {
+        self
+    }
+}
+
+#[cfg(feature = "tracing")]
+macro_rules! install_span {
+    ($name:literal, $inputs:expr $(, $key:ident = $value:expr)*) => {{
+        let inputs: &ProvisionInputs = $inputs;
+        tracing::info_span!(
+            $name,
+            tenant = inputs.tenant.tenant.as_deref().unwrap_or("unknown"),
+            environment = inputs.tenant.environment.as_deref().unwrap_or("unknown"),
+            provider_id = inputs.provider_id.as_str(),
+            install_id = inputs.install_id.as_str(),
+            $($key = $value),*
+        )
+    }};
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! install_span {
+    ($name:literal, $inputs:expr $(, $key:ident = $value:expr)*) => {{
+        let _: &ProvisionInputs = $inputs;
+        $(let _ = $value;)*
+        Span
+    }};
+}
+
+pub(crate) fn run(inputs: &ProvisionInputs, mode: &'static str) -> Span {
+    install_span!("provision.run", inputs, mode = mode)
+}
+
+pub(crate) fn step(inputs: &ProvisionInputs, step: &str) -> Span {
+    install_span!("provision.step", inputs, step = step)
+}
+
+pub(crate) fn compile(inputs: &ProvisionInputs, step: &str) -> Span {
+    install_span!("wasm.compile", inputs, step = step)
+}
+
+pub(crate) fn instantiate(inputs: &ProvisionInputs, step: &str) -> Span {
+    install_span!("wasm.instantiate", inputs, step = step)
+}
+
+/// One applier phase: `config`, `secrets`, `oauth`, `subscriptions`, `install_record` or
+/// `rollback`.
+pub(crate) fn apply_phase(inputs: &ProvisionInputs, phase: &'static str) -> Span {
+    install_span!("provision.apply", inputs, phase = phase)
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) fn instrument<F: Future>(future: F, span: Span) -> impl Future<Output = F::Output> {
+    tracing::Instrument::instrument(future, span)
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn instrument<F: Future>(future: F, _span: Span) -> F {
+    future
+}
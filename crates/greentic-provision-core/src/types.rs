@@ -1,9 +1,12 @@
 use std::collections::BTreeMap;
 
 use greentic_types::validate::Diagnostic;
-use serde::{Deserialize, Serialize};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Value;
 
+use crate::secret::{SecretString, secrets_exposed};
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum ProvisionMode {
@@ -13,6 +16,17 @@ pub enum ProvisionMode {
     DryRun,
 }
 
+impl ProvisionMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProvisionMode::Install => "install",
+            ProvisionMode::Update => "update",
+            ProvisionMode::Delete => "delete",
+            ProvisionMode::DryRun => "dry_run",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum ProvisionStep {
@@ -22,6 +36,17 @@ pub enum ProvisionStep {
     Summary,
 }
 
+impl ProvisionStep {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProvisionStep::Collect => "collect",
+            ProvisionStep::Validate => "validate",
+            ProvisionStep::Apply => "apply",
+            ProvisionStep::Summary => "summary",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
 pub struct TenantContext {
     pub environment: Option<String>,
@@ -56,10 +81,33 @@ pub struct SecretsPatch {
     pub delete: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+/// A secrets patch entry.
+///
+/// A plaintext `value` only serializes inside [`expose_secrets`](crate::secret::expose_secrets).
+/// Elsewhere it serializes as
+/// `{"redacted": true, "value": null}`, so a plan written to disk or stdout never carries it.
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct RedactedValue {
     pub redacted: bool,
-    pub value: Option<String>,
+    pub value: Option<SecretString>,
+    /// Where the applier reads the value at apply time, e.g. `answers://api_token`. See
+    /// [`SecretRef`](crate::secret_refs::SecretRef).
+    #[serde(default, rename = "ref")]
+    pub reference: Option<String>,
+}
+
+impl Serialize for RedactedValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = self.value.as_ref().filter(|_| secrets_exposed());
+        let fields = if self.reference.is_some() { 3 } else { 2 };
+        let mut state = serializer.serialize_struct("RedactedValue", fields)?;
+        state.serialize_field("redacted", &(self.redacted || value.is_none()))?;
+        state.serialize_field("value", &value)?;
+        if let Some(reference) = &self.reference {
+            state.serialize_field("ref", reference)?;
+        }
+        state.end()
+    }
 }
 
 impl RedactedValue {
@@ -67,13 +115,24 @@ impl RedactedValue {
         Self {
             redacted: true,
             value: None,
+            reference: None,
         }
     }
 
     pub fn plaintext(value: impl Into<String>) -> Self {
         Self {
             redacted: false,
-            value: Some(value.into()),
+            value: Some(SecretString::new(value)),
+            reference: None,
+        }
+    }
+
+    /// A redacted value the applier resolves from `reference` when the plan is applied.
+    pub fn reference(reference: impl Into<String>) -> Self {
+        Self {
+            redacted: true,
+            value: None,
+            reference: Some(reference.into()),
         }
     }
 }
@@ -132,11 +191,15 @@ impl ProvisionPlan {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "op", rename_all = "snake_case")]
 pub enum OAuthOp {
+    /// Sends the user to the provider. Applying it reports an authorize URL and its `state`.
     Start {
         provider: String,
         scopes: Vec<String>,
         redirect_url: Option<String>,
     },
+    /// Completes the authorization started with `state`, using the `code` the provider
+    /// redirected back with. The code is usually a reference such as `answers://oauth_code`.
+    Finish { state: String, code: RedactedValue },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -158,6 +221,13 @@ pub struct StepOutput {
     pub diagnostics: Vec<Diagnostic>,
     pub plan_patch: Option<ProvisionPlanPatch>,
     pub questions: Option<Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<HostLogEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub console: Option<ConsoleOutput>,
+    /// Component layout the executor resolved for this step.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layout: Option<StepLayout>,
 }
 
 impl Default for StepOutput {
@@ -167,10 +237,78 @@ impl Default for StepOutput {
             diagnostics: Vec::new(),
             plan_patch: None,
             questions: None,
+            logs: Vec::new(),
+            console: None,
+            layout: None,
         }
     }
 }
 
+/// How a pack lays out the components behind its setup flow.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepLayout {
+    /// One `setup_default__{step}` component per step.
+    PerStep,
+    /// A single `setup_default` component that dispatches on the `step` input field.
+    SingleComponent,
+}
+
+impl StepLayout {
+    /// Component file stem the layout expects for `step_name`.
+    pub fn component_name(self, step_name: &str) -> String {
+        match self {
+            StepLayout::PerStep => format!("setup_default__{step_name}"),
+            StepLayout::SingleComponent => "setup_default".to_string(),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StepLayout::PerStep => "per_step",
+            StepLayout::SingleComponent => "single_component",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    /// Maps the raw level passed by guests (`0` = trace .. `4` = error); out of range values
+    /// clamp to the nearest level.
+    pub fn from_raw(level: i32) -> Self {
+        match level {
+            i32::MIN..=0 => LogLevel::Trace,
+            1 => LogLevel::Debug,
+            2 => LogLevel::Info,
+            3 => LogLevel::Warn,
+            _ => LogLevel::Error,
+        }
+    }
+}
+
+/// Log line emitted by a pack step through the `log` host function.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HostLogEntry {
+    pub level: LogLevel,
+    pub data: Value,
+}
+
+/// stdout/stderr captured from a WASI guest.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct ConsoleOutput {
+    pub stdout: String,
+    pub stderr: String,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -187,4 +325,28 @@ mod tests {
         let zeta_pos = serialized.find("\"zeta\"").expect("missing zeta");
         assert!(alpha_pos < zeta_pos, "expected deterministic key ordering");
     }
+
+    #[test]
+    fn plaintext_secrets_serialize_redacted_unless_exposed() {
+        let mut plan = ProvisionPlan::default();
+        plan.secrets_patch
+            .set
+            .insert("token".to_string(), RedactedValue::plaintext("hunter2"));
+
+        let serialized = serde_json::to_value(&plan).expect("serialize plan");
+        assert_eq!(
+            serialized["secrets_patch"]["set"]["token"],
+            serde_json::json!({"redacted": true, "value": null})
+        );
+        assert!(!format!("{plan:?}").contains("hunter2"));
+
+        let exposed = crate::secret::expose_secrets(|| serde_json::to_value(&plan))
+            .expect("serialize exposed plan");
+        assert_eq!(
+            exposed["secrets_patch"]["set"]["token"],
+            serde_json::json!({"redacted": false, "value": "hunter2"})
+        );
+        let back: ProvisionPlan = serde_json::from_value(exposed).expect("deserialize plan");
+        assert_eq!(back, plan);
+    }
 }
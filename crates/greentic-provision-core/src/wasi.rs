@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use wasmtime_wasi::p1::WasiP1Ctx;
+use wasmtime_wasi::p2::pipe::MemoryOutputPipe;
+use wasmtime_wasi::{Deterministic, HostMonotonicClock, HostWallClock, WasiCtxBuilder};
+
+use crate::host::splitmix64;
+use crate::types::ConsoleOutput;
+
+/// Import module used by core modules built for `wasm32-wasip1`.
+pub const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+/// Opt-in WASI support for pack steps built with standard toolchains: `wasi_snapshot_preview1`
+/// for core modules and the WASI preview 2 interfaces for components.
+///
+/// Guests get no preopened directories, no environment or arguments and no network. Both clocks
+/// are pinned for the whole step to one reading of the host's
+/// [`HostClock`](crate::HostClock) and randomness comes from the host's random seed. The reading
+/// and the seed are recorded as a host call, so replays see the same time and bytes even when the
+/// recording was made with [`HostClock::System`](crate::HostClock::System).
+#[derive(Debug, Clone)]
+pub struct WasiOptions {
+    /// Maximum bytes captured per stream and step; writing past it traps the guest.
+    pub capture_limit: usize,
+}
+
+impl Default for WasiOptions {
+    fn default() -> Self {
+        Self {
+            capture_limit: 64 * 1024,
+        }
+    }
+}
+
+pub(crate) struct WasiState {
+    ctx: WasiP1Ctx,
+    stdout: MemoryOutputPipe,
+    stderr: MemoryOutputPipe,
+}
+
+impl WasiState {
+    pub(crate) fn new(options: &WasiOptions, clock_ms: u64, random_seed: u64) -> Self {
+        let stdout = MemoryOutputPipe::new(options.capture_limit);
+        let stderr = MemoryOutputPipe::new(options.capture_limit);
+
+        let mut seed = random_seed;
+        let random_bytes: Vec<u8> = (0..512)
+            .flat_map(|_| splitmix64(&mut seed).to_le_bytes())
+            .collect();
+
+        let mut builder = WasiCtxBuilder::new();
+        builder
+            .stdout(stdout.clone())
+            .stderr(stderr.clone())
+            .allow_tcp(false)
+            .allow_udp(false)
+            .allow_ip_name_lookup(false)
+            .secure_random(Deterministic::new(random_bytes.clone()))
+            .insecure_random(Deterministic::new(random_bytes))
+            .insecure_random_seed(u128::from(random_seed))
+            .wall_clock(FixedClock(clock_ms))
+            .monotonic_clock(FixedClock(clock_ms));
+
+        Self {
+            ctx: builder.build_p1(),
+            stdout,
+            stderr,
+        }
+    }
+
+    pub(crate) fn ctx(&mut self) -> &mut WasiP1Ctx {
+        &mut self.ctx
+    }
+
+    pub(crate) fn console(&self) -> ConsoleOutput {
+        ConsoleOutput {
+            stdout: String::from_utf8_lossy(&self.stdout.contents()).into_owned(),
+            stderr: String::from_utf8_lossy(&self.stderr.contents()).into_owned(),
+        }
+    }
+}
+
+/// Clock pinned to a unix timestamp in milliseconds.
+struct FixedClock(u64);
+
+impl HostWallClock for FixedClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_millis(1)
+    }
+
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.0)
+    }
+}
+
+impl HostMonotonicClock for FixedClock {
+    fn resolution(&self) -> u64 {
+        1_000_000
+    }
+
+    fn now(&self) -> u64 {
+        self.0.saturating_mul(1_000_000)
+    }
+}
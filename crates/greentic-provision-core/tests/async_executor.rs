@@ -0,0 +1,90 @@
+mod common;
+
+use std::time::{Duration, Instant};
+
+use greentic_provision_core::{ExecutionLimits, ProvisionEngine, ProvisionMode, WasmtimeExecutor};
+use serde_json::Value;
+
+const SPIN_GUEST: &str = r#"(module
+  (memory (export "memory") 1)
+  (func (export "run") (param i32 i32) (result i32 i32)
+    (loop $spin (br $spin))
+    (i32.const 0)
+    (i32.const 0)))"#;
+
+fn spin_executor(timeout_ms: u64, fuel: u64) -> (tempfile::TempDir, WasmtimeExecutor) {
+    let pack = tempfile::tempdir().expect("tempdir");
+    std::fs::write(pack.path().join("setup_default.wat"), SPIN_GUEST).expect("write guest");
+    let limits = ExecutionLimits {
+        timeout_ms,
+        fuel,
+        ..ExecutionLimits::default()
+    };
+    let executor = WasmtimeExecutor::new(pack.path(), limits).expect("failed to create executor");
+    (pack, executor)
+}
+
+#[tokio::test]
+async fn async_engine_matches_sync_engine() {
+    let executor = WasmtimeExecutor::new(common::fixture_pack(), ExecutionLimits::default())
+        .expect("failed to create executor");
+    let engine = ProvisionEngine::new(executor);
+
+    let async_result = engine
+        .run_async(ProvisionMode::DryRun, common::inputs("noop-provision"))
+        .await;
+    let sync_result = engine.run(ProvisionMode::DryRun, common::inputs("noop-provision"));
+    assert_eq!(async_result, sync_result);
+    assert_eq!(
+        async_result.plan.config_patch.get("foo"),
+        Some(&Value::String("bar".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn runaway_guest_times_out() {
+    let (_pack, executor) = spin_executor(50, u64::MAX);
+    let result = ProvisionEngine::new(executor)
+        .run_async(ProvisionMode::DryRun, common::inputs("noop-provision"))
+        .await;
+
+    let step_results = result.step_results.expect("missing step results");
+    assert_eq!(step_results.len(), 4);
+    assert!(
+        step_results[0].output.data["error"]
+            .as_str()
+            .expect("error")
+            .contains("step timed out after 50ms")
+    );
+}
+
+#[tokio::test]
+async fn runaway_guest_runs_out_of_fuel() {
+    let (_pack, executor) = spin_executor(60_000, 10_000);
+    let result = ProvisionEngine::new(executor)
+        .run_async(ProvisionMode::DryRun, common::inputs("noop-provision"))
+        .await;
+
+    let step_results = result.step_results.expect("missing step results");
+    assert!(
+        step_results[0].output.data["error"]
+            .as_str()
+            .expect("error")
+            .contains("step ran out of fuel after 10000 units")
+    );
+}
+
+#[tokio::test]
+async fn dropping_the_future_cancels_the_guest() {
+    let (_pack, executor) = spin_executor(60_000, u64::MAX);
+    let engine = ProvisionEngine::new(executor);
+
+    let started = Instant::now();
+    let outcome = tokio::time::timeout(
+        Duration::from_millis(100),
+        engine.run_async(ProvisionMode::DryRun, common::inputs("noop-provision")),
+    )
+    .await;
+    assert!(outcome.is_err());
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
@@ -0,0 +1,193 @@
+mod common;
+
+use greentic_provision_core::{
+    BatchOptions, ExecutionLimits, PoolingOptions, ProvisionContext, ProvisionEngine,
+    ProvisionExecutor, ProvisionMode, ProvisionStep, StepOutput, WasmtimeExecutor,
+};
+use serde_json::Value;
+use tempfile::TempDir;
+
+fn guest_pack(wat: &str) -> TempDir {
+    let dir = tempfile::tempdir().expect("tempdir");
+    std::fs::write(dir.path().join("setup_default.wat"), wat).expect("write guest");
+    dir
+}
+
+fn step_error(result: &greentic_provision_core::ProvisionResult) -> String {
+    let step_results = result.step_results.as_ref().expect("missing step results");
+    step_results[0].output.data["error"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[test]
+fn run_many_provisions_installs_concurrently() {
+    let executor = WasmtimeExecutor::new(common::fixture_pack(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_pooling(PoolingOptions::default())
+        .expect("pooling engine");
+    let engine = ProvisionEngine::new(executor);
+
+    let installs: Vec<_> = (0..12)
+        .map(|index| common::install_inputs("noop-provision", &format!("install-{index}")))
+        .collect();
+    let batch = engine.run_many(
+        ProvisionMode::DryRun,
+        installs,
+        &BatchOptions { concurrency: 4 },
+    );
+
+    assert_eq!(batch.runs.len(), 12);
+    for (index, run) in batch.runs.iter().enumerate() {
+        assert_eq!(run.install_id, format!("install-{index}"));
+        assert_eq!(
+            run.result.plan.config_patch.get("foo"),
+            Some(&Value::String("bar".to_string()))
+        );
+    }
+    assert!(batch.busy() >= batch.slowest().expect("slowest run").elapsed);
+}
+
+#[test]
+fn run_many_stays_within_the_instance_pool() {
+    // Busy long enough that every worker's step overlaps the others.
+    let pack = guest_pack(
+        r#"(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "{}")
+  (func (export "run") (param i32 i32) (result i32 i32)
+    (local $n i32)
+    (local.set $n (i32.const 2000000))
+    (loop $spin
+      (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+      (br_if $spin (local.get $n)))
+    (i32.const 0)
+    (i32.const 2)))"#,
+    );
+    let limits = ExecutionLimits {
+        timeout_ms: 10_000,
+        ..ExecutionLimits::default()
+    };
+    let executor = WasmtimeExecutor::new(pack.path(), limits)
+        .expect("failed to create executor")
+        .with_pooling(PoolingOptions {
+            max_instances: 2,
+            ..PoolingOptions::default()
+        })
+        .expect("pooling engine");
+    let engine = ProvisionEngine::new(executor);
+
+    let installs: Vec<_> = (0..8)
+        .map(|index| common::install_inputs("noop-provision", &format!("install-{index}")))
+        .collect();
+    let batch = engine.run_many(
+        ProvisionMode::DryRun,
+        installs,
+        &BatchOptions { concurrency: 8 },
+    );
+
+    assert_eq!(batch.runs.len(), 8);
+    for run in &batch.runs {
+        assert_eq!(step_error(&run.result), "", "{}", run.install_id);
+    }
+}
+
+/// Panics while provisioning the `boom` install.
+struct PanickingExecutor;
+
+impl ProvisionExecutor for PanickingExecutor {
+    fn run_step(&self, _step: ProvisionStep, ctx: &ProvisionContext) -> StepOutput {
+        if ctx.inputs.install_id == "boom" {
+            panic!("executor bug");
+        }
+        StepOutput::default()
+    }
+}
+
+#[test]
+fn run_many_reports_a_panicking_run_as_that_install_failing() {
+    let engine = ProvisionEngine::new(PanickingExecutor);
+    let batch = engine.run_many(
+        ProvisionMode::DryRun,
+        vec![
+            common::install_inputs("noop-provision", "before"),
+            common::install_inputs("noop-provision", "boom"),
+            common::install_inputs("noop-provision", "after"),
+        ],
+        &BatchOptions { concurrency: 2 },
+    );
+
+    assert_eq!(batch.runs.len(), 3);
+    assert!(batch.runs[0].result.diagnostics.is_empty());
+    assert!(batch.runs[2].result.diagnostics.is_empty());
+    let failed = &batch.runs[1];
+    assert_eq!(failed.install_id, "boom");
+    assert!(failed.result.step_results.is_none());
+    let diagnostic = &failed.result.diagnostics[0];
+    assert_eq!(diagnostic.code, "PROVISION_RUN_PANICKED");
+    assert!(diagnostic.message.contains("executor bug"));
+}
+
+#[test]
+fn pooled_instances_respect_per_instance_memory_limit() {
+    // 256 pages is 16MiB, twice the default per-instance limit.
+    let pack = guest_pack(
+        r#"(module
+  (memory (export "memory") 256)
+  (func (export "run") (param i32 i32) (result i32 i32)
+    (i32.const 0)
+    (i32.const 0)))"#,
+    );
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_pooling(PoolingOptions::default())
+        .expect("pooling engine");
+    let result = ProvisionEngine::new(executor).run(
+        ProvisionMode::DryRun,
+        common::install_inputs("noop-provision", "install"),
+    );
+
+    assert!(step_error(&result).contains("memory"));
+}
+
+#[tokio::test]
+async fn exhausted_memory_pool_fails_instantiation() {
+    let pack = guest_pack(
+        r#"(module
+  (memory (export "memory") 1)
+  (func (export "run") (param i32 i32) (result i32 i32)
+    (loop $spin (br $spin))
+    (i32.const 0)
+    (i32.const 0)))"#,
+    );
+    let limits = ExecutionLimits {
+        timeout_ms: 200,
+        fuel: u64::MAX,
+        ..ExecutionLimits::default()
+    };
+    // Room for exactly one instance's memory across the pool.
+    let pooling = PoolingOptions {
+        max_instances: 4,
+        total_memory_bytes: limits.memory_limit_bytes,
+    };
+    let executor = WasmtimeExecutor::new(pack.path(), limits)
+        .expect("failed to create executor")
+        .with_pooling(pooling)
+        .expect("pooling engine");
+    let engine = ProvisionEngine::new(executor);
+
+    let (first, second) = tokio::join!(
+        engine.run_async(
+            ProvisionMode::DryRun,
+            common::install_inputs("noop-provision", "first")
+        ),
+        engine.run_async(
+            ProvisionMode::DryRun,
+            common::install_inputs("noop-provision", "second")
+        ),
+    );
+
+    assert!(step_error(&first).contains("timed out"));
+    assert!(step_error(&second).contains("concurrent"));
+}
@@ -0,0 +1,43 @@
+//! Fixtures shared by the integration tests.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+
+use greentic_provision_core::{ProvisionInputs, ProvisionPlan, ProvisionResult, TenantContext};
+use serde_json::Value;
+
+/// Secrets namespace of `inputs("provider")`.
+pub const SECRETS_NAMESPACE: &str = "provision:unknown:unknown:unknown:provider:install:secrets";
+
+/// The `noop-provision` pack under the workspace's `tests/fixtures/packs`.
+pub fn fixture_pack() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("..")
+        .join("..")
+        .join("tests/fixtures/packs/noop-provision.gtpack")
+}
+
+/// Inputs for the `install` install of `provider_id` in the default tenant, without answers.
+pub fn inputs(provider_id: &str) -> ProvisionInputs {
+    install_inputs(provider_id, "install")
+}
+
+pub fn install_inputs(provider_id: &str, install_id: &str) -> ProvisionInputs {
+    ProvisionInputs {
+        tenant: TenantContext::default(),
+        provider_id: provider_id.to_string(),
+        install_id: install_id.to_string(),
+        public_base_url: None,
+        answers: Value::Object(serde_json::Map::new()),
+        existing_state: None,
+    }
+}
+
+/// An engine result carrying only `plan`, as handed to the applier.
+pub fn result(plan: ProvisionPlan) -> ProvisionResult {
+    ProvisionResult {
+        plan,
+        diagnostics: Vec::new(),
+        step_results: None,
+    }
+}
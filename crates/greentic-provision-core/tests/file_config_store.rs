@@ -0,0 +1,150 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::thread;
+
+use greentic_provision_core::{
+    ApplyMode, ConfigStore, FileConfigStore, InMemoryInstallStore, InMemorySecretsStore,
+    InstallStore, NoopOAuthHandler, ProvisionApplier, ProvisionPlan, TenantContext,
+};
+use serde_json::{Value, json};
+
+fn patch(entries: &[(&str, Value)]) -> BTreeMap<String, Value> {
+    entries
+        .iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect()
+}
+
+#[test]
+fn namespaces_are_separate_documents() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut store = FileConfigStore::new(dir.path().join("config"));
+    let first = "provision:dev:acme:unknown:slack:main";
+    let second = "provision:dev:acme:unknown:slack:main.v2";
+
+    store
+        .apply_patch(first, &patch(&[("channel", json!("#ops"))]))
+        .expect("patch");
+    store
+        .apply_patch(second, &patch(&[("channel", json!("#dev"))]))
+        .expect("patch");
+
+    let path = store.namespace_path(first);
+    assert_eq!(
+        path.file_name().and_then(|name| name.to_str()),
+        Some("provision.dev.acme.unknown.slack.main.json")
+    );
+    assert_ne!(path, store.namespace_path(second));
+    let on_disk: Value =
+        serde_json::from_str(&std::fs::read_to_string(&path).expect("document")).expect("json");
+    assert_eq!(on_disk, json!({"channel": "#ops"}));
+
+    // A fresh store over the same directory sees the persisted values.
+    let reopened = FileConfigStore::new(dir.path().join("config"));
+    assert_eq!(
+        reopened
+            .read_namespace(second)
+            .expect("read config")
+            .get("channel"),
+        Some(&json!("#dev"))
+    );
+    assert!(
+        reopened
+            .read_namespace("provision:missing")
+            .expect("read config")
+            .is_empty()
+    );
+}
+
+#[test]
+fn removing_every_key_removes_the_document() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let mut store = FileConfigStore::new(dir.path());
+    store
+        .apply_patch("ns", &patch(&[("a", json!(1)), ("b", json!(2))]))
+        .expect("patch");
+
+    store.remove_keys("ns", &["a".to_string()]).expect("remove");
+    assert_eq!(
+        store.read_namespace("ns").expect("read config"),
+        patch(&[("b", json!(2))])
+    );
+
+    store.remove_keys("ns", &["b".to_string()]).expect("remove");
+    assert!(!store.namespace_path("ns").exists());
+    assert!(
+        !store
+            .namespace_path("ns")
+            .with_extension("json.tmp")
+            .exists()
+    );
+}
+
+#[test]
+fn concurrent_writers_keep_every_key() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let writers: Vec<_> = (0..8)
+        .map(|writer| {
+            let dir = dir.path().to_path_buf();
+            thread::spawn(move || {
+                let mut store = FileConfigStore::new(dir);
+                for key in 0..20 {
+                    store
+                        .apply_patch(
+                            "shared",
+                            &patch(&[(&format!("{writer}-{key}"), json!(key))]),
+                        )
+                        .expect("patch");
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().expect("writer");
+    }
+
+    assert_eq!(
+        FileConfigStore::new(dir.path())
+            .read_namespace("shared")
+            .expect("read config")
+            .len(),
+        160
+    );
+}
+
+#[test]
+fn applier_persists_config_to_files() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let inputs = common::inputs("provider");
+    let mut plan = ProvisionPlan::default();
+    plan.config_patch
+        .insert("webhook_url".to_string(), json!("https://example.invalid"));
+    let result = common::result(plan);
+
+    let mut applier = ProvisionApplier::new(
+        inputs,
+        FileConfigStore::new(dir.path()),
+        InMemorySecretsStore::default(),
+        NoopOAuthHandler,
+        InMemoryInstallStore::default(),
+    );
+    let report = applier.apply(result, ApplyMode::Apply).expect("apply");
+    let (_config, _secrets, _oauth, installs) = applier.into_parts();
+    assert_eq!(
+        installs
+            .list(&TenantContext::default())
+            .expect("list")
+            .len(),
+        1
+    );
+
+    let reopened = FileConfigStore::new(dir.path());
+    assert_eq!(
+        reopened
+            .read_namespace(&report.install_record.config_namespace)
+            .expect("read config")
+            .get("webhook_url"),
+        Some(&json!("https://example.invalid"))
+    );
+}
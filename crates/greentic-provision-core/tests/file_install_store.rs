@@ -0,0 +1,122 @@
+use std::thread;
+
+use greentic_provision_core::{
+    ApplyErrorKind, FileInstallStore, InstallStore, ProviderInstallRecord, TenantContext,
+};
+
+fn record(provider_id: &str, install_id: &str) -> ProviderInstallRecord {
+    ProviderInstallRecord {
+        tenant: TenantContext::default(),
+        provider_id: provider_id.to_string(),
+        install_id: install_id.to_string(),
+        config_namespace: format!("config:{install_id}"),
+        secrets_namespace: format!("secrets:{install_id}"),
+        subscriptions: Vec::new(),
+    }
+}
+
+#[test]
+fn concurrent_writers_keep_every_record() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("provision").join("installs.json");
+
+    // Each writer opens its own store, as separate CLI invocations would.
+    let writers: Vec<_> = (0..8)
+        .map(|writer| {
+            let path = path.clone();
+            thread::spawn(move || {
+                let mut store = FileInstallStore::new(&path).expect("store");
+                for install in 0..25 {
+                    store
+                        .put(record(&format!("provider-{writer}"), &format!("{install}")))
+                        .expect("put");
+                }
+                store
+                    .delete(
+                        &TenantContext::default(),
+                        &format!("provider-{writer}"),
+                        "0",
+                    )
+                    .expect("delete");
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().expect("writer thread");
+    }
+
+    let store = FileInstallStore::new(&path).expect("reopen");
+    let records = store.list(&TenantContext::default()).expect("list");
+    assert_eq!(records.len(), 8 * 24);
+    assert!(
+        store
+            .get(&TenantContext::default(), "provider-3", "0")
+            .expect("get")
+            .is_none()
+    );
+    assert!(
+        store
+            .get(&TenantContext::default(), "provider-7", "24")
+            .expect("get")
+            .is_some()
+    );
+    assert!(!dir.path().join("provision/installs.json.tmp").exists());
+}
+
+#[test]
+fn stores_see_each_others_writes() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("installs.json");
+    let mut first = FileInstallStore::new(&path).expect("first store");
+    let mut second = FileInstallStore::new(&path).expect("second store");
+
+    first.put(record("provider", "a")).expect("put a");
+    second.put(record("provider", "b")).expect("put b");
+
+    let tenant = TenantContext::default();
+    assert!(
+        second
+            .get(&tenant, "provider", "a")
+            .expect("get a")
+            .is_some()
+    );
+    assert_eq!(first.list(&tenant).expect("list").len(), 2);
+    assert!(first.delete(&tenant, "provider", "b").expect("delete b"));
+    assert!(
+        second
+            .get(&tenant, "provider", "b")
+            .expect("get b")
+            .is_none()
+    );
+}
+
+#[test]
+fn leftover_temp_file_is_replaced() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("installs.json");
+    // A crash between writing the temp file and the rename leaves it behind.
+    std::fs::write(dir.path().join("installs.json.tmp"), "{ truncated").expect("temp file");
+
+    let mut store = FileInstallStore::new(&path).expect("store");
+    store.put(record("provider", "a")).expect("put");
+
+    let contents = std::fs::read_to_string(&path).expect("installs file");
+    let records: Vec<ProviderInstallRecord> = serde_json::from_str(&contents).expect("valid json");
+    assert_eq!(records, vec![record("provider", "a")]);
+}
+
+#[test]
+fn unreadable_file_is_an_error_not_stale_records() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("installs.json");
+    let mut store = FileInstallStore::new(&path).expect("store");
+    store.put(record("provider", "a")).expect("put");
+
+    // Another writer (or a bad disk) leaves the file unparseable after this store last saw it.
+    std::fs::write(&path, "{ truncated").expect("corrupt");
+
+    let tenant = TenantContext::default();
+    let err = store.list(&tenant).expect_err("corrupt file");
+    assert_eq!(err.kind(), ApplyErrorKind::Backend, "{err}");
+    assert!(store.get(&tenant, "provider", "a").is_err());
+}
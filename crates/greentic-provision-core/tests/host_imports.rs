@@ -0,0 +1,518 @@
+mod common;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use greentic_provision_core::{
+    Capability, ConfigStore, ExecutionLimits, HostBindings, HostCall, HostClock, HostLogEntry,
+    HostRecorder, HostRecording, HostReplay, HttpError, HttpFixtures, HttpRequest, HttpResponse,
+    HttpTransport, InMemoryConfigStore, InMemorySecretsStore, LogLevel, ProvisionEngine,
+    ProvisionMode, RecordedStep, ReplayTransport, SecretsStore, WasmtimeExecutor,
+};
+use serde_json::{Value, json};
+use tempfile::TempDir;
+
+const NAMESPACE: &str = "provision:unknown:unknown:unknown:host-pack:install";
+
+// Reads `plan` from config and returns it as the step plan. Traps unless the `api_token` secret
+// exists and the clock reports the pinned timestamp.
+const HOST_GUEST: &str = r#"
+(module
+  (import "greentic:host" "config_get" (func $config_get (param i32 i32 i32 i32) (result i32)))
+  (import "greentic:host" "secret_exists" (func $secret_exists (param i32 i32) (result i32)))
+  (import "greentic:host" "log" (func $log (param i32 i32 i32)))
+  (import "greentic:host" "clock_now_ms" (func $clock (result i64)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "plan")
+  (data (i32.const 16) "api_token")
+  (data (i32.const 32) "{\"event\":\"validated\"}")
+  (data (i32.const 1016) "{\"plan\":")
+  (func (export "run") (param i32 i32) (result i32 i32)
+    (local $len i32)
+    (if (i32.eqz (call $secret_exists (i32.const 16) (i32.const 9))) (then unreachable))
+    (if (i64.ne (call $clock) (i64.const 1700000000000)) (then unreachable))
+    (call $log (i32.const 2) (i32.const 32) (i32.const 21))
+    (local.set $len (call $config_get (i32.const 0) (i32.const 4) (i32.const 1024) (i32.const 2048)))
+    (if (i32.lt_s (local.get $len) (i32.const 0)) (then unreachable))
+    (i32.store8 (i32.add (i32.const 1024) (local.get $len)) (i32.const 125))
+    (i32.const 1016)
+    (i32.add (local.get $len) (i32.const 9))
+  )
+)
+"#;
+
+// Sends a GET through `http_request` and returns `{"http": <response>}` as step output.
+const HTTP_GUEST: &str = r#"
+(module
+  (import "greentic:host" "http_request" (func $http_request (param i32 i32 i32 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "{\"method\":\"GET\",\"url\":\"https://api.example.com/me\"}")
+  (data (i32.const 1016) "{\"http\":")
+  (func (export "run") (param i32 i32) (result i32 i32)
+    (local $len i32)
+    (local.set $len (call $http_request (i32.const 0) (i32.const 51) (i32.const 1024) (i32.const 2048)))
+    (i32.store8 (i32.add (i32.const 1024) (local.get $len)) (i32.const 125))
+    (i32.const 1016)
+    (i32.add (local.get $len) (i32.const 9))
+  )
+)
+"#;
+
+// Component counterpart of `HOST_GUEST`: traps unless the `api_token` secret exists and the clock
+// reports the pinned timestamp, then returns `{"ok":true}`.
+const HOST_COMPONENT: &str = r#"
+(component
+  (import "greentic:host/step" (instance $host
+    (export "secret-exists" (func (param "key" string) (result bool)))
+    (export "clock-now-ms" (func (result u64)))
+  ))
+  (alias export $host "secret-exists" (func $secret_exists))
+  (alias export $host "clock-now-ms" (func $clock))
+
+  (core module $Libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 8192))
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $ptr i32)
+      (local.set $ptr (global.get $heap))
+      (global.set $heap (i32.add (local.get $ptr) (i32.add (local.get 3) (i32.const 8))))
+      (local.get $ptr)
+    )
+  )
+  (core instance $libc (instantiate $Libc))
+  (alias core export $libc "memory" (core memory $mem))
+  (core func $secret_exists_lower (canon lower (func $secret_exists) (memory $mem)))
+  (core func $clock_lower (canon lower (func $clock)))
+
+  (core module $Main
+    (import "libc" "memory" (memory 1))
+    (import "host" "secret-exists" (func $secret_exists (param i32 i32) (result i32)))
+    (import "host" "clock-now-ms" (func $clock (result i64)))
+    (data (i32.const 16) "api_token")
+    (data (i32.const 100) "{\22ok\22:true}")
+    (func (export "run") (param i32 i32) (result i32)
+      (if (i32.eqz (call $secret_exists (i32.const 16) (i32.const 9))) (then unreachable))
+      (if (i64.ne (call $clock) (i64.const 1700000000000)) (then unreachable))
+      (i32.store (i32.const 1040) (i32.const 100))
+      (i32.store (i32.const 1044) (i32.const 11))
+      (i32.const 1040)
+    )
+  )
+  (core instance $main (instantiate $Main
+    (with "libc" (instance $libc))
+    (with "host" (instance
+      (export "secret-exists" (func $secret_exists_lower))
+      (export "clock-now-ms" (func $clock_lower))
+    ))
+  ))
+  (func (export "run") (param "input" string) (result string)
+    (canon lift (core func $main "run") (memory $mem) (realloc (func $libc "realloc")))
+  )
+)
+"#;
+
+fn host_pack() -> TempDir {
+    let dir = tempfile::tempdir().expect("tempdir");
+    std::fs::write(dir.path().join("setup_default.wat"), HOST_GUEST).expect("write guest");
+    dir
+}
+
+fn bindings(with_secret: bool) -> HostBindings {
+    let mut config = InMemoryConfigStore::default();
+    let mut patch = BTreeMap::new();
+    patch.insert("plan".to_string(), json!({ "notes": ["configured"] }));
+    config.apply_patch(NAMESPACE, &patch).expect("config patch");
+
+    let mut secrets = InMemorySecretsStore::default();
+    if with_secret {
+        secrets
+            .set_secret(&format!("{NAMESPACE}:secrets"), "api_token", "s3cr3t")
+            .expect("secret");
+    }
+
+    HostBindings::new()
+        .with_config_store(Arc::new(Mutex::new(config)))
+        .with_secrets_store(Arc::new(Mutex::new(secrets)))
+        .with_clock(HostClock::Fixed(1_700_000_000_000))
+}
+
+#[test]
+fn host_imports_serve_config_secrets_clock_and_logs() {
+    let pack = host_pack();
+    for mode in [ProvisionMode::DryRun, ProvisionMode::Install] {
+        let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+            .expect("failed to create executor")
+            .with_capabilities([Capability::Config, Capability::Secrets])
+            .with_host(bindings(true));
+        let result = ProvisionEngine::new(executor).run(mode, common::inputs("host-pack"));
+
+        assert_eq!(result.plan.notes, vec!["configured".to_string(); 4]);
+        let step_results = result.step_results.expect("missing step results");
+        assert_eq!(
+            step_results[0].output.logs,
+            vec![HostLogEntry {
+                level: LogLevel::Info,
+                data: json!({ "event": "validated" }),
+            }]
+        );
+    }
+}
+
+#[test]
+fn missing_secret_is_reported_to_guest() {
+    let pack = host_pack();
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_capabilities([Capability::Config, Capability::Secrets])
+        .with_host(bindings(false));
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+
+    assert!(result.plan.notes.is_empty());
+    let step_results = result.step_results.expect("missing step results");
+    assert!(step_results[0].output.data.get("error").is_some());
+}
+
+#[test]
+fn undeclared_capability_fails_instantiation() {
+    let pack = host_pack();
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_capabilities([Capability::Config])
+        .with_host(bindings(true));
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+
+    assert!(result.plan.notes.is_empty());
+    assert_eq!(result.diagnostics.len(), 4);
+    let diagnostic = &result.diagnostics[0];
+    assert_eq!(diagnostic.code, "PROVISION_CAPABILITY_DENIED");
+    assert!(diagnostic.message.contains("greentic:host::secret_exists"));
+    assert!(diagnostic.message.contains("`secrets`"));
+}
+
+#[test]
+fn component_host_imports_follow_declared_capabilities() {
+    let pack = tempfile::tempdir().expect("tempdir");
+    std::fs::write(pack.path().join("setup_default.wat"), HOST_COMPONENT).expect("write guest");
+
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_capabilities([Capability::Secrets])
+        .with_host(bindings(true));
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+    assert!(result.diagnostics.is_empty(), "{:?}", result.diagnostics);
+    let step_results = result.step_results.expect("missing step results");
+    assert_eq!(step_results[0].output.data, json!({ "ok": true }));
+
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_host(bindings(true));
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+    let diagnostic = &result.diagnostics[0];
+    assert_eq!(diagnostic.code, "PROVISION_CAPABILITY_DENIED");
+    assert!(
+        diagnostic
+            .message
+            .contains("greentic:host/step::secret-exists")
+    );
+}
+
+#[test]
+fn unknown_declared_capabilities_are_diagnosed() {
+    let declared = vec!["config".to_string(), "htpp".to_string()];
+    let diagnostics = Capability::parse_declared(&declared).expect_err("unknown capability");
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(diagnostics[0].code, "PROVISION_UNKNOWN_CAPABILITY");
+    assert_eq!(diagnostics[0].message, "unknown capability: htpp");
+    assert_eq!(diagnostics[0].path.as_deref(), Some("meta.capabilities[1]"));
+
+    let declared = vec!["config".to_string(), "http".to_string()];
+    assert_eq!(
+        Capability::parse_declared(&declared).expect("known capabilities"),
+        [Capability::Config, Capability::Http].into()
+    );
+}
+
+#[test]
+fn unknown_host_imports_are_rejected() {
+    let pack = tempfile::tempdir().expect("tempdir");
+    std::fs::write(
+        pack.path().join("setup_default.wat"),
+        r#"(module
+  (import "env" "socket" (func (param i32) (result i32)))
+  (memory (export "memory") 1)
+  (func (export "run") (param i32 i32) (result i32 i32)
+    i32.const 0
+    i32.const 0))"#,
+    )
+    .expect("write guest");
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_capabilities([
+            Capability::Config,
+            Capability::Secrets,
+            Capability::OAuth,
+            Capability::Http,
+            Capability::Subscriptions,
+        ]);
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+
+    assert_eq!(result.diagnostics[0].code, "PROVISION_UNSUPPORTED_IMPORT");
+    assert!(result.diagnostics[0].message.contains("env::socket"));
+}
+
+#[test]
+fn random_fill_rejects_out_of_range_and_oversized_buffers() {
+    for (len, error) in [
+        (i32::MAX, "guest pointer out of bounds"),
+        (65_537, "exceeds the limit of 65536 bytes"),
+    ] {
+        let pack = tempfile::tempdir().expect("tempdir");
+        std::fs::write(
+            pack.path().join("setup_default.wat"),
+            format!(
+                r#"(module
+  (import "greentic:host" "random_fill" (func $random_fill (param i32 i32)))
+  (memory (export "memory") 2)
+  (func (export "run") (param i32 i32) (result i32 i32)
+    (call $random_fill (i32.const 0) (i32.const {len}))
+    i32.const 0
+    i32.const 0))"#
+            ),
+        )
+        .expect("write guest");
+        let recorder = HostRecorder::new();
+        let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+            .expect("failed to create executor")
+            .with_host(HostBindings::new().with_recorder(recorder.clone()));
+        let result =
+            ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+
+        let step_results = result.step_results.expect("missing step results");
+        let message = step_results[0].output.data["error"]
+            .as_str()
+            .expect("error");
+        assert!(message.contains(error), "{message}");
+        assert!(
+            recorder
+                .recording()
+                .steps
+                .iter()
+                .all(|step| step.calls.is_empty())
+        );
+    }
+}
+
+#[test]
+fn component_random_fill_rejects_oversized_buffers() {
+    let pack = tempfile::tempdir().expect("tempdir");
+    std::fs::write(
+        pack.path().join("setup_default.wat"),
+        r#"(component
+  (import "greentic:host/step" (instance $host
+    (export "random-fill" (func (param "len" u32) (result (list u8))))
+  ))
+  (alias export $host "random-fill" (func $random_fill))
+  (core module $Libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 8192))
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $ptr i32)
+      (local.set $ptr (global.get $heap))
+      (global.set $heap (i32.add (local.get $ptr) (i32.add (local.get 3) (i32.const 8))))
+      (local.get $ptr)
+    )
+  )
+  (core instance $libc (instantiate $Libc))
+  (alias core export $libc "memory" (core memory $mem))
+  (core func $random_fill_lower
+    (canon lower (func $random_fill) (memory $mem) (realloc (func $libc "realloc"))))
+  (core module $Main
+    (import "libc" "memory" (memory 1))
+    (import "host" "random-fill" (func $random_fill (param i32 i32)))
+    (func (export "run") (param i32 i32) (result i32)
+      (call $random_fill (i32.const 65537) (i32.const 1040))
+      (i32.const 1040)
+    )
+  )
+  (core instance $main (instantiate $Main
+    (with "libc" (instance $libc))
+    (with "host" (instance (export "random-fill" (func $random_fill_lower))))
+  ))
+  (func (export "run") (param "input" string) (result string)
+    (canon lift (core func $main "run") (memory $mem) (realloc (func $libc "realloc")))
+  )
+)"#,
+    )
+    .expect("write guest");
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor");
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+
+    let step_results = result.step_results.expect("missing step results");
+    let message = step_results[0].output.data["error"]
+        .as_str()
+        .expect("error");
+    assert!(
+        message.contains("exceeds the limit of 65536 bytes"),
+        "{message}"
+    );
+}
+
+fn http_pack() -> TempDir {
+    let dir = tempfile::tempdir().expect("tempdir");
+    std::fs::write(dir.path().join("setup_default.wat"), HTTP_GUEST).expect("write guest");
+    dir
+}
+
+fn run_http_pack(mode: ProvisionMode, transport: Arc<dyn HttpTransport>) -> Value {
+    let pack = http_pack();
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_capabilities([Capability::Http])
+        .with_host(HostBindings::new().with_http_transport(transport));
+    let result = ProvisionEngine::new(executor).run(mode, common::inputs("host-pack"));
+    let step_results = result.step_results.expect("missing step results");
+    step_results[0].output.data["http"].clone()
+}
+
+#[test]
+fn http_requests_are_served_from_fixtures() {
+    let fixtures: HttpFixtures = serde_json::from_value(json!({
+        "exchanges": [{
+            "request": { "method": "GET", "url": "https://api.example.com/me" },
+            "response": { "status": 200, "body": "{\"id\":\"bot\"}" }
+        }]
+    }))
+    .expect("fixtures");
+    let response = run_http_pack(
+        ProvisionMode::DryRun,
+        Arc::new(ReplayTransport::new(fixtures)),
+    );
+    assert_eq!(response["status"], json!(200));
+    assert_eq!(response["body"], json!("{\"id\":\"bot\"}"));
+
+    let response = run_http_pack(
+        ProvisionMode::DryRun,
+        Arc::new(ReplayTransport::new(HttpFixtures::default())),
+    );
+    assert!(
+        response["error"]
+            .as_str()
+            .expect("error")
+            .contains("no fixture for GET https://api.example.com/me")
+    );
+}
+
+struct LiveTransport;
+
+impl HttpTransport for LiveTransport {
+    fn send(&self, _request: &HttpRequest) -> Result<HttpResponse, HttpError> {
+        Ok(HttpResponse {
+            status: 204,
+            headers: BTreeMap::new(),
+            body: String::new(),
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        "live"
+    }
+}
+
+#[test]
+fn dry_run_refuses_network_transports() {
+    let response = run_http_pack(ProvisionMode::DryRun, Arc::new(LiveTransport));
+    assert!(
+        response["error"]
+            .as_str()
+            .expect("error")
+            .contains("not sandboxed")
+    );
+
+    let response = run_http_pack(ProvisionMode::Install, Arc::new(LiveTransport));
+    assert_eq!(response["status"], json!(204));
+}
+
+#[test]
+fn recorded_host_calls_replay_without_stores() {
+    let pack = host_pack();
+    let recorder = HostRecorder::new();
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_capabilities([Capability::Config, Capability::Secrets])
+        .with_host(bindings(true).with_recorder(recorder.clone()));
+    let recorded =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+
+    let recording = recorder.recording();
+    assert_eq!(recording.steps.len(), 4);
+    assert_eq!(recording.steps[0].step, "collect");
+    assert_eq!(
+        recording.steps[0].calls[..2],
+        [
+            HostCall::SecretExists {
+                key: "api_token".to_string(),
+                exists: true,
+            },
+            HostCall::ClockNowMs {
+                value: 1_700_000_000_000,
+            },
+        ]
+    );
+
+    let replay = HostReplay::new(recording.clone());
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_capabilities([Capability::Config, Capability::Secrets])
+        .with_host(HostBindings::new().with_replay(replay.clone()));
+    let replayed =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+    assert_eq!(replayed, recorded);
+    assert!(replay.unconsumed().is_empty());
+
+    // Calls the guest never makes are reported instead of silently ignored.
+    let mut padded = recording.clone();
+    padded.steps[1]
+        .calls
+        .push(HostCall::RandomFill { bytes: vec![0; 4] });
+    padded.steps.push(RecordedStep {
+        step: "cleanup".to_string(),
+        calls: vec![HostCall::ClockNowMs { value: 0 }],
+    });
+    let replay = HostReplay::new(padded);
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_capabilities([Capability::Config, Capability::Secrets])
+        .with_host(HostBindings::new().with_replay(replay.clone()));
+    let replayed =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+    assert_eq!(replayed, recorded);
+    let unconsumed = replay.unconsumed();
+    assert_eq!(unconsumed.len(), 2);
+    assert_eq!(unconsumed[0].step, "validate");
+    assert_eq!(
+        unconsumed[0].calls,
+        [HostCall::RandomFill { bytes: vec![0; 4] }]
+    );
+    assert_eq!(unconsumed[1].step, "cleanup");
+
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_capabilities([Capability::Config, Capability::Secrets])
+        .with_host(HostBindings::new().with_replay(HostReplay::new(HostRecording::default())));
+    let diverged =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("host-pack"));
+    let step_results = diverged.step_results.expect("missing step results");
+    assert!(
+        step_results[0].output.data["error"]
+            .as_str()
+            .expect("error")
+            .contains("replay diverged in step collect")
+    );
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use std::sync::Arc;
+
+use greentic_provision_core::{
+    ExecutionLimits, ProvisionEngine, ProvisionMetrics, ProvisionMode, WasmtimeExecutor,
+};
+
+#[test]
+fn engine_and_executor_record_metrics() {
+    let metrics = ProvisionMetrics::new();
+    let executor = WasmtimeExecutor::new(common::fixture_pack(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_metrics(metrics.clone());
+    let engine = ProvisionEngine::new(executor).with_observer(Arc::new(metrics.clone()));
+    engine.run(ProvisionMode::DryRun, common::inputs("noop-provision"));
+    engine.run(ProvisionMode::DryRun, common::inputs("noop-provision"));
+
+    let text = metrics.encode();
+    assert!(text.contains(
+        "greentic_provision_runs_total{provider=\"noop-provision\",outcome=\"success\"} 2"
+    ));
+    assert!(text.contains(
+        "greentic_provision_step_duration_seconds_count{provider=\"noop-provision\",step=\"apply\"} 2"
+    ));
+    assert!(text.contains(
+        "greentic_provision_step_fuel_count{provider=\"noop-provision\",step=\"apply\"} 2"
+    ));
+    assert!(text.contains("greentic_provision_compile_cache_total{result=\"miss\"} 4"));
+    assert!(text.contains("greentic_provision_compile_cache_total{result=\"hit\"} 4"));
+}
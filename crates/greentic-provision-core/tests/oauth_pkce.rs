@@ -0,0 +1,472 @@
+//! `PkceOAuthHandler` against an in-process mock authorization server, across the pause between
+//! `start` and `finish`.
+
+mod common;
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use greentic_provision_core::oauth::pkce_challenge;
+use greentic_provision_core::types::{OAuthOp, RedactedValue};
+use greentic_provision_core::{
+    ApplyError, ApplyMode, AuthorizationCallback, AuthorizationRequest, HttpError, HttpRequest,
+    HttpResponse, HttpTransport, InMemoryConfigStore, InMemoryInstallStore, InMemorySecretsStore,
+    OAuthHandler, OAuthProviderConfig, PENDING_AUTHORIZATIONS_NAMESPACE, PkceOAuthHandler,
+    ProvisionApplier, ProvisionInputs, ProvisionPlan, ProvisionResult, SecretString, SecretsStore,
+};
+use serde_json::json;
+use url::Url;
+
+use common::SECRETS_NAMESPACE;
+
+const AUTHORIZE_URL: &str = "https://auth.example.test/authorize";
+const TOKEN_URL: &str = "https://auth.example.test/token";
+const CLIENT_ID: &str = "provision-client";
+const REDIRECT_URL: &str = "http://127.0.0.1:8765/callback";
+/// A code the server issued and the authorize parameters it is bound to.
+struct Grant {
+    challenge: String,
+    client_id: String,
+    redirect_uri: String,
+}
+
+/// Issues codes on the authorize endpoint and redeems them, once, on the token endpoint.
+#[derive(Default)]
+struct MockAuthServer {
+    state: Mutex<MockState>,
+}
+
+#[derive(Default)]
+struct MockState {
+    grants: BTreeMap<String, Grant>,
+    issued: usize,
+    token_requests: Vec<BTreeMap<String, String>>,
+}
+
+fn json_reply(status: u16, body: serde_json::Value) -> HttpResponse {
+    HttpResponse {
+        status,
+        headers: BTreeMap::from([("content-type".to_string(), "application/json".to_string())]),
+        body: body.to_string(),
+    }
+}
+
+impl MockAuthServer {
+    /// What the authorize endpoint does once the user consents: validates the request and
+    /// redirects back with a fresh code.
+    fn consent(&self, authorize_url: &str) -> String {
+        let url = Url::parse(authorize_url).unwrap();
+        assert!(authorize_url.starts_with(AUTHORIZE_URL), "{authorize_url}");
+        let params: BTreeMap<String, String> = url.query_pairs().into_owned().collect();
+        assert_eq!(params["response_type"], "code");
+        assert_eq!(params["code_challenge_method"], "S256");
+        let mut state = self.state.lock().unwrap();
+        state.issued += 1;
+        let code = format!("code-{}", state.issued);
+        state.grants.insert(
+            code.clone(),
+            Grant {
+                challenge: params["code_challenge"].clone(),
+                client_id: params["client_id"].clone(),
+                redirect_uri: params["redirect_uri"].clone(),
+            },
+        );
+        format!(
+            "{}?code={code}&state={}",
+            params["redirect_uri"], params["state"]
+        )
+    }
+
+    fn token_requests(&self) -> Vec<BTreeMap<String, String>> {
+        self.state.lock().unwrap().token_requests.clone()
+    }
+}
+
+impl HttpTransport for MockAuthServer {
+    fn send(&self, request: &HttpRequest) -> Result<HttpResponse, HttpError> {
+        assert_eq!(
+            (request.method.as_str(), request.url.as_str()),
+            ("POST", TOKEN_URL)
+        );
+        assert_eq!(
+            request.headers["content-type"],
+            "application/x-www-form-urlencoded"
+        );
+        let form: BTreeMap<String, String> =
+            url::form_urlencoded::parse(request.body.as_deref().unwrap_or_default().as_bytes())
+                .into_owned()
+                .collect();
+        let mut state = self.state.lock().unwrap();
+        state.token_requests.push(form.clone());
+        let invalid_grant = || json_reply(400, json!({"error": "invalid_grant"}));
+        let Some(grant) = state.grants.remove(&form["code"]) else {
+            return Ok(invalid_grant());
+        };
+        if form["grant_type"] != "authorization_code"
+            || form["client_id"] != grant.client_id
+            || form["redirect_uri"] != grant.redirect_uri
+            || pkce_challenge(&form["code_verifier"]) != grant.challenge
+        {
+            return Ok(invalid_grant());
+        }
+        Ok(json_reply(
+            200,
+            json!({
+                "access_token": format!("access-for-{}", form["code"]),
+                "refresh_token": "refresh-token",
+                "token_type": "Bearer",
+                "expires_in": 3600
+            }),
+        ))
+    }
+
+    fn name(&self) -> &'static str {
+        "mock-auth-server"
+    }
+}
+
+/// Plays the user: consents at the authorize URL and reads the code from the redirect.
+fn consent(server: &MockAuthServer, request: &AuthorizationRequest) -> AuthorizationCallback {
+    AuthorizationCallback::from_redirect(&server.consent(&request.url)).expect("callback")
+}
+
+fn provider() -> OAuthProviderConfig {
+    OAuthProviderConfig::new(AUTHORIZE_URL, TOKEN_URL, CLIENT_ID)
+        .with_default_scopes(["offline_access".to_string()])
+        .with_redirect_url(REDIRECT_URL)
+}
+
+fn handler<S: SecretsStore>(server: &Arc<MockAuthServer>, pending: S) -> PkceOAuthHandler<S> {
+    PkceOAuthHandler::new(server.clone(), pending).with_provider("mock", provider())
+}
+
+fn scopes(scopes: &[&str]) -> Vec<String> {
+    scopes.iter().map(|scope| scope.to_string()).collect()
+}
+
+fn inputs(answers: serde_json::Value) -> ProvisionInputs {
+    ProvisionInputs {
+        answers,
+        ..common::inputs("provider")
+    }
+}
+
+fn result(oauth_op: OAuthOp) -> ProvisionResult {
+    let mut plan = ProvisionPlan::default();
+    plan.oauth_ops.push(oauth_op);
+    common::result(plan)
+}
+
+#[test]
+fn setup_pauses_for_consent_and_finish_stores_the_tokens() {
+    let server = Arc::new(MockAuthServer::default());
+    let mut pending = InMemorySecretsStore::default();
+    let mut secrets = InMemorySecretsStore::default();
+
+    let mut applier = ProvisionApplier::new(
+        inputs(json!({})),
+        InMemoryConfigStore::default(),
+        &mut secrets,
+        handler(&server, &mut pending),
+        InMemoryInstallStore::default(),
+    );
+    let report = applier
+        .apply(
+            result(OAuthOp::Start {
+                provider: "mock".to_string(),
+                scopes: scopes(&["chat:write"]),
+                redirect_url: None,
+            }),
+            ApplyMode::Apply,
+        )
+        .expect("start");
+    drop(applier);
+    let [request] = &report.pending_authorizations[..] else {
+        panic!("expected one pending authorization");
+    };
+    assert_eq!(request.provider, "mock");
+    assert!(secrets.list_keys(SECRETS_NAMESPACE).is_empty());
+    assert_eq!(
+        pending.list_keys(PENDING_AUTHORIZATIONS_NAMESPACE),
+        std::slice::from_ref(&request.state)
+    );
+
+    // The user consents, and a later setup run finishes with the code from its answers.
+    let callback = consent(&server, request);
+    let finish = OAuthOp::Finish {
+        state: callback.state.clone(),
+        code: RedactedValue::reference("answers://oauth_code"),
+    };
+    let mut applier = ProvisionApplier::new(
+        inputs(json!({"oauth_code": callback.code.expose_secret()})),
+        InMemoryConfigStore::default(),
+        &mut secrets,
+        handler(&server, &mut pending),
+        InMemoryInstallStore::default(),
+    );
+    let report = applier
+        .apply(result(finish), ApplyMode::Apply)
+        .expect("finish");
+    drop(applier);
+    assert!(report.pending_authorizations.is_empty());
+    assert!(
+        pending
+            .list_keys(PENDING_AUTHORIZATIONS_NAMESPACE)
+            .is_empty()
+    );
+
+    let secret = |key| {
+        secrets
+            .get_secret(SECRETS_NAMESPACE, key)
+            .expect("read secret")
+            .as_ref()
+            .map(SecretString::expose_secret)
+            .map(str::to_string)
+    };
+    assert_eq!(
+        secret("oauth_access_token").as_deref(),
+        Some("access-for-code-1")
+    );
+    assert_eq!(
+        secret("oauth_refresh_token").as_deref(),
+        Some("refresh-token")
+    );
+}
+
+#[test]
+fn finish_authorization_stores_tokens_for_the_existing_install() {
+    let server = Arc::new(MockAuthServer::default());
+    let mut secrets = InMemorySecretsStore::default();
+    let mut applier = ProvisionApplier::new(
+        inputs(json!({})),
+        InMemoryConfigStore::default(),
+        &mut secrets,
+        handler(&server, InMemorySecretsStore::default()),
+        InMemoryInstallStore::default(),
+    );
+    let code = SecretString::new("code-0");
+    let failure = applier
+        .finish_authorization("state", &code)
+        .expect_err("no install yet");
+    assert_eq!(failure.phase, "install_record");
+    assert!(matches!(failure.error, ApplyError::NotFound(_)));
+
+    let report = applier
+        .apply(
+            result(OAuthOp::Start {
+                provider: "mock".to_string(),
+                scopes: Vec::new(),
+                redirect_url: None,
+            }),
+            ApplyMode::Apply,
+        )
+        .expect("start");
+    let callback = consent(&server, &report.pending_authorizations[0]);
+    let report = applier
+        .finish_authorization(&callback.state, &callback.code)
+        .expect("finish");
+    drop(applier);
+    assert_eq!(
+        report.secret_set_keys,
+        ["oauth_access_token", "oauth_refresh_token"]
+    );
+    assert!(report.config_changes.is_empty());
+    assert_eq!(report.install_record.secrets_namespace, SECRETS_NAMESPACE);
+    assert_eq!(report.operations.len(), 2);
+    assert!(
+        secrets
+            .get_secret(SECRETS_NAMESPACE, "oauth_access_token")
+            .expect("read secret")
+            .is_some()
+    );
+}
+
+#[test]
+fn finish_codes_never_serialize() {
+    let op = OAuthOp::Finish {
+        state: "state".to_string(),
+        code: RedactedValue::plaintext("code-hunter2"),
+    };
+    let json = serde_json::to_value(&op).expect("serialize");
+    assert_eq!(json["op"], "finish");
+    assert!(!json.to_string().contains("hunter2"), "{json}");
+}
+
+#[test]
+fn finish_without_a_code_is_invalid() {
+    let server = Arc::new(MockAuthServer::default());
+    let mut applier = ProvisionApplier::new(
+        inputs(json!({})),
+        InMemoryConfigStore::default(),
+        InMemorySecretsStore::default(),
+        handler(&server, InMemorySecretsStore::default()),
+        InMemoryInstallStore::default(),
+    );
+    for code in [
+        RedactedValue::redacted(),
+        RedactedValue::reference("vault://code"),
+    ] {
+        let failure = applier
+            .apply(
+                result(OAuthOp::Finish {
+                    state: "state".to_string(),
+                    code,
+                }),
+                ApplyMode::Apply,
+            )
+            .expect_err("no code");
+        assert_eq!(failure.phase, "resolve");
+        assert!(
+            matches!(failure.error, ApplyError::Invalid(_)),
+            "{}",
+            failure.error
+        );
+    }
+
+    for redirect in ["not a url", "https://app.example/callback?state=abc"] {
+        assert!(matches!(
+            AuthorizationCallback::from_redirect(redirect),
+            Err(ApplyError::Invalid(_))
+        ));
+    }
+}
+
+#[test]
+fn each_start_gets_fresh_state_verifier_and_scopes() {
+    let server = Arc::new(MockAuthServer::default());
+    let mut handler = handler(&server, InMemorySecretsStore::default());
+    let first = handler
+        .start(SECRETS_NAMESPACE, "mock", &[], None)
+        .expect("default scopes")
+        .expect("pending");
+    let second = handler
+        .start(
+            SECRETS_NAMESPACE,
+            "mock",
+            &scopes(&["chat:write", "users:read"]),
+            None,
+        )
+        .expect("requested scopes")
+        .expect("pending");
+    assert_ne!(first.state, second.state);
+    assert_eq!(first.redirect_url, REDIRECT_URL);
+    let scope = |request: &AuthorizationRequest| {
+        Url::parse(&request.url)
+            .unwrap()
+            .query_pairs()
+            .find(|(name, _)| name == "scope")
+            .map(|(_, value)| value.into_owned())
+    };
+    assert_eq!(scope(&first).as_deref(), Some("offline_access"));
+    assert_eq!(scope(&second).as_deref(), Some("chat:write users:read"));
+
+    for request in [&first, &second] {
+        let callback = consent(&server, request);
+        handler
+            .finish(SECRETS_NAMESPACE, &callback.state, &callback.code)
+            .expect("finish");
+    }
+    let token_requests = server.token_requests();
+    assert_eq!(token_requests.len(), 2);
+    assert_ne!(
+        token_requests[0]["code_verifier"],
+        token_requests[1]["code_verifier"]
+    );
+    assert!(!token_requests[0].contains_key("client_secret"));
+    assert!(format!("{handler:?}").contains("mock-auth-server"));
+}
+
+#[test]
+fn each_state_finishes_once() {
+    let server = Arc::new(MockAuthServer::default());
+    let mut handler = handler(&server, InMemorySecretsStore::default());
+    let code = SecretString::new("code-1");
+    assert!(matches!(
+        handler.finish(SECRETS_NAMESPACE, "unknown", &code),
+        Err(ApplyError::NotFound(_))
+    ));
+    assert!(server.token_requests().is_empty());
+
+    let request = handler
+        .start(SECRETS_NAMESPACE, "mock", &[], None)
+        .unwrap()
+        .unwrap();
+    let callback = consent(&server, &request);
+    handler
+        .finish(SECRETS_NAMESPACE, &request.state, &callback.code)
+        .expect("first finish");
+    assert!(matches!(
+        handler.finish(SECRETS_NAMESPACE, &request.state, &callback.code),
+        Err(ApplyError::NotFound(_))
+    ));
+    assert_eq!(server.token_requests().len(), 1);
+}
+
+#[test]
+fn codes_are_bound_to_the_authorization_that_requested_them() {
+    let server = Arc::new(MockAuthServer::default());
+    let mut handler = handler(&server, InMemorySecretsStore::default());
+    let first = handler
+        .start(SECRETS_NAMESPACE, "mock", &[], None)
+        .unwrap()
+        .unwrap();
+    let second = handler
+        .start(SECRETS_NAMESPACE, "mock", &[], None)
+        .unwrap()
+        .unwrap();
+
+    // The code was issued for the first challenge, so the second verifier cannot redeem it.
+    let callback = consent(&server, &first);
+    let err = handler
+        .finish(SECRETS_NAMESPACE, &second.state, &callback.code)
+        .unwrap_err();
+    assert!(
+        matches!(&err, ApplyError::PermissionDenied(message) if message.contains("invalid_grant")),
+        "{err}"
+    );
+    assert!(!err.to_string().contains(callback.code.expose_secret()));
+}
+
+#[test]
+fn authorizations_finish_only_for_the_install_that_started_them() {
+    let server = Arc::new(MockAuthServer::default());
+    let mut handler = handler(&server, InMemorySecretsStore::default());
+    let request = handler
+        .start(SECRETS_NAMESPACE, "mock", &[], None)
+        .unwrap()
+        .unwrap();
+    let callback = consent(&server, &request);
+
+    let other = "provision:unknown:unknown:unknown:provider:other:secrets";
+    assert!(matches!(
+        handler.finish(other, &callback.state, &callback.code),
+        Err(ApplyError::PermissionDenied(_))
+    ));
+    assert!(server.token_requests().is_empty());
+    handler
+        .finish(SECRETS_NAMESPACE, &callback.state, &callback.code)
+        .expect("the starting install can still finish");
+}
+
+#[test]
+fn unknown_providers_and_missing_redirects_fail() {
+    let server = Arc::new(MockAuthServer::default());
+    let mut handler = handler(&server, InMemorySecretsStore::default()).with_provider(
+        "bare",
+        OAuthProviderConfig::new(AUTHORIZE_URL, TOKEN_URL, CLIENT_ID),
+    );
+    assert!(matches!(
+        handler.start(SECRETS_NAMESPACE, "other", &[], None),
+        Err(ApplyError::NotFound(_))
+    ));
+    assert!(matches!(
+        handler.start(SECRETS_NAMESPACE, "bare", &[], None),
+        Err(ApplyError::Backend(_))
+    ));
+    assert!(
+        handler
+            .into_pending_store()
+            .list_keys(PENDING_AUTHORIZATIONS_NAMESPACE)
+            .is_empty()
+    );
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use greentic_provision_core::{
+    DiagnosticEmitted, ExecutionLimits, JsonLinesObserver, PlanMerged, ProvisionEngine,
+    ProvisionMode, ProvisionObserver, RunFinished, RunStarted, StepFinished, StepStarted,
+    WasmtimeExecutor,
+};
+use serde_json::Value;
+
+#[derive(Default)]
+struct EventLog(Mutex<Vec<String>>);
+
+impl EventLog {
+    fn push(&self, event: String) {
+        self.0.lock().expect("event log").push(event);
+    }
+}
+
+impl ProvisionObserver for EventLog {
+    fn run_started(&self, event: &RunStarted) {
+        self.push(format!("run_started {}", event.install_id));
+    }
+
+    fn step_started(&self, event: &StepStarted) {
+        self.push(format!("step_started {}", event.step.as_str()));
+    }
+
+    fn step_finished(&self, event: &StepFinished) {
+        assert!(event.output_bytes > 0);
+        self.push(format!("step_finished {}", event.step.as_str()));
+    }
+
+    fn plan_merged(&self, event: &PlanMerged) {
+        assert!(event.plan_bytes >= event.patch_bytes);
+        self.push(format!("plan_merged {}", event.step.as_str()));
+    }
+
+    fn diagnostic_emitted(&self, event: &DiagnosticEmitted) {
+        self.push(format!("diagnostic {}", event.diagnostic.code));
+    }
+
+    fn run_finished(&self, event: &RunFinished) {
+        self.push(format!("run_finished {} steps", event.steps));
+    }
+}
+
+#[test]
+fn observers_see_every_run_event_in_order() {
+    let executor = WasmtimeExecutor::new(common::fixture_pack(), ExecutionLimits::default())
+        .expect("failed to create executor");
+    let log = Arc::new(EventLog::default());
+    let engine = ProvisionEngine::new(executor).with_observer(log.clone());
+    engine.run(ProvisionMode::DryRun, common::inputs("noop-provision"));
+
+    let events = log.0.lock().expect("event log").clone();
+    assert_eq!(
+        events.first().map(String::as_str),
+        Some("run_started install")
+    );
+    assert_eq!(
+        events.last().map(String::as_str),
+        Some("run_finished 4 steps")
+    );
+    let apply: Vec<_> = events
+        .iter()
+        .skip_while(|event| *event != "step_started apply")
+        .take(3)
+        .map(String::as_str)
+        .collect();
+    assert_eq!(
+        apply,
+        [
+            "step_started apply",
+            "step_finished apply",
+            "plan_merged apply"
+        ]
+    );
+}
+
+#[test]
+fn json_lines_observer_writes_one_event_per_line() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("events").join("run.jsonl");
+    let executor = WasmtimeExecutor::new(common::fixture_pack(), ExecutionLimits::default())
+        .expect("failed to create executor");
+    let observer = JsonLinesObserver::create(&path).expect("events file");
+    ProvisionEngine::new(executor)
+        .with_observer(Arc::new(observer))
+        .run(ProvisionMode::DryRun, common::inputs("noop-provision"));
+
+    let lines: Vec<Value> = std::fs::read_to_string(&path)
+        .expect("events")
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("event json"))
+        .collect();
+    assert_eq!(lines[0]["event"], "run_started");
+    assert_eq!(lines[0]["mode"], "dry_run");
+    assert_eq!(lines[1]["event"], "step_started");
+    assert_eq!(lines[1]["step"], "collect");
+    let finished = lines.last().expect("run_finished");
+    assert_eq!(finished["event"], "run_finished");
+    assert_eq!(finished["steps"], 4);
+    assert!(finished["plan_bytes"].as_u64().expect("plan bytes") > 0);
+}
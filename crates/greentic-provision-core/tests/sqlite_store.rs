@@ -0,0 +1,239 @@
+#![cfg(feature = "sqlite")]
+
+mod common;
+
+use std::collections::BTreeMap;
+
+use greentic_provision_core::types::RedactedValue;
+use greentic_provision_core::{
+    ApplyError, ApplyMode, AuthorizationRequest, ConfigStore, InstallStore, NoopOAuthHandler,
+    OAuthHandler, OAuthOp, OAuthTokenSet, ProviderInstallRecord, ProvisionInputs, ProvisionPlan,
+    ProvisionResult, SecretString, SecretsStore, SqliteStore, TenantContext,
+};
+use serde_json::json;
+
+fn tenant(name: &str) -> TenantContext {
+    TenantContext {
+        environment: Some("dev".to_string()),
+        tenant: Some(name.to_string()),
+        team: None,
+        user: None,
+    }
+}
+
+fn record(tenant_name: &str, provider_id: &str, install_id: &str) -> ProviderInstallRecord {
+    ProviderInstallRecord {
+        tenant: tenant(tenant_name),
+        provider_id: provider_id.to_string(),
+        install_id: install_id.to_string(),
+        config_namespace: format!("config:{install_id}"),
+        secrets_namespace: format!("secrets:{install_id}"),
+        subscriptions: Vec::new(),
+    }
+}
+
+fn inputs() -> ProvisionInputs {
+    ProvisionInputs {
+        tenant: tenant("acme"),
+        ..common::inputs("provider")
+    }
+}
+
+fn plan(with_oauth: bool) -> ProvisionResult {
+    let mut plan = ProvisionPlan::default();
+    plan.config_patch
+        .insert("webhook_url".to_string(), json!("https://example.invalid"));
+    plan.secrets_patch
+        .set
+        .insert("api_token".to_string(), RedactedValue::plaintext("s3cr3t"));
+    if with_oauth {
+        plan.oauth_ops.push(OAuthOp::Start {
+            provider: "example".to_string(),
+            scopes: Vec::new(),
+            redirect_url: None,
+        });
+    }
+    common::result(plan)
+}
+
+struct FailingOAuth;
+
+impl OAuthHandler for FailingOAuth {
+    fn start(
+        &mut self,
+        _secrets_namespace: &str,
+        _provider: &str,
+        _scopes: &[String],
+        _redirect_url: Option<&str>,
+    ) -> Result<Option<AuthorizationRequest>, ApplyError> {
+        Err(ApplyError::Backend(
+            "authorization server unreachable".to_string(),
+        ))
+    }
+
+    fn finish(
+        &mut self,
+        _secrets_namespace: &str,
+        _state: &str,
+        _code: &SecretString,
+    ) -> Result<OAuthTokenSet, ApplyError> {
+        Err(ApplyError::Backend(
+            "authorization server unreachable".to_string(),
+        ))
+    }
+}
+
+#[test]
+fn stores_persist_across_reopen() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("provision").join("store.db");
+    {
+        let store = SqliteStore::open(&path).expect("open");
+        let mut installs = store.install_store();
+        installs.put(record("acme", "slack", "a")).expect("put");
+        installs.put(record("globex", "slack", "b")).expect("put");
+        installs.put(record("acme", "teams", "c")).expect("put");
+        store
+            .config_store()
+            .apply_patch("ns", &BTreeMap::from([("k".to_string(), json!({"n": 1}))]))
+            .expect("config");
+        store
+            .secrets_store()
+            .set_secret("ns:secrets", "token", "value")
+            .expect("secret");
+    }
+
+    let store = SqliteStore::open(&path).expect("reopen");
+    assert_eq!(store.schema_version().expect("version"), 1);
+    let installs = store.install_store();
+    assert_eq!(installs.list(&tenant("acme")).expect("list").len(), 2);
+    assert_eq!(
+        installs.get(&tenant("globex"), "slack", "b").expect("get"),
+        Some(record("globex", "slack", "b"))
+    );
+    let slack: Vec<_> = installs
+        .list_by_provider("slack")
+        .expect("query")
+        .into_iter()
+        .map(|record| record.install_id)
+        .collect();
+    assert_eq!(slack, ["a", "b"]);
+    assert_eq!(
+        store
+            .config_store()
+            .read_namespace("ns")
+            .expect("read config")
+            .get("k"),
+        Some(&json!({"n": 1}))
+    );
+    let secrets = store.secrets_store();
+    assert_eq!(
+        secrets
+            .get_secret("ns:secrets", "token")
+            .expect("read secret")
+            .as_ref()
+            .map(SecretString::expose_secret),
+        Some("value")
+    );
+    assert_eq!(secrets.list_keys("ns:secrets"), ["token"]);
+
+    let mut installs = store.install_store();
+    assert!(
+        installs
+            .delete(&tenant("acme"), "teams", "c")
+            .expect("delete")
+    );
+    assert!(
+        !installs
+            .delete(&tenant("acme"), "teams", "c")
+            .expect("delete")
+    );
+}
+
+#[test]
+fn newer_schema_is_rejected() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("store.db");
+    let conn = rusqlite::Connection::open(&path).expect("connection");
+    conn.pragma_update(None, "user_version", 99)
+        .expect("version");
+    drop(conn);
+
+    let err = SqliteStore::open(&path).expect_err("schema from the future");
+    assert!(matches!(err, ApplyError::Conflict(_)), "{err}");
+}
+
+#[test]
+fn apply_commits_all_stores_together() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("store.db");
+    let store = SqliteStore::open(&path).expect("open");
+    let report = store
+        .apply(inputs(), plan(false), ApplyMode::Apply, NoopOAuthHandler)
+        .expect("apply");
+
+    let other = SqliteStore::open(&path).expect("second connection");
+    let record = other
+        .install_store()
+        .get(&tenant("acme"), "provider", "install")
+        .expect("read install")
+        .expect("install record");
+    assert_eq!(record, report.install_record);
+    assert_eq!(
+        other
+            .config_store()
+            .read_namespace(&record.config_namespace)
+            .expect("read config")
+            .get("webhook_url"),
+        Some(&json!("https://example.invalid"))
+    );
+    assert_eq!(
+        other
+            .secrets_store()
+            .get_secret(&record.secrets_namespace, "api_token")
+            .expect("read secret")
+            .as_ref()
+            .map(SecretString::expose_secret),
+        Some("s3cr3t")
+    );
+}
+
+#[test]
+fn failed_apply_leaves_no_trace() {
+    let dir = tempfile::tempdir().expect("tempdir");
+    let path = dir.path().join("store.db");
+    let store = SqliteStore::open(&path).expect("open");
+    let failure = store
+        .apply(inputs(), plan(true), ApplyMode::Apply, FailingOAuth)
+        .expect_err("oauth fails after the install record is written");
+    assert_eq!(failure.phase, "oauth");
+    assert_eq!(failure.reverted.len(), 3);
+
+    let other = SqliteStore::open(&path).expect("second connection");
+    assert!(
+        other
+            .install_store()
+            .list(&tenant("acme"))
+            .expect("list")
+            .is_empty()
+    );
+    let namespace = "provision:dev:acme:unknown:provider:install";
+    assert!(
+        other
+            .config_store()
+            .read_namespace(namespace)
+            .expect("read config")
+            .is_empty()
+    );
+    assert!(
+        other
+            .secrets_store()
+            .list_keys(&format!("{namespace}:secrets"))
+            .is_empty()
+    );
+
+    // The connection is usable again once the transaction is rolled back.
+    store
+        .apply(inputs(), plan(false), ApplyMode::Apply, NoopOAuthHandler)
+        .expect("retry");
+}
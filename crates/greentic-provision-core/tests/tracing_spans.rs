@@ -0,0 +1,101 @@
+#![cfg(feature = "tracing")]
+
+mod common;
+
+use std::sync::{Arc, Mutex};
+
+use greentic_provision_core::types::{RedactedValue, SecretsPatch};
+use greentic_provision_core::{
+    ApplyMode, ExecutionLimits, InMemoryConfigStore, InMemoryInstallStore, InMemorySecretsStore,
+    NoopOAuthHandler, ProvisionApplier, ProvisionEngine, ProvisionInputs, ProvisionMode,
+    TenantContext, WasmtimeExecutor,
+};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+/// Span names with their recorded fields, rendered as `name key=value ...`.
+#[derive(Clone, Default)]
+struct SpanLog(Arc<Mutex<Vec<String>>>);
+
+struct FieldWriter<'a>(&'a mut String);
+
+impl Visit for FieldWriter<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0.push_str(&format!(" {}={:?}", field.name(), value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push_str(&format!(" {}={}", field.name(), value));
+    }
+}
+
+impl<S: tracing::Subscriber> Layer<S> for SpanLog {
+    fn on_new_span(&self, attrs: &Attributes<'_>, _id: &Id, _ctx: Context<'_, S>) {
+        let mut line = attrs.metadata().name().to_string();
+        attrs.record(&mut FieldWriter(&mut line));
+        self.0.lock().expect("span log").push(line);
+    }
+}
+
+#[test]
+fn runs_and_applies_emit_spans_with_install_ids() {
+    let inputs = ProvisionInputs {
+        tenant: TenantContext {
+            tenant: Some("tenant-a".to_string()),
+            ..TenantContext::default()
+        },
+        ..common::install_inputs("noop-provision", "install-1")
+    };
+    let log = SpanLog::default();
+    let subscriber = tracing_subscriber::registry().with(log.clone());
+
+    tracing::subscriber::with_default(subscriber, || {
+        let executor = WasmtimeExecutor::new(common::fixture_pack(), ExecutionLimits::default())
+            .expect("failed to create executor");
+        let mut result = ProvisionEngine::new(executor).run(ProvisionMode::Install, inputs.clone());
+        let mut secrets = SecretsPatch::default();
+        secrets
+            .set
+            .insert("token".to_string(), RedactedValue::plaintext("s3cr3t"));
+        result.plan.secrets_patch = secrets;
+
+        let mut applier = ProvisionApplier::new(
+            inputs,
+            InMemoryConfigStore::default(),
+            InMemorySecretsStore::default(),
+            NoopOAuthHandler,
+            InMemoryInstallStore::default(),
+        );
+        applier
+            .apply(result, ApplyMode::Apply)
+            .expect("apply succeeds");
+    });
+
+    let spans = log.0.lock().expect("span log").clone();
+    for span in &spans {
+        assert!(span.contains("tenant=tenant-a"), "{span}");
+        assert!(span.contains("provider_id=noop-provision"), "{span}");
+        assert!(span.contains("install_id=install-1"), "{span}");
+        assert!(!span.contains("s3cr3t"), "{span}");
+    }
+    let count = |prefix: &str| spans.iter().filter(|span| span.starts_with(prefix)).count();
+    assert_eq!(count("provision.run "), 1);
+    assert_eq!(count("provision.step "), 4);
+    assert_eq!(count("wasm.compile "), 4);
+    assert_eq!(count("wasm.instantiate "), 4);
+    for phase in [
+        "resolve",
+        "config",
+        "secrets",
+        "subscriptions",
+        "install_record",
+        "oauth",
+    ] {
+        assert!(
+            spans.iter().any(|span| span.starts_with("provision.apply ")
+                && span.contains(&format!("phase={phase}"))),
+            "missing {phase} phase"
+        );
+    }
+}
@@ -0,0 +1,276 @@
+mod common;
+
+use greentic_provision_core::{
+    ConsoleOutput, ExecutionLimits, HostBindings, HostCall, HostClock, HostRecorder, HostReplay,
+    ProvisionEngine, ProvisionMode, WasiOptions, WasmtimeExecutor,
+};
+use serde_json::json;
+use tempfile::TempDir;
+
+// Prints to stdout and stderr through `fd_write` and traps unless the realtime clock reports the
+// pinned timestamp. Traps after printing when `$fail` is set.
+fn wasi_guest(fail: bool) -> String {
+    format!(
+        r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "clock_time_get" (func $clock_time_get (param i32 i64 i32) (result i32)))
+  (memory (export "memory") 1)
+  (data (i32.const 0) "hello\n")
+  (data (i32.const 16) "warn\n")
+  (data (i32.const 32) "\00\00\00\00\06\00\00\00")
+  (data (i32.const 40) "\10\00\00\00\05\00\00\00")
+  (data (i32.const 100) "{{\"ok\":true}}")
+  (func (export "run") (param i32 i32) (result i32 i32)
+    (drop (call $fd_write (i32.const 1) (i32.const 32) (i32.const 1) (i32.const 48)))
+    (if (i32.ne (call $clock_time_get (i32.const 0) (i64.const 1) (i32.const 56)) (i32.const 0))
+      (then unreachable))
+    (if (i64.ne (i64.load (i32.const 56)) (i64.const 1700000000000000000)) (then unreachable))
+    (drop (call $fd_write (i32.const 2) (i32.const 40) (i32.const 1) (i32.const 48)))
+    {trap}
+    (i32.const 100)
+    (i32.const 11)
+  )
+)
+"#,
+        trap = if fail { "unreachable" } else { "" }
+    )
+}
+
+fn wasi_pack(fail: bool) -> TempDir {
+    let dir = tempfile::tempdir().expect("tempdir");
+    std::fs::write(dir.path().join("setup_default.wat"), wasi_guest(fail)).expect("write guest");
+    dir
+}
+
+// Component importing the WASI preview 2 wall clock and random source. `run` returns
+// `{"seconds":<wall clock seconds>,"random":<first random u64>}`.
+const WASI_COMPONENT: &str = r#"
+(component
+  (import "wasi:clocks/wall-clock@0.2.0" (instance $clock
+    (type $datetime (record (field "seconds" u64) (field "nanoseconds" u32)))
+    (export "datetime" (type $dt (eq $datetime)))
+    (export "now" (func (result $dt)))
+  ))
+  (import "wasi:random/random@0.2.0" (instance $random
+    (export "get-random-u64" (func (result u64)))
+  ))
+  (alias export $clock "now" (func $now))
+  (alias export $random "get-random-u64" (func $get_random))
+
+  (core module $Libc
+    (memory (export "memory") 1)
+    (global $heap (mut i32) (i32.const 8192))
+    (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+      (local $ptr i32)
+      (local.set $ptr (global.get $heap))
+      (global.set $heap (i32.add (local.get $ptr) (i32.add (local.get 3) (i32.const 8))))
+      (local.get $ptr)
+    )
+  )
+  (core instance $libc (instantiate $Libc))
+  (alias core export $libc "memory" (core memory $mem))
+  (core func $now_lower (canon lower (func $now) (memory $mem)))
+  (core func $random_lower (canon lower (func $get_random)))
+
+  (core module $Main
+    (import "libc" "memory" (memory 1))
+    (import "host" "now" (func $now (param i32)))
+    (import "host" "random" (func $random (result i64)))
+    (data (i32.const 2000) "{\22seconds\22:")
+    (data (i32.const 2016) ",\22random\22:")
+    (func $digits (param $v i64) (param $pos i32) (result i32)
+      (local $start i32)
+      (local.set $start (i32.const 3100))
+      (loop $next
+        (local.set $start (i32.sub (local.get $start) (i32.const 1)))
+        (i32.store8 (local.get $start)
+          (i32.add (i32.const 48) (i32.wrap_i64 (i64.rem_u (local.get $v) (i64.const 10)))))
+        (local.set $v (i64.div_u (local.get $v) (i64.const 10)))
+        (br_if $next (i64.ne (local.get $v) (i64.const 0))))
+      (memory.copy (local.get $pos) (local.get $start) (i32.sub (i32.const 3100) (local.get $start)))
+      (i32.add (local.get $pos) (i32.sub (i32.const 3100) (local.get $start)))
+    )
+    (func (export "run") (param i32 i32) (result i32)
+      (local $pos i32)
+      (call $now (i32.const 1024))
+      (memory.copy (i32.const 4096) (i32.const 2000) (i32.const 11))
+      (local.set $pos (call $digits (i64.load (i32.const 1024)) (i32.const 4107)))
+      (memory.copy (local.get $pos) (i32.const 2016) (i32.const 10))
+      (local.set $pos (call $digits (call $random) (i32.add (local.get $pos) (i32.const 10))))
+      (i32.store8 (local.get $pos) (i32.const 125))
+      (i32.store (i32.const 1040) (i32.const 4096))
+      (i32.store (i32.const 1044) (i32.sub (i32.add (local.get $pos) (i32.const 1)) (i32.const 4096)))
+      (i32.const 1040)
+    )
+  )
+  (core instance $main (instantiate $Main
+    (with "libc" (instance $libc))
+    (with "host" (instance
+      (export "now" (func $now_lower))
+      (export "random" (func $random_lower))
+    ))
+  ))
+  (func (export "run") (param "input" string) (result string)
+    (canon lift (core func $main "run") (memory $mem) (realloc (func $libc "realloc")))
+  )
+)
+"#;
+
+fn component_pack() -> TempDir {
+    let dir = tempfile::tempdir().expect("tempdir");
+    std::fs::write(dir.path().join("setup_default.wat"), WASI_COMPONENT).expect("write guest");
+    dir
+}
+
+fn wasi_executor(pack: &TempDir) -> WasmtimeExecutor {
+    WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_wasi(WasiOptions::default())
+        .with_host(HostBindings::new().with_clock(HostClock::Fixed(1_700_000_000_000)))
+}
+
+#[test]
+fn wasi_guest_output_is_captured() {
+    let pack = wasi_pack(false);
+    let result = ProvisionEngine::new(wasi_executor(&pack))
+        .run(ProvisionMode::DryRun, common::inputs("wasi-pack"));
+
+    assert!(result.diagnostics.is_empty());
+    let step_results = result.step_results.expect("missing step results");
+    assert_eq!(step_results.len(), 4);
+    for step in &step_results {
+        assert_eq!(step.output.data, json!({ "ok": true }));
+        assert_eq!(
+            step.output.console,
+            Some(ConsoleOutput {
+                stdout: "hello\n".to_string(),
+                stderr: "warn\n".to_string(),
+            })
+        );
+    }
+}
+
+#[test]
+fn wasi_output_is_kept_when_step_traps() {
+    let pack = wasi_pack(true);
+    let result = ProvisionEngine::new(wasi_executor(&pack))
+        .run(ProvisionMode::DryRun, common::inputs("wasi-pack"));
+
+    let step_results = result.step_results.expect("missing step results");
+    let output = &step_results[0].output;
+    assert!(output.data.get("error").is_some());
+    let console = output.console.as_ref().expect("console output");
+    assert_eq!(console.stdout, "hello\n");
+    assert_eq!(console.stderr, "warn\n");
+}
+
+#[test]
+fn wasi_imports_require_opt_in() {
+    let pack = wasi_pack(false);
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor");
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("wasi-pack"));
+
+    assert_eq!(result.diagnostics[0].code, "PROVISION_UNSUPPORTED_IMPORT");
+    assert!(
+        result.diagnostics[0]
+            .message
+            .contains("wasi_snapshot_preview1::fd_write")
+    );
+    let step_results = result.step_results.expect("missing step results");
+    assert!(step_results[0].output.console.is_none());
+}
+
+#[test]
+fn wasi_clocks_replay_from_the_recording() {
+    let pack = wasi_pack(false);
+    let recorder = HostRecorder::new();
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_wasi(WasiOptions::default())
+        .with_host(
+            HostBindings::new()
+                .with_clock(HostClock::Fixed(1_700_000_000_000))
+                .with_recorder(recorder.clone()),
+        );
+    let recorded =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("wasi-pack"));
+    assert!(recorded.diagnostics.is_empty());
+
+    // The guest traps unless it sees the recorded time, not the replaying host's clock.
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_wasi(WasiOptions::default())
+        .with_host(HostBindings::new().with_replay(HostReplay::new(recorder.recording())));
+    let replayed =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("wasi-pack"));
+    assert_eq!(replayed, recorded);
+}
+
+#[test]
+fn wasi_component_clock_is_pinned_and_recorded_under_system_clock() {
+    let pack = component_pack();
+    let recorder = HostRecorder::new();
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_wasi(WasiOptions::default())
+        .with_host(
+            HostBindings::new()
+                .with_clock(HostClock::System)
+                .with_random_seed(7)
+                .with_recorder(recorder.clone()),
+        );
+    let recorded =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("wasi-pack"));
+    assert!(
+        recorded.diagnostics.is_empty(),
+        "{:?}",
+        recorded.diagnostics
+    );
+
+    let recording = recorder.recording();
+    let step_results = recorded.step_results.clone().expect("missing step results");
+    assert_eq!(recording.steps.len(), 4);
+    for (step, result) in recording.steps.iter().zip(&step_results) {
+        let HostCall::WasiContext {
+            clock_ms,
+            random_seed,
+        } = step.calls[0]
+        else {
+            panic!("expected a wasi_context call, got {:?}", step.calls);
+        };
+        assert_eq!(random_seed, 7);
+        assert_eq!(result.output.data["seconds"], json!(clock_ms / 1000));
+        assert!(result.output.data["random"].is_u64());
+    }
+
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_wasi(WasiOptions::default())
+        .with_host(
+            HostBindings::new()
+                .with_clock(HostClock::System)
+                .with_replay(HostReplay::new(recording)),
+        );
+    let replayed =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("wasi-pack"));
+    assert_eq!(replayed, recorded);
+}
+
+#[test]
+fn wasi_component_imports_require_opt_in() {
+    let pack = component_pack();
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor");
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("wasi-pack"));
+
+    assert_eq!(result.diagnostics[0].code, "PROVISION_UNSUPPORTED_IMPORT");
+    assert!(
+        result.diagnostics[0]
+            .message
+            .contains("wasi:clocks/wall-clock@0.2.0")
+    );
+}
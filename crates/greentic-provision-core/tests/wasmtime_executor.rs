@@ -1,33 +1,39 @@
-use std::path::PathBuf;
+mod common;
 
 use greentic_provision_core::{
-    ExecutionLimits, ProvisionEngine, ProvisionInputs, ProvisionMode, TenantContext,
-    WasmtimeExecutor,
+    ExecutionLimits, ProvisionEngine, ProvisionInputs, ProvisionMode, StepLayout, WasmtimeExecutor,
 };
 use serde_json::Value;
 
-fn fixture_pack() -> PathBuf {
-    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("..")
-        .join("..")
-        .join("tests/fixtures/packs/noop-provision.gtpack")
-}
+// Single `setup_default` component that dispatches on the `step` field. Input keys are
+// serialized in order, so the step name closes the payload: `..."step":"apply"}`.
+const DISPATCH_GUEST: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "{\"plan\":{\"notes\":[\"collect\"]}}")
+  (data (i32.const 64) "{\"plan\":{\"notes\":[\"validate\"]}}")
+  (data (i32.const 128) "{\"plan\":{\"notes\":[\"apply\"]}}")
+  (data (i32.const 192) "{\"plan\":{\"notes\":[\"summary\"]}}")
+  (func (export "run") (param $ptr i32) (param $len i32) (result i32 i32)
+    (local $c i32)
+    (local.set $c (i32.load8_u (i32.sub (i32.add (local.get $ptr) (local.get $len)) (i32.const 4))))
+    (if (i32.eq (local.get $c) (i32.const 99)) (then (return (i32.const 0) (i32.const 30))))
+    (if (i32.eq (local.get $c) (i32.const 116)) (then (return (i32.const 64) (i32.const 31))))
+    (if (i32.eq (local.get $c) (i32.const 108)) (then (return (i32.const 128) (i32.const 28))))
+    (if (i32.eq (local.get $c) (i32.const 114)) (then (return (i32.const 192) (i32.const 30))))
+    unreachable
+  )
+)
+"#;
 
 #[test]
 fn wasmtime_executor_runs_fixture_pack() {
-    let pack = fixture_pack();
+    let pack = common::fixture_pack();
     let executor =
         WasmtimeExecutor::new(pack, ExecutionLimits::default()).expect("failed to create executor");
     let engine = ProvisionEngine::new(executor);
 
-    let inputs = ProvisionInputs {
-        tenant: TenantContext::default(),
-        provider_id: "noop-provision".to_string(),
-        install_id: "install".to_string(),
-        public_base_url: None,
-        answers: Value::Object(serde_json::Map::new()),
-        existing_state: None,
-    };
+    let inputs = common::inputs("noop-provision");
 
     let result = engine.run(ProvisionMode::DryRun, inputs);
     assert_eq!(
@@ -37,9 +43,62 @@ fn wasmtime_executor_runs_fixture_pack() {
     assert!(result.plan.secrets_patch.set.contains_key("token"));
 }
 
+#[test]
+fn resolved_step_layout_is_reported() {
+    let executor = WasmtimeExecutor::new(common::fixture_pack(), ExecutionLimits::default())
+        .expect("failed to create executor");
+    let (component, layout) = executor.resolve_step("apply").expect("resolve apply");
+    assert_eq!(layout, StepLayout::PerStep);
+    assert!(component.ends_with("components/setup_default__apply.wat"));
+
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("dispatch"));
+    for step in result.step_results.expect("missing step results") {
+        assert_eq!(step.output.layout, Some(StepLayout::PerStep));
+    }
+}
+
+#[test]
+fn single_component_dispatches_on_step() {
+    let pack = tempfile::tempdir().expect("tempdir");
+    std::fs::write(pack.path().join("setup_default.wat"), DISPATCH_GUEST).expect("write guest");
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_step_layout(StepLayout::SingleComponent);
+
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("dispatch"));
+    assert!(result.diagnostics.is_empty());
+    assert_eq!(
+        result.plan.notes,
+        vec!["collect", "validate", "apply", "summary"]
+    );
+    for step in result.step_results.expect("missing step results") {
+        assert_eq!(step.output.layout, Some(StepLayout::SingleComponent));
+    }
+}
+
+#[test]
+fn declared_layout_reports_missing_components() {
+    let pack = tempfile::tempdir().expect("tempdir");
+    std::fs::write(pack.path().join("setup_default.wat"), DISPATCH_GUEST).expect("write guest");
+    let executor = WasmtimeExecutor::new(pack.path(), ExecutionLimits::default())
+        .expect("failed to create executor")
+        .with_step_layout(StepLayout::PerStep);
+
+    let result =
+        ProvisionEngine::new(executor).run(ProvisionMode::DryRun, common::inputs("dispatch"));
+    assert!(result.plan.notes.is_empty());
+    assert_eq!(result.diagnostics.len(), 4);
+    let diagnostic = &result.diagnostics[0];
+    assert_eq!(diagnostic.code, "PROVISION_COMPONENT_MISSING");
+    assert!(diagnostic.message.contains("setup_default__collect"));
+    assert!(diagnostic.message.contains("per_step layout"));
+}
+
 #[test]
 fn mutation_inputs_do_not_panic() {
-    let pack = fixture_pack();
+    let pack = common::fixture_pack();
     let executor =
         WasmtimeExecutor::new(pack, ExecutionLimits::default()).expect("failed to create executor");
     let engine = ProvisionEngine::new(executor);
@@ -48,12 +107,8 @@ fn mutation_inputs_do_not_panic() {
     answers.insert("field".to_string(), Value::String("value".to_string()));
 
     let inputs = ProvisionInputs {
-        tenant: TenantContext::default(),
-        provider_id: "noop-provision".to_string(),
-        install_id: "install".to_string(),
-        public_base_url: None,
         answers: Value::Object(answers),
-        existing_state: None,
+        ..common::inputs("noop-provision")
     };
 
     let _ = engine.run(ProvisionMode::DryRun, inputs);
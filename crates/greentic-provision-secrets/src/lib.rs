@@ -0,0 +1,118 @@
+//! A [`SecretsStore`] over the `greentic:secrets-store@1.0.0` host interface.
+//!
+//! `greentic:secrets-store` is read-only: it exposes one `get(key)` that returns the secret bytes,
+//! `none` when the key is missing, or a `secrets-error`. A host that already implements the
+//! interface's generated [`Host`] trait wraps it in [`GreenticSecretsStore`] so the applier and
+//! `secrets://` references can read the same secrets its components see.
+//!
+//! The interface has no namespaces, since the host scopes secrets to the caller. The store is
+//! bound to one namespace when it is created; keys in that namespace are passed to the host as
+//! they are, and any other namespace is refused. Writes, deletes and listing are not part of the
+//! interface, so `set_secret` and deleting a secret that is set fail with
+//! [`ApplyError::PermissionDenied`], and `list_keys` is always empty. Use another store as the applier's write target.
+
+use std::sync::Mutex;
+
+use greentic_interfaces::secrets_store_v1::greentic::secrets_store::secrets_store::{
+    Host, SecretsError,
+};
+use greentic_provision_core::{ApplyError, SecretString, SecretsStore};
+use greentic_types::secrets::SecretKey;
+use zeroize::Zeroizing;
+
+/// Reads secrets in one namespace through a `greentic:secrets-store` host.
+#[derive(Debug)]
+pub struct GreenticSecretsStore<H> {
+    namespace: String,
+    host: Mutex<H>,
+}
+
+impl<H: Host> GreenticSecretsStore<H> {
+    /// Serves `namespace` from `host`. Other namespaces are refused.
+    pub fn new(host: H, namespace: impl Into<String>) -> Self {
+        Self {
+            namespace: namespace.into(),
+            host: Mutex::new(host),
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn into_inner(self) -> H {
+        self.host
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn check_namespace(&self, namespace: &str) -> Result<(), ApplyError> {
+        if namespace == self.namespace {
+            Ok(())
+        } else {
+            Err(ApplyError::PermissionDenied(format!(
+                "secrets store is bound to {}, not {namespace}",
+                self.namespace
+            )))
+        }
+    }
+}
+
+fn read_only(namespace: &str, key: &str) -> ApplyError {
+    ApplyError::PermissionDenied(format!(
+        "cannot write {namespace}/{key}: greentic:secrets-store is read-only"
+    ))
+}
+
+impl<H: Host> SecretsStore for GreenticSecretsStore<H> {
+    fn set_secret(&mut self, namespace: &str, key: &str, _value: &str) -> Result<(), ApplyError> {
+        Err(read_only(namespace, key))
+    }
+
+    /// Deleting a secret that is not set succeeds, as in the other stores, so rolling back a
+    /// refused write has nothing to undo.
+    fn delete_secret(&mut self, namespace: &str, key: &str) -> Result<(), ApplyError> {
+        match self.get_secret(namespace, key)? {
+            None => Ok(()),
+            Some(_) => Err(read_only(namespace, key)),
+        }
+    }
+
+    fn get_secret(&self, namespace: &str, key: &str) -> Result<Option<SecretString>, ApplyError> {
+        self.check_namespace(namespace)?;
+        // Keys the host would answer with `invalid-key` are rejected without a call.
+        let key = SecretKey::parse(key)
+            .map_err(|err| ApplyError::Backend(format!("invalid secret key {key}: {err}")))?;
+        let result = self
+            .host
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(key.as_str().to_string());
+        let bytes = match result {
+            Ok(Some(bytes)) => Zeroizing::new(bytes),
+            // `not-found` also covers secrets that exist but are not provisioned for the caller.
+            Ok(None) | Err(SecretsError::NotFound) => return Ok(None),
+            Err(err) => return Err(secrets_error(namespace, key.as_str(), err)),
+        };
+        let value = std::str::from_utf8(&bytes).map_err(|_| {
+            ApplyError::Backend(format!(
+                "secret {namespace}/{} is not utf-8 text",
+                key.as_str()
+            ))
+        })?;
+        Ok(Some(SecretString::new(value)))
+    }
+
+    fn list_keys(&self, _namespace: &str) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+fn secrets_error(namespace: &str, key: &str, err: SecretsError) -> ApplyError {
+    let message = format!("secret {namespace}/{key}: {err:?}");
+    match err {
+        SecretsError::NotFound => ApplyError::NotFound(message),
+        SecretsError::Denied => ApplyError::PermissionDenied(message),
+        SecretsError::InvalidKey | SecretsError::Internal => ApplyError::Backend(message),
+    }
+}
@@ -0,0 +1,211 @@
+//! The secrets store against an in-process `greentic:secrets-store` host.
+
+use std::collections::BTreeMap;
+
+use greentic_interfaces::secrets_store_v1::greentic::secrets_store::secrets_store::{
+    Host, SecretsError,
+};
+use greentic_provision_core::secret_refs::{
+    DefaultSecretResolver, ResolveContext, SecretRef, SecretResolver,
+};
+use greentic_provision_core::types::RedactedValue;
+use greentic_provision_core::{
+    ApplyError, ApplyMode, ConfigStore, InMemoryConfigStore, InMemoryInstallStore,
+    NoopOAuthHandler, ProvisionApplier, ProvisionInputs, ProvisionPlan, ProvisionResult,
+    SecretString, SecretsStore, TenantContext,
+};
+use greentic_provision_secrets::GreenticSecretsStore;
+use serde_json::{Value, json};
+
+const NAMESPACE: &str = "provision:dev:acme:unknown:slack:main:secrets";
+
+/// Serves secrets from memory and fails the keys in `errors` with the given error.
+#[derive(Debug, Default)]
+struct StandInSecrets {
+    values: BTreeMap<String, Vec<u8>>,
+    errors: BTreeMap<String, SecretsError>,
+    lookups: Vec<String>,
+}
+
+impl StandInSecrets {
+    fn with(values: &[(&str, &[u8])]) -> Self {
+        Self {
+            values: values
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_vec()))
+                .collect(),
+            ..Self::default()
+        }
+    }
+}
+
+impl Host for StandInSecrets {
+    fn get(&mut self, key: String) -> Result<Option<Vec<u8>>, SecretsError> {
+        self.lookups.push(key.clone());
+        if let Some(err) = self.errors.get(&key) {
+            return Err(*err);
+        }
+        Ok(self.values.get(&key).cloned())
+    }
+}
+
+fn inputs() -> ProvisionInputs {
+    ProvisionInputs {
+        tenant: TenantContext {
+            environment: Some("dev".to_string()),
+            tenant: Some("acme".to_string()),
+            team: None,
+            user: None,
+        },
+        provider_id: "slack".to_string(),
+        install_id: "main".to_string(),
+        public_base_url: None,
+        answers: Value::Null,
+        existing_state: None,
+    }
+}
+
+#[test]
+fn reads_secrets_in_the_bound_namespace() {
+    let store = GreenticSecretsStore::new(
+        StandInSecrets::with(&[("bot_token", b"xoxb-1"), ("oauth/refresh", b"r-1")]),
+        NAMESPACE,
+    );
+    let secret = |key| {
+        store
+            .get_secret(NAMESPACE, key)
+            .expect("read secret")
+            .as_ref()
+            .map(SecretString::expose_secret)
+            .map(str::to_string)
+    };
+    assert_eq!(secret("bot_token").as_deref(), Some("xoxb-1"));
+    assert_eq!(secret("oauth/refresh").as_deref(), Some("r-1"));
+    assert_eq!(secret("missing"), None);
+    assert!(store.list_keys(NAMESPACE).is_empty());
+
+    let err = store
+        .get_secret(
+            "provision:dev:globex:unknown:slack:main:secrets",
+            "bot_token",
+        )
+        .expect_err("other namespace");
+    assert!(matches!(err, ApplyError::PermissionDenied(_)), "{err}");
+    assert_eq!(
+        store.into_inner().lookups,
+        ["bot_token", "oauth/refresh", "missing"]
+    );
+}
+
+#[test]
+fn host_errors_map_to_apply_error_kinds() {
+    let mut host = StandInSecrets::with(&[("binary", &[0xff, 0xfe])]);
+    for (key, err) in [
+        ("gone", SecretsError::NotFound),
+        ("denied", SecretsError::Denied),
+        ("broken", SecretsError::Internal),
+    ] {
+        host.errors.insert(key.to_string(), err);
+    }
+    let store = GreenticSecretsStore::new(host, NAMESPACE);
+
+    assert_eq!(
+        store.get_secret(NAMESPACE, "gone").expect("not found"),
+        None
+    );
+    let err = store.get_secret(NAMESPACE, "denied").expect_err("denied");
+    assert!(matches!(err, ApplyError::PermissionDenied(_)), "{err}");
+    let err = store.get_secret(NAMESPACE, "broken").expect_err("internal");
+    assert!(matches!(err, ApplyError::Backend(_)), "{err}");
+    let err = store
+        .get_secret(NAMESPACE, "binary")
+        .expect_err("not utf-8");
+    assert!(matches!(err, ApplyError::Backend(_)), "{err}");
+
+    // Invalid keys never reach the host.
+    let err = store
+        .get_secret(NAMESPACE, "../escape")
+        .expect_err("invalid key");
+    assert!(matches!(err, ApplyError::Backend(_)), "{err}");
+    assert!(
+        !store
+            .into_inner()
+            .lookups
+            .contains(&"../escape".to_string())
+    );
+}
+
+#[test]
+fn writes_are_refused() {
+    let mut store =
+        GreenticSecretsStore::new(StandInSecrets::with(&[("bot_token", b"xoxb-1")]), NAMESPACE);
+    let err = store
+        .set_secret(NAMESPACE, "bot_token", "xoxb-1")
+        .expect_err("read-only");
+    assert!(matches!(err, ApplyError::PermissionDenied(_)), "{err}");
+    let err = store
+        .delete_secret(NAMESPACE, "bot_token")
+        .expect_err("read-only");
+    assert!(matches!(err, ApplyError::PermissionDenied(_)), "{err}");
+    store
+        .delete_secret(NAMESPACE, "missing")
+        .expect("nothing to delete");
+}
+
+#[test]
+fn secret_references_resolve_through_the_host() {
+    let store =
+        GreenticSecretsStore::new(StandInSecrets::with(&[("api_token", b"s3cr3t")]), NAMESPACE);
+    let inputs = inputs();
+    let ctx = ResolveContext {
+        inputs: &inputs,
+        secrets: &store,
+    };
+    let reference = SecretRef::parse(&format!("secrets://{NAMESPACE}/api_token")).unwrap();
+    let value = DefaultSecretResolver::new()
+        .resolve(&reference, &ctx)
+        .expect("resolve");
+    assert_eq!(value.expose_secret(), "s3cr3t");
+}
+
+#[test]
+fn applying_secrets_fails_and_rolls_back_config() {
+    let mut plan = ProvisionPlan::default();
+    plan.config_patch
+        .insert("channel".to_string(), json!("#ops"));
+    plan.secrets_patch
+        .set
+        .insert("bot_token".to_string(), RedactedValue::plaintext("xoxb-1"));
+    let mut applier = ProvisionApplier::new(
+        inputs(),
+        InMemoryConfigStore::default(),
+        GreenticSecretsStore::new(StandInSecrets::default(), NAMESPACE),
+        NoopOAuthHandler,
+        InMemoryInstallStore::default(),
+    );
+    let failure = applier
+        .apply(
+            ProvisionResult {
+                plan,
+                diagnostics: Vec::new(),
+                step_results: None,
+            },
+            ApplyMode::Apply,
+        )
+        .expect_err("the store is read-only");
+    assert_eq!(failure.phase, "secrets");
+    assert!(
+        matches!(failure.error, ApplyError::PermissionDenied(_)),
+        "{}",
+        failure.error
+    );
+    assert!(failure.fully_reverted());
+
+    let (config, _, _, _) = applier.into_parts();
+    assert!(
+        config
+            .read_namespace("provision:dev:acme:unknown:slack:main")
+            .expect("read config")
+            .is_empty()
+    );
+}
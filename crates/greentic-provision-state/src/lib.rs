@@ -0,0 +1,126 @@
+//! A [`ConfigStore`] over the `greentic:state@1.0.0` `state-store` host interface.
+//!
+//! `state-store` keeps opaque blobs under a `state-key`. [`GreenticStateConfigStore`] keeps each
+//! config namespace as one blob: the namespace is the state key and the blob is the namespace's
+//! values as a JSON object. A host that already implements the interface's generated [`Host`]
+//! trait wraps it here so the applier writes config where its components read state.
+//!
+//! `host-error` codes are read as the `error-code` names of `greentic:interfaces-types`:
+//! `not-found` on a read is an empty namespace, `permission-denied` and `unauthenticated` become
+//! [`ApplyError::PermissionDenied`], `conflict` becomes [`ApplyError::Conflict`], and every other
+//! code is [`ApplyError::Backend`].
+//!
+//! The interface has no compare-and-swap, so a patch is a read followed by a write of the whole
+//! namespace. Hosts must not apply two plans to the same install at once.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use greentic_interfaces::state_store_v1::greentic::interfaces_types::types::TenantCtx;
+use greentic_interfaces::state_store_v1::greentic::state::state_store::{Host, HostError};
+use greentic_provision_core::{ApplyError, ConfigStore};
+use serde_json::Value;
+
+/// Keeps config namespaces as JSON blobs in a `greentic:state` host.
+#[derive(Debug)]
+pub struct GreenticStateConfigStore<H> {
+    host: Mutex<H>,
+    tenant: Option<TenantCtx>,
+}
+
+impl<H: Host> GreenticStateConfigStore<H> {
+    pub fn new(host: H) -> Self {
+        Self {
+            host: Mutex::new(host),
+            tenant: None,
+        }
+    }
+
+    /// Passes `tenant` as the `ctx` of every call, for hosts that scope state by tenant.
+    pub fn with_tenant(mut self, tenant: TenantCtx) -> Self {
+        self.tenant = Some(tenant);
+        self
+    }
+
+    pub fn into_inner(self) -> H {
+        self.host
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn host(&self) -> std::sync::MutexGuard<'_, H> {
+        self.host.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    fn load(&self, namespace: &str) -> Result<BTreeMap<String, Value>, ApplyError> {
+        let bytes = match self.host().read(namespace.to_string(), self.tenant.clone()) {
+            Ok(bytes) => bytes,
+            Err(err) if err.code == "not-found" => return Ok(BTreeMap::new()),
+            Err(err) => return Err(host_error("read", namespace, err)),
+        };
+        serde_json::from_slice(&bytes).map_err(|err| {
+            ApplyError::Backend(format!("config {namespace} is not a JSON object: {err}"))
+        })
+    }
+
+    /// Writes `values` as the namespace's blob, or deletes the blob once it is empty.
+    fn store(&self, namespace: &str, values: &BTreeMap<String, Value>) -> Result<(), ApplyError> {
+        let mut host = self.host();
+        if values.is_empty() {
+            return match host.delete(namespace.to_string(), self.tenant.clone()) {
+                Ok(_) => Ok(()),
+                Err(err) if err.code == "not-found" => Ok(()),
+                Err(err) => Err(host_error("delete", namespace, err)),
+            };
+        }
+        let bytes =
+            serde_json::to_vec(values).map_err(|err| ApplyError::Backend(err.to_string()))?;
+        host.write(namespace.to_string(), bytes, self.tenant.clone())
+            .map(|_| ())
+            .map_err(|err| host_error("write", namespace, err))
+    }
+}
+
+fn host_error(op: &str, namespace: &str, err: HostError) -> ApplyError {
+    let message = format!("state {op} {namespace}: {}: {}", err.code, err.message);
+    match err.code.as_str() {
+        "not-found" => ApplyError::NotFound(message),
+        "permission-denied" | "unauthenticated" => ApplyError::PermissionDenied(message),
+        "conflict" => ApplyError::Conflict(message),
+        _ => ApplyError::Backend(message),
+    }
+}
+
+impl<H: Host> ConfigStore for GreenticStateConfigStore<H> {
+    fn apply_patch(
+        &mut self,
+        namespace: &str,
+        patch: &BTreeMap<String, Value>,
+    ) -> Result<Vec<String>, ApplyError> {
+        if patch.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut values = self.load(namespace)?;
+        values.extend(
+            patch
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        );
+        self.store(namespace, &values)?;
+        Ok(patch.keys().cloned().collect())
+    }
+
+    fn remove_keys(&mut self, namespace: &str, keys: &[String]) -> Result<(), ApplyError> {
+        let mut values = self.load(namespace)?;
+        let before = values.len();
+        values.retain(|key, _| !keys.contains(key));
+        if values.len() != before {
+            self.store(namespace, &values)?;
+        }
+        Ok(())
+    }
+
+    fn read_namespace(&self, namespace: &str) -> Result<BTreeMap<String, Value>, ApplyError> {
+        self.load(namespace)
+    }
+}
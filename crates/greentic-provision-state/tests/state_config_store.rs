@@ -0,0 +1,269 @@
+//! The config store against an in-process `greentic:state` host.
+
+use std::collections::BTreeMap;
+
+use greentic_interfaces::state_store_v1::greentic::interfaces_types::types::TenantCtx;
+use greentic_interfaces::state_store_v1::greentic::state::state_store::{Host, HostError, OpAck};
+use greentic_provision_core::types::RedactedValue;
+use greentic_provision_core::{
+    ApplyError, ApplyMode, ConfigStore, InMemoryInstallStore, InMemorySecretsStore,
+    NoopOAuthHandler, ProvisionApplier, ProvisionInputs, ProvisionPlan, ProvisionResult,
+    SecretString, SecretsStore, TenantContext,
+};
+use greentic_provision_state::GreenticStateConfigStore;
+use serde_json::{Value, json};
+
+const NAMESPACE: &str = "provision:dev:acme:unknown:slack:main";
+
+/// Keeps blobs in memory. Missing keys fail with `not-found`, keys in `denied` with
+/// `permission-denied`, and everything with `unavailable` while `unavailable` is set.
+#[derive(Debug, Default)]
+struct StandInState {
+    blobs: BTreeMap<String, Vec<u8>>,
+    denied: Vec<String>,
+    unavailable: bool,
+    contexts: Vec<Option<String>>,
+}
+
+fn host_error(code: &str) -> HostError {
+    HostError {
+        code: code.to_string(),
+        message: String::new(),
+    }
+}
+
+impl StandInState {
+    fn check(&mut self, key: &str, ctx: Option<TenantCtx>) -> Result<(), HostError> {
+        self.contexts.push(ctx.map(|ctx| ctx.tenant));
+        if self.unavailable {
+            return Err(host_error("unavailable"));
+        }
+        if self.denied.iter().any(|denied| denied == key) {
+            return Err(host_error("permission-denied"));
+        }
+        Ok(())
+    }
+}
+
+impl Host for StandInState {
+    fn read(&mut self, key: String, ctx: Option<TenantCtx>) -> Result<Vec<u8>, HostError> {
+        self.check(&key, ctx)?;
+        self.blobs
+            .get(&key)
+            .cloned()
+            .ok_or_else(|| host_error("not-found"))
+    }
+
+    fn write(
+        &mut self,
+        key: String,
+        bytes: Vec<u8>,
+        ctx: Option<TenantCtx>,
+    ) -> Result<OpAck, HostError> {
+        self.check(&key, ctx)?;
+        self.blobs.insert(key, bytes);
+        Ok(OpAck::Ok)
+    }
+
+    fn delete(&mut self, key: String, ctx: Option<TenantCtx>) -> Result<OpAck, HostError> {
+        self.check(&key, ctx)?;
+        self.blobs
+            .remove(&key)
+            .map(|_| OpAck::Ok)
+            .ok_or_else(|| host_error("not-found"))
+    }
+}
+
+fn tenant_ctx(tenant: &str) -> TenantCtx {
+    TenantCtx {
+        env: "dev".to_string(),
+        tenant: tenant.to_string(),
+        tenant_id: tenant.to_string(),
+        team: None,
+        team_id: None,
+        user: None,
+        user_id: None,
+        trace_id: None,
+        correlation_id: None,
+        attributes: Vec::new(),
+        session_id: None,
+        flow_id: None,
+        node_id: None,
+        provider_id: None,
+        deadline_ms: None,
+        attempt: 0,
+        idempotency_key: None,
+        impersonation: None,
+    }
+}
+
+fn inputs() -> ProvisionInputs {
+    ProvisionInputs {
+        tenant: TenantContext {
+            environment: Some("dev".to_string()),
+            tenant: Some("acme".to_string()),
+            team: None,
+            user: None,
+        },
+        provider_id: "slack".to_string(),
+        install_id: "main".to_string(),
+        public_base_url: None,
+        answers: Value::Null,
+        existing_state: None,
+    }
+}
+
+fn plan() -> ProvisionResult {
+    let mut plan = ProvisionPlan::default();
+    plan.config_patch
+        .insert("channel".to_string(), json!("#ops"));
+    plan.secrets_patch
+        .set
+        .insert("bot_token".to_string(), RedactedValue::plaintext("xoxb-1"));
+    ProvisionResult {
+        plan,
+        diagnostics: Vec::new(),
+        step_results: None,
+    }
+}
+
+#[test]
+fn namespaces_are_json_blobs_under_their_state_key() {
+    let mut config =
+        GreenticStateConfigStore::new(StandInState::default()).with_tenant(tenant_ctx("acme"));
+
+    assert!(config.read_namespace(NAMESPACE).expect("read").is_empty());
+    let changed = config
+        .apply_patch(
+            NAMESPACE,
+            &BTreeMap::from([
+                ("channel".to_string(), json!("#ops")),
+                ("retries".to_string(), json!(3)),
+            ]),
+        )
+        .expect("patch");
+    assert_eq!(changed, ["channel", "retries"]);
+    config
+        .remove_keys(NAMESPACE, &["retries".to_string(), "missing".to_string()])
+        .expect("remove");
+    assert_eq!(
+        config.read_namespace(NAMESPACE).expect("read"),
+        BTreeMap::from([("channel".to_string(), json!("#ops"))])
+    );
+
+    config
+        .remove_keys(NAMESPACE, &["channel".to_string()])
+        .expect("remove last key");
+    let state = config.into_inner();
+    assert!(
+        state.blobs.is_empty(),
+        "an empty namespace deletes its blob"
+    );
+    assert!(
+        state
+            .contexts
+            .iter()
+            .all(|tenant| tenant.as_deref() == Some("acme"))
+    );
+}
+
+#[test]
+fn host_errors_map_to_apply_error_kinds() {
+    let config = GreenticStateConfigStore::new(StandInState {
+        unavailable: true,
+        ..StandInState::default()
+    });
+    let err = config.read_namespace(NAMESPACE).expect_err("unavailable");
+    assert!(matches!(err, ApplyError::Backend(_)), "{err}");
+    assert!(err.to_string().contains("unavailable"), "{err}");
+
+    let mut config = GreenticStateConfigStore::new(StandInState {
+        denied: vec![NAMESPACE.to_string()],
+        ..StandInState::default()
+    });
+    let err = config
+        .apply_patch(NAMESPACE, &BTreeMap::from([("k".to_string(), json!(1))]))
+        .expect_err("denied");
+    assert!(matches!(err, ApplyError::PermissionDenied(_)), "{err}");
+
+    let mut state = StandInState::default();
+    state
+        .blobs
+        .insert(NAMESPACE.to_string(), b"[1, 2]".to_vec());
+    let err = GreenticStateConfigStore::new(state)
+        .read_namespace(NAMESPACE)
+        .expect_err("not an object");
+    assert!(matches!(err, ApplyError::Backend(_)), "{err}");
+}
+
+#[test]
+fn applier_writes_config_through_the_state_store() {
+    let mut applier = ProvisionApplier::new(
+        inputs(),
+        GreenticStateConfigStore::new(StandInState::default()),
+        InMemorySecretsStore::default(),
+        NoopOAuthHandler,
+        InMemoryInstallStore::default(),
+    );
+    let report = applier.apply(plan(), ApplyMode::Apply).expect("apply");
+
+    let (config, secrets, _oauth, _installs) = applier.into_parts();
+    assert_eq!(
+        config
+            .read_namespace(&report.install_record.config_namespace)
+            .expect("read config")
+            .get("channel"),
+        Some(&json!("#ops"))
+    );
+    assert_eq!(
+        secrets.list_keys(&report.install_record.secrets_namespace),
+        ["bot_token"]
+    );
+}
+
+#[test]
+fn failed_apply_restores_the_previous_blob() {
+    let mut state = StandInState::default();
+    state
+        .blobs
+        .insert(NAMESPACE.to_string(), br##"{"channel":"#old"}"##.to_vec());
+    let mut applier = ProvisionApplier::new(
+        inputs(),
+        GreenticStateConfigStore::new(state),
+        RejectingSecretsStore,
+        NoopOAuthHandler,
+        InMemoryInstallStore::default(),
+    );
+    let failure = applier
+        .apply(plan(), ApplyMode::Apply)
+        .expect_err("secrets write fails");
+    assert_eq!(failure.phase, "secrets");
+    assert!(failure.fully_reverted());
+
+    let (config, _, _, _) = applier.into_parts();
+    assert_eq!(
+        config.read_namespace(NAMESPACE).expect("read config"),
+        BTreeMap::from([("channel".to_string(), json!("#old"))])
+    );
+}
+
+/// Refuses every write, so the applier has to roll back the config it already wrote.
+struct RejectingSecretsStore;
+
+impl SecretsStore for RejectingSecretsStore {
+    fn set_secret(&mut self, _: &str, _: &str, _: &str) -> Result<(), ApplyError> {
+        Err(ApplyError::PermissionDenied("denied".to_string()))
+    }
+
+    fn delete_secret(&mut self, _: &str, _: &str) -> Result<(), ApplyError> {
+        Ok(())
+    }
+
+    fn get_secret(&self, _: &str, _: &str) -> Result<Option<SecretString>, ApplyError> {
+        Ok(None)
+    }
+
+    fn list_keys(&self, _: &str) -> Vec<String> {
+        Vec::new()
+    }
+}